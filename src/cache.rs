@@ -0,0 +1,409 @@
+use data::LayerReport;
+use geojson::feature::Id;
+use lingua::{FeatureRecord, FeatureStore, Plane, PlaneKind, PlaneList, PlaneT, Point};
+use serde_json;
+use manifest::{LayerConfig, Manifest};
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"CBSCENE\0";
+/// Bump whenever the layout below or the way planes are built changes.
+const VERSION: u32 = 7;
+
+/// FNV-1a, stable across builds unlike `DefaultHasher`.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+fn hash_file(h: &mut Fnv, path: &Path) -> io::Result<()> {
+    let mut f = fs::File::open(path)?;
+    let mut buf = vec![0u8; 1 << 16];
+    let mut len: u64 = 0;
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        h.write(&buf[..n]);
+        len += n as u64;
+    }
+    h.write(&len.to_le_bytes());
+    Ok(())
+}
+
+/// Hash of everything a cached scene depends on: the manifest, the region
+/// polygon, the elevation model, and the data files of the visible layers.
+/// Styles are left out, planes being restyled after they are read back.
+pub fn scene_key(manifest: &Manifest, layers: &[&LayerConfig]) -> io::Result<u64> {
+    let mut h = Fnv::new();
+    h.write(&VERSION.to_le_bytes());
+    hash_file(&mut h, &manifest.path)?;
+    if let Some(path) = manifest.region.as_ref().and_then(|r| r.polygon.as_ref()) {
+        hash_file(&mut h, path)?;
+    }
+    if let Some(ref path) = manifest.dem {
+        hash_file(&mut h, path)?;
+    }
+    for layer in layers {
+        for path in layer.data_files() {
+            hash_file(&mut h, &path)?;
+        }
+    }
+    Ok(h.0)
+}
+
+struct Writer<W: Write>(W);
+
+impl<W: Write> Writer<W> {
+    fn u8(&mut self, v: u8) -> io::Result<()> {
+        self.0.write_all(&[v])
+    }
+
+    fn u32(&mut self, v: usize) -> io::Result<()> {
+        self.0.write_all(&(v as u32).to_le_bytes())
+    }
+
+    fn u64(&mut self, v: u64) -> io::Result<()> {
+        self.0.write_all(&v.to_le_bytes())
+    }
+
+    fn str(&mut self, v: &str) -> io::Result<()> {
+        self.u32(v.len())?;
+        self.0.write_all(v.as_bytes())
+    }
+
+    fn points(&mut self, points: &[Point]) -> io::Result<()> {
+        self.u32(points.len())?;
+        for pt in points {
+            self.0.write_all(&pt.x.to_le_bytes())?;
+            self.0.write_all(&pt.y.to_le_bytes())?;
+            self.0.write_all(&pt.z.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, report: &LayerReport) -> io::Result<()> {
+        self.str(&report.name)?;
+        self.u64(report.features_read as u64)?;
+        self.u64(report.features_styled as u64)?;
+        self.u64(report.features_unmatched as u64)?;
+        self.u64(report.features_invalid as u64)?;
+        self.u64(report.features_filtered as u64)?;
+        self.u64(report.features_outside as u64)?;
+        self.u64(report.features_off_dem as u64)?;
+        self.u32(report.skipped_geometries.len())?;
+        for (kind, n) in report.skipped_geometries.iter() {
+            self.str(kind)?;
+            self.u64(*n as u64)?;
+        }
+        Ok(())
+    }
+
+    fn feature(&mut self, record: &FeatureRecord) -> io::Result<()> {
+        match record.id {
+            None => self.u8(0)?,
+            Some(Id::String(ref s)) => {
+                self.u8(1)?;
+                self.str(s)?;
+            }
+            Some(Id::Number(ref n)) => {
+                self.u8(2)?;
+                self.str(&n.to_string())?;
+            }
+        }
+        match record.properties {
+            None => self.u8(0),
+            Some(ref props) => {
+                self.u8(1)?;
+                self.str(&serde_json::to_string(props)?)
+            }
+        }
+    }
+
+    fn plane(&mut self, plane: &PlaneT) -> io::Result<()> {
+        self.u32(plane.layer_index)?;
+        self.u32(plane.style_index)?;
+        self.u32(plane.feature_index)?;
+        self.u8(match plane.kind {
+            PlaneKind::Polygon => 0,
+            PlaneKind::Line => 1,
+            PlaneKind::Marker => 2,
+            PlaneKind::Ground => 3,
+        })?;
+        self.points(&plane.points)?;
+        self.u32(plane.holes.len())?;
+        for hole in plane.holes.iter() {
+            self.points(hole)?;
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+fn corrupted() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupted scene cache")
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.buf.len() {
+            return Err(corrupted());
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<usize> {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b) as usize)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        self.u64().map(f64::from_bits)
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let n = self.u32()?;
+        String::from_utf8(self.take(n)?.to_vec()).map_err(|_| corrupted())
+    }
+
+    fn points(&mut self) -> io::Result<Vec<Point>> {
+        let n = self.u32()?;
+        let mut points = Vec::with_capacity(n);
+        for _ in 0..n {
+            let x = self.f64()?;
+            let y = self.f64()?;
+            let z = self.f64()?;
+            points.push(Point::new(x, y, z));
+        }
+        Ok(points)
+    }
+
+    fn report(&mut self) -> io::Result<LayerReport> {
+        let mut report = LayerReport::new(&self.str()?);
+        report.features_read = self.u64()? as usize;
+        report.features_styled = self.u64()? as usize;
+        report.features_unmatched = self.u64()? as usize;
+        report.features_invalid = self.u64()? as usize;
+        report.features_filtered = self.u64()? as usize;
+        report.features_outside = self.u64()? as usize;
+        report.features_off_dem = self.u64()? as usize;
+        for _ in 0..self.u32()? {
+            let kind = self.str()?;
+            let n = self.u64()? as usize;
+            report.skipped_geometries.insert(kind, n);
+        }
+        Ok(report)
+    }
+
+    fn feature(&mut self) -> io::Result<FeatureRecord> {
+        let id = match self.u8()? {
+            0 => None,
+            1 => Some(Id::String(self.str()?)),
+            2 => Some(Id::Number(
+                serde_json::from_str(&self.str()?).map_err(|_| corrupted())?,
+            )),
+            _ => return Err(corrupted()),
+        };
+        let properties = match self.u8()? {
+            0 => None,
+            1 => Some(serde_json::from_str(&self.str()?).map_err(|_| corrupted())?),
+            _ => return Err(corrupted()),
+        };
+        Ok(FeatureRecord { id, properties })
+    }
+
+    fn plane(&mut self) -> io::Result<PlaneT> {
+        let layer_index = self.u32()?;
+        let style_index = self.u32()?;
+        let feature_index = self.u32()?;
+        let kind = match self.u8()? {
+            0 => PlaneKind::Polygon,
+            1 => PlaneKind::Line,
+            2 => PlaneKind::Marker,
+            3 => PlaneKind::Ground,
+            _ => return Err(corrupted()),
+        };
+        let points = self.points()?;
+        let n_holes = self.u32()?;
+        let mut holes = Vec::with_capacity(n_holes);
+        for _ in 0..n_holes {
+            holes.push(self.points()?);
+        }
+        Ok(PlaneT {
+            layer_index,
+            style_index,
+            feature_index,
+            kind,
+            points,
+            holes,
+        })
+    }
+}
+
+pub fn write(
+    path: &Path,
+    key: u64,
+    planes: &PlaneList,
+    features: &[FeatureStore],
+    reports: &[LayerReport],
+) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    {
+        let mut w = Writer(BufWriter::new(fs::File::create(&tmp)?));
+        w.0.write_all(MAGIC)?;
+        w.u32(VERSION as usize)?;
+        w.u64(key)?;
+        w.u32(reports.len())?;
+        for report in reports {
+            w.report(report)?;
+        }
+        w.u32(features.len())?;
+        for store in features {
+            w.u32(store.len())?;
+            for record in store.iter() {
+                w.feature(record)?;
+            }
+        }
+        w.u64(planes.len() as u64)?;
+        for plane in planes.one() {
+            w.u32(plane.len())?;
+            for t in plane {
+                w.plane(t)?;
+            }
+        }
+        w.0.flush()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+/// The cached planes, features and reports, or `None` when there is no
+/// cache or it was built from other inputs.
+pub fn read(
+    path: &Path,
+    key: u64,
+) -> io::Result<Option<(PlaneList, Vec<FeatureStore>, Vec<LayerReport>)>> {
+    let buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut r = Reader { buf: &buf, pos: 0 };
+
+    if r.take(MAGIC.len())? != MAGIC || r.u32()? != VERSION as usize || r.u64()? != key {
+        return Ok(None);
+    }
+
+    let n_reports = r.u32()?;
+    let mut reports = Vec::with_capacity(n_reports);
+    for _ in 0..n_reports {
+        reports.push(r.report()?);
+    }
+
+    let n_stores = r.u32()?;
+    let mut features = Vec::with_capacity(n_stores);
+    for _ in 0..n_stores {
+        let mut store = FeatureStore::new();
+        for _ in 0..r.u32()? {
+            store.push_record(r.feature()?);
+        }
+        features.push(store);
+    }
+
+    let n_planes = r.u64()? as usize;
+    let mut planes = PlaneList::new(n_planes);
+    for _ in 0..n_planes {
+        let n = r.u32()?;
+        let mut plane: Plane = Vec::with_capacity(n);
+        for _ in 0..n {
+            plane.push(r.plane()?);
+        }
+        planes.push(plane);
+    }
+
+    Ok(Some((planes, features, reports)))
+}
+
+#[cfg(test)]
+mod tests {
+    use cache;
+    use data::LayerReport;
+    use geojson::feature::Id;
+    use lingua::{FeatureRecord, FeatureStore, PlaneList, PlaneT, Point};
+    use serde_json;
+    use std::env;
+
+    #[test]
+    fn round_trip() {
+        let path = env::temp_dir().join("cardboard-cache-round-trip.cache");
+        let mut planes = PlaneList::new(1);
+        planes.push(vec![
+            PlaneT::polygon(
+                0,
+                1,
+                vec![Point::new(0.0, 0.0, 1.0), Point::new(1.0, 0.0, 1.0)],
+                vec![vec![Point::new(0.5, 0.5, 1.0)]],
+            ),
+        ]);
+        let mut store = FeatureStore::new();
+        store.push_record(FeatureRecord {
+            id: Some(Id::String("a".to_owned())),
+            properties: None,
+        });
+        store.push_record(FeatureRecord {
+            id: Some(Id::Number(7.into())),
+            properties: serde_json::from_str(r#"{"h": 3.5}"#).unwrap(),
+        });
+        planes.push(vec![{
+            let mut marker = PlaneT::marker(0, 2, Point::new(3.0, 4.0, 5.0));
+            marker.feature_index = 1;
+            marker
+        }]);
+        let mut report = LayerReport::new("test");
+        report.features_read = 2;
+        report.skipped_geometries.insert("null".to_owned(), 1);
+
+        cache::write(&path, 42, &planes, &[store], &[report]).unwrap();
+        assert!(cache::read(&path, 43).unwrap().is_none());
+        let (read_planes, features, reports) = cache::read(&path, 42).unwrap().unwrap();
+        let flat = read_planes.flattened();
+        assert_eq!(flat.len(), 2);
+        assert_eq!(flat[0].holes[0][0], Point::new(0.5, 0.5, 1.0));
+        assert_eq!(flat[1].points[0], Point::new(3.0, 4.0, 5.0));
+        let record = features[0].get(flat[1].feature_index).unwrap();
+        assert_eq!(record.id, Some(Id::Number(7.into())));
+        assert_eq!(record.properties.as_ref().unwrap()["h"], 3.5);
+        assert_eq!(features[0].get(0).unwrap().id, Some(Id::String("a".to_owned())));
+        assert_eq!(reports[0].features_read, 2);
+        assert_eq!(reports[0].skipped_geometries.get("null"), Some(&1));
+    }
+}
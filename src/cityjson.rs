@@ -0,0 +1,332 @@
+use data::{LayerData, LayerSeed, LoadError};
+use geojson::feature::Id;
+use geojson::{Feature, Geometry, PolygonType, Value};
+use input;
+use manifest::LayerConfig;
+use serde_json;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+
+type JsonObject = serde_json::Map<String, JsonValue>;
+
+/// Property carrying the type of the city object, e.g. `Building`.
+pub const OBJECT_TYPE: &str = "objectType";
+/// Property carrying the semantic surface type, e.g. `RoofSurface`.
+pub const SURFACE_TYPE: &str = "surfaceType";
+
+#[derive(Deserialize)]
+struct Transform {
+    scale: [f64; 3],
+    translate: [f64; 3],
+}
+
+#[derive(Deserialize)]
+struct Semantics {
+    surfaces: Vec<JsonObject>,
+    values: JsonValue,
+}
+
+#[derive(Deserialize)]
+struct CityGeometry {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    lod: JsonValue,
+    #[serde(default)]
+    boundaries: JsonValue,
+    #[serde(default)]
+    semantics: Option<Semantics>,
+}
+
+#[derive(Deserialize)]
+struct CityObject {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    attributes: Option<JsonObject>,
+    #[serde(default)]
+    geometry: Vec<CityGeometry>,
+}
+
+#[derive(Deserialize)]
+struct CityModel {
+    #[serde(rename = "CityObjects")]
+    city_objects: BTreeMap<String, CityObject>,
+    vertices: Vec<[f64; 3]>,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+/// LoD is a number in CityJSON 1.0 and a string in 1.1.
+fn lod_value(lod: &JsonValue) -> Option<f64> {
+    lod.as_f64()
+        .or_else(|| lod.as_str().and_then(|s| s.parse::<f64>().ok()))
+}
+
+/// Number of array levels above surfaces in the boundaries of a geometry.
+fn surface_depth(kind: &str) -> Option<usize> {
+    match kind {
+        "MultiSurface" | "CompositeSurface" => Some(1),
+        "Solid" => Some(2),
+        "MultiSolid" | "CompositeSolid" => Some(3),
+        _ => None,
+    }
+}
+
+struct Surfaces<'a> {
+    vertices: &'a [[f64; 3]],
+    transform: &'a Option<Transform>,
+    semantics: &'a Option<Semantics>,
+    /// Rings grouped by semantic surface type, `None` for plain surfaces.
+    groups: BTreeMap<Option<String>, Vec<PolygonType>>,
+}
+
+impl<'a> Surfaces<'a> {
+    fn position(&self, index: &JsonValue) -> Option<Vec<f64>> {
+        let v = self.vertices.get(index.as_u64()? as usize)?;
+        Some(match self.transform {
+            Some(ref t) => (0..3).map(|i| v[i] * t.scale[i] + t.translate[i]).collect(),
+            None => v.to_vec(),
+        })
+    }
+
+    fn surface_type(&self, value: &JsonValue) -> Option<String> {
+        let index = value.as_u64()? as usize;
+        self.semantics
+            .as_ref()
+            .and_then(|s| s.surfaces.get(index))
+            .and_then(|s| s.get("type"))
+            .and_then(|t| t.as_str())
+            .map(|t| t.to_owned())
+    }
+
+    fn surface(&mut self, rings: &JsonValue, semantic: &JsonValue) -> Result<(), String> {
+        let mut poly: PolygonType = Vec::new();
+        for ring in rings.as_array().ok_or("expected an array of rings")? {
+            let mut positions = Vec::new();
+            for index in ring.as_array().ok_or("expected an array of vertices")? {
+                positions.push(
+                    self.position(index)
+                        .ok_or_else(|| format!("bad vertex index {}", index))?,
+                );
+            }
+            // CityJSON rings are implicitly closed
+            if let Some(first) = positions.first().cloned() {
+                positions.push(first);
+            }
+            poly.push(positions);
+        }
+        let surface_type = self.surface_type(semantic);
+        self.groups
+            .entry(surface_type)
+            .or_insert_with(Vec::new)
+            .push(poly);
+        Ok(())
+    }
+
+    fn walk(&mut self, depth: usize, boundaries: &JsonValue, semantic: &JsonValue) -> Result<(), String> {
+        if depth == 0 {
+            return self.surface(boundaries, semantic);
+        }
+        let items = boundaries.as_array().ok_or("expected an array")?;
+        for (i, item) in items.iter().enumerate() {
+            let sem = semantic
+                .as_array()
+                .and_then(|s| s.get(i))
+                .unwrap_or(&JsonValue::Null);
+            self.walk(depth - 1, item, sem)?;
+        }
+        Ok(())
+    }
+}
+
+fn object_properties(object: &CityObject, surface_type: &Option<String>) -> JsonObject {
+    let mut props = object.attributes.clone().unwrap_or_else(JsonObject::new);
+    props.insert(OBJECT_TYPE.to_owned(), JsonValue::String(object.kind.clone()));
+    if let Some(ref t) = surface_type {
+        props.insert(SURFACE_TYPE.to_owned(), JsonValue::String(t.clone()));
+    }
+    props
+}
+
+/// Loads a CityJSON file, each city object yielding one feature per
+/// semantic surface type found in its selected level of detail. The layer
+/// report counts these features, an invalid object counting as one.
+pub fn load(layer: &LayerConfig, seed: LayerSeed) -> Result<LayerData, LoadError> {
+    let reader = input::open(&layer.data).map_err(|e| LoadError::Io(layer.data.clone(), e))?;
+    eprintln!("Loading CityJSON {} ({})", layer.name, layer.data.display());
+    let model: CityModel = serde_json::from_reader(reader)
+        .map_err(|e| LoadError::Data(layer.data.clone(), e))?;
+    let mut data = seed.empty();
+
+    for (id, object) in model.city_objects.iter() {
+        let lod = layer.options.lod.or_else(|| {
+            object
+                .geometry
+                .iter()
+                .filter_map(|g| lod_value(&g.lod))
+                .fold(None, |acc: Option<f64>, l| Some(acc.map_or(l, |a| a.max(l))))
+        });
+        let mut surfaces = Surfaces {
+            vertices: &model.vertices,
+            transform: &model.transform,
+            semantics: &None,
+            groups: BTreeMap::new(),
+        };
+        let mut invalid = false;
+
+        for geometry in object.geometry.iter() {
+            if lod.is_some() && lod_value(&geometry.lod) != lod {
+                continue;
+            }
+            match surface_depth(&geometry.kind) {
+                Some(depth) => {
+                    surfaces.semantics = &geometry.semantics;
+                    let values = geometry
+                        .semantics
+                        .as_ref()
+                        .map_or(&JsonValue::Null, |s| &s.values);
+                    if surfaces.walk(depth, &geometry.boundaries, values).is_err() {
+                        invalid = true;
+                    }
+                }
+                None => data.report.skip(&geometry.kind),
+            }
+        }
+
+        if invalid {
+            data.report.features_read += 1;
+            data.report.features_invalid += 1;
+            continue;
+        }
+
+        for (surface_type, polys) in surfaces.groups.into_iter() {
+            data.report.features_read += 1;
+            let feature = Feature {
+                bbox: None,
+                geometry: Some(Geometry::new(Value::MultiPolygon(polys))),
+                id: Some(Id::String(id.clone())),
+                properties: Some(object_properties(object, &surface_type)),
+                foreign_members: None,
+            };
+            seed.push_feature(&feature, &mut data);
+        }
+    }
+
+    eprintln!("Loaded {}", layer.name);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use cityjson::{OBJECT_TYPE, SURFACE_TYPE};
+    use data::Data;
+    use std::env;
+    use std::fs;
+
+    /// A building with a plain footprint in LoD 1 and a ground and a roof
+    /// in a LoD 2 solid, a bridge with LoD 1 alone and an object whose
+    /// surface points past the vertices.
+    const MODEL: &str = r#"{
+        "type": "CityJSON",
+        "version": "1.1",
+        "transform": {"scale": [0.5, 0.5, 0.1], "translate": [100, 200, 10]},
+        "CityObjects": {
+            "a": {
+                "type": "Building",
+                "attributes": {"h": 10},
+                "geometry": [
+                    {"type": "MultiSurface", "lod": "1", "boundaries": [[[0, 1, 2, 3]]]},
+                    {
+                        "type": "Solid",
+                        "lod": "2",
+                        "boundaries": [[[[3, 2, 1, 0]], [[4, 5, 6, 7]]]],
+                        "semantics": {
+                            "surfaces": [{"type": "GroundSurface"}, {"type": "RoofSurface"}],
+                            "values": [[0, 1]]
+                        }
+                    }
+                ]
+            },
+            "b": {
+                "type": "Bridge",
+                "geometry": [{"type": "MultiSurface", "lod": 1, "boundaries": [[[0, 1, 2, 3]]]}]
+            },
+            "c": {
+                "type": "Building",
+                "geometry": [{"type": "MultiSurface", "lod": 1, "boundaries": [[[0, 1, 99]]]}]
+            }
+        },
+        "vertices": [
+            [0, 0, 0], [20, 0, 0], [20, 20, 0], [0, 20, 0],
+            [0, 0, 100], [20, 0, 100], [20, 20, 100], [0, 20, 100]
+        ]
+    }"#;
+
+    fn load(options: &str) -> Data {
+        let dir = env::temp_dir().join("cardboard-cityjson");
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("m.json.cache"));
+        fs::write(dir.join("a.city.json"), MODEL).unwrap();
+        fs::write(
+            dir.join("s.json"),
+            r#"{"kind":"simple","strokeColor":"black","fillColor":"red","strokeWidth":1}"#,
+        ).unwrap();
+        fs::write(
+            dir.join("m.json"),
+            format!(
+                r#"{{"layers":[{{"name":"a","data":"a.city.json","style":"s.json","options":{}}}]}}"#,
+                options
+            ),
+        ).unwrap();
+        Data::from_file(dir.join("m.json")).unwrap()
+    }
+
+    fn types(data: &Data) -> Vec<(String, Option<String>)> {
+        data.features[0]
+            .iter()
+            .map(|record| {
+                let props = record.properties.as_ref().unwrap();
+                (
+                    props[OBJECT_TYPE].as_str().unwrap().to_owned(),
+                    props.get(SURFACE_TYPE).and_then(|t| t.as_str()).map(|t| t.to_owned()),
+                )
+            }).collect()
+    }
+
+    #[test]
+    fn city_objects() {
+        // the highest LoD of each object
+        let data = load("{}");
+        let report = &data.reports[0];
+        assert_eq!(
+            types(&data),
+            vec![
+                ("Building".to_owned(), Some("GroundSurface".to_owned())),
+                ("Building".to_owned(), Some("RoofSurface".to_owned())),
+                ("Bridge".to_owned(), None),
+            ]
+        );
+        assert_eq!(report.features_invalid, 1);
+        assert_eq!(
+            report.features_read,
+            report.features_styled + report.features_unmatched + report.features_invalid
+        );
+
+        // vertices are scaled then translated
+        let planes = data.planes.flattened();
+        let roof = planes.iter().find(|p| p.feature_index == 1).unwrap();
+        let (xs, ys): (Vec<f64>, Vec<f64>) = roof.points.iter().map(|p| (p.x, p.y)).unzip();
+        assert!(xs.iter().all(|x| *x == 100.0 || *x == 110.0));
+        assert!(ys.iter().all(|y| *y == 200.0 || *y == 210.0));
+        assert!(roof.points.iter().all(|p| (p.z - 20.0).abs() < 1e-9));
+
+        // a given LoD leaves out objects not having it
+        let data = load(r#"{"lod":1}"#);
+        assert_eq!(
+            types(&data),
+            vec![("Building".to_owned(), None), ("Bridge".to_owned(), None)]
+        );
+        assert_eq!(data.reports[0].features_read, 3);
+    }
+}
@@ -0,0 +1,295 @@
+use data::{Data, LoadError};
+use ramp::{ColorRamp, ColorRampConfig, ColorSpace, Interpolation};
+use std::str::FromStr;
+use style::{Color, PolygonInterval, PolygonStyleConfig};
+
+/// How breaks between classes are chosen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// Classes of the same width.
+    EqualInterval,
+    /// Classes holding the same number of features.
+    Quantile,
+    /// Jenks natural breaks, minimizing the variance within classes.
+    Jenks,
+    /// Classes one standard deviation wide, centred on the mean.
+    StdDev,
+}
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Method, String> {
+        match s.to_lowercase().as_str() {
+            "equal" | "equal-interval" => Ok(Method::EqualInterval),
+            "quantile" => Ok(Method::Quantile),
+            "jenks" => Ok(Method::Jenks),
+            "stddev" | "standard-deviation" => Ok(Method::StdDev),
+            _ => Err(format!(
+                "unknown classification method \"{}\", expected equal, quantile, jenks or stddev",
+                s
+            )),
+        }
+    }
+}
+
+const STROKE_COLOR: &str = "black";
+const STROKE_WIDTH: f64 = 0.5;
+
+/// Above this many values, Jenks breaks are computed on an even sample of
+/// them, the exact method being quadratic.
+const JENKS_SAMPLE: usize = 3000;
+
+fn equal_interval(sorted: &[f64], classes: usize) -> Vec<f64> {
+    let (min, max) = (sorted[0], sorted[sorted.len() - 1]);
+    let step = (max - min) / classes as f64;
+    (1..classes).map(|i| min + step * i as f64).collect()
+}
+
+fn quantile(sorted: &[f64], classes: usize) -> Vec<f64> {
+    (1..classes)
+        .map(|i| sorted[i * sorted.len() / classes])
+        .collect()
+}
+
+fn std_dev(sorted: &[f64], classes: usize) -> Vec<f64> {
+    let n = sorted.len() as f64;
+    let mean = sorted.iter().sum::<f64>() / n;
+    let sd = (sorted.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / n).sqrt();
+    let half = classes as f64 / 2.0;
+    (1..classes)
+        .map(|i| mean + sd * (i as f64 - half))
+        .collect()
+}
+
+/// Fisher's exact optimization, as in Jenks' original code. Breaks are the
+/// first value of each class but the first.
+fn jenks(sorted: &[f64], classes: usize) -> Vec<f64> {
+    let data: Vec<f64> = if sorted.len() > JENKS_SAMPLE {
+        (0..JENKS_SAMPLE)
+            .map(|i| sorted[i * (sorted.len() - 1) / (JENKS_SAMPLE - 1)])
+            .collect()
+    } else {
+        sorted.to_vec()
+    };
+    let n = data.len();
+    // lower[l][j]: 1-based index of the first value of the last class when
+    // splitting the first l values into j classes
+    let mut lower = vec![vec![0usize; classes + 1]; n + 1];
+    let mut variance = vec![vec![::std::f64::INFINITY; classes + 1]; n + 1];
+    for j in 1..=classes {
+        lower[1][j] = 1;
+        variance[1][j] = 0.0;
+    }
+    for l in 2..=n {
+        let (mut sum, mut squares, mut w) = (0.0, 0.0, 0.0);
+        let mut v = 0.0;
+        for m in 1..=l {
+            let first = l - m + 1;
+            let value = data[first - 1];
+            sum += value;
+            squares += value * value;
+            w += 1.0;
+            v = squares - sum * sum / w;
+            let before = first - 1;
+            if before != 0 {
+                for j in 2..=classes {
+                    let candidate = v + variance[before][j - 1];
+                    if variance[l][j] >= candidate {
+                        lower[l][j] = first;
+                        variance[l][j] = candidate;
+                    }
+                }
+            }
+        }
+        lower[l][1] = 1;
+        variance[l][1] = v;
+    }
+    let mut breaks = vec![0.0; classes - 1];
+    let mut end = n;
+    for j in (2..=classes).rev() {
+        let first = lower[end][j].max(1);
+        breaks[j - 2] = data[first - 1];
+        end = (first - 1).max(1);
+    }
+    breaks
+}
+
+/// Class bounds over sorted values, from the smallest to just above the
+/// largest so that it falls in the last class. Breaks landing on the same
+/// value are merged, leaving fewer classes than asked for.
+pub fn bounds(sorted: &[f64], method: Method, classes: usize) -> Vec<f64> {
+    let (min, max) = (sorted[0], sorted[sorted.len() - 1]);
+    let breaks = match method {
+        Method::EqualInterval => equal_interval(sorted, classes),
+        Method::Quantile => quantile(sorted, classes),
+        Method::Jenks => jenks(sorted, classes),
+        Method::StdDev => std_dev(sorted, classes),
+    };
+    let mut bounds = vec![min];
+    for b in breaks.into_iter().chain(Some(max.next_up())) {
+        if b > bounds[bounds.len() - 1] && b <= max.next_up() {
+            bounds.push(b);
+        }
+    }
+    if bounds.len() == 1 {
+        bounds.push(max.next_up());
+    }
+    bounds
+}
+
+fn hex(c: Color) -> String {
+    let byte = |v: f64| (v.max(0.0).min(1.0) * 255.0).round() as u8;
+    let rgb = format!("#{:02x}{:02x}{:02x}", byte(c.red), byte(c.green), byte(c.blue));
+    if c.alpha < 1.0 {
+        format!("{}{:02x}", rgb, byte(c.alpha))
+    } else {
+        rgb
+    }
+}
+
+/// A continuous style splitting the numeric values of `prop_name` in a
+/// layer into classes, coloured along a palette.
+pub fn classify(
+    data: &Data,
+    layer_name: &str,
+    prop_name: &str,
+    method: Method,
+    classes: usize,
+    palette: &str,
+) -> Result<PolygonStyleConfig, String> {
+    if classes == 0 {
+        return Err("at least one class is needed".to_owned());
+    }
+    // features are stored in the order of visible layers
+    let layer_index = data
+        .manifest
+        .visible_layers()
+        .iter()
+        .position(|l| l.name == layer_name)
+        .ok_or_else(|| format!("no visible layer named \"{}\"", layer_name))?;
+    let unstyled;
+    let features = match data.reports[layer_index].error {
+        // the style to be written may well not exist yet
+        Some(LoadError::Style(_, _)) => {
+            unstyled = data
+                .unstyled_features(layer_index)
+                .map_err(|e| format!("layer {}: {}", layer_name, e))?;
+            &unstyled
+        }
+        Some(ref e) => return Err(format!("layer {} did not load: {}", layer_name, e)),
+        None => &data.features[layer_index],
+    };
+    let mut values: Vec<f64> = features
+        .iter()
+        .filter_map(|f| f.properties.as_ref())
+        .filter_map(|props| props.get(prop_name).and_then(|v| v.as_f64()))
+        .filter(|v| v.is_finite())
+        .collect();
+    if values.is_empty() {
+        return Err(format!(
+            "no numeric value for \"{}\" in layer {}",
+            prop_name, layer_name
+        ));
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let bounds = bounds(&values, method, classes);
+    let ramp = ColorRamp::from_config(
+        &ColorRampConfig::Palette {
+            palette: palette.to_owned(),
+            domain: [0.0, 1.0],
+            reverse: false,
+        },
+        Interpolation::Linear,
+        ColorSpace::Lab,
+    )?;
+    let n = bounds.len() - 1;
+    let intervals = bounds
+        .windows(2)
+        .enumerate()
+        .map(|(i, w)| {
+            let t = if n > 1 { i as f64 / (n - 1) as f64 } else { 0.5 };
+            PolygonInterval::new(w[0], w[1], hex(ramp.at(t)), STROKE_COLOR, STROKE_WIDTH)
+        }).collect();
+    Ok(PolygonStyleConfig::continuous(prop_name, intervals))
+}
+
+#[cfg(test)]
+mod tests {
+    use classify::{bounds, classify, Method};
+    use data::Data;
+    use std::env;
+    use std::fs;
+    use style::PolygonStyleConfig;
+
+    fn points(values: &[f64]) -> String {
+        let features: Vec<String> = values
+            .iter()
+            .map(|v| {
+                format!(
+                    r#"{{"type":"Feature","properties":{{"v":{}}},"geometry":{{"type":"Point","coordinates":[0,0]}}}}"#,
+                    v
+                )
+            }).collect();
+        format!(r#"{{"type":"FeatureCollection","features":[{}]}}"#, features.join(","))
+    }
+
+    #[test]
+    fn layer_lookup() {
+        let dir = env::temp_dir().join("cardboard-classify");
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("m.json.cache"));
+        let _ = fs::remove_file(dir.join("new.json"));
+        fs::write(dir.join("hidden.geojson"), points(&[1000.0, 2000.0])).unwrap();
+        fs::write(dir.join("low.geojson"), points(&[1.0, 2.0, 3.0, 4.0])).unwrap();
+        fs::write(dir.join("high.geojson"), points(&[50.0, 60.0])).unwrap();
+        fs::write(
+            dir.join("s.json"),
+            r#"{"kind":"simple","strokeColor":"black","fillColor":"red","strokeWidth":1}"#,
+        ).unwrap();
+        fs::write(
+            dir.join("m.json"),
+            r#"{"layers":[
+                {"name":"hidden","data":"hidden.geojson","style":"s.json","visible":false},
+                {"name":"low","data":"low.geojson","style":"s.json"},
+                {"name":"high","data":"high.geojson","style":"s.json","priority":5},
+                {"name":"new","data":"low.geojson","style":"new.json"}]}"#,
+        ).unwrap();
+        let data = Data::from_file(dir.join("m.json")).unwrap();
+
+        let range = |style: PolygonStyleConfig| {
+            let json = serde_json::to_value(&style).unwrap();
+            let intervals = json["intervals"].as_array().unwrap().clone();
+            (
+                intervals[0]["low"].as_f64().unwrap(),
+                intervals[intervals.len() - 1]["high"].as_f64().unwrap(),
+            )
+        };
+        let low = classify(&data, "low", "v", Method::EqualInterval, 3, "blues").unwrap();
+        assert_eq!(range(low).0, 1.0);
+        let high = classify(&data, "high", "v", Method::Quantile, 2, "blues").unwrap();
+        assert_eq!(range(high).0, 50.0);
+        assert!(classify(&data, "hidden", "v", Method::Quantile, 2, "blues").is_err());
+        // a layer whose style is yet to be written
+        let new = classify(&data, "new", "v", Method::Jenks, 2, "blues").unwrap();
+        assert_eq!(range(new).0, 1.0);
+    }
+
+    #[test]
+    fn breaks() {
+        let values = [1.0, 2.0, 3.0, 10.0, 11.0, 12.0, 30.0, 31.0, 32.0];
+        let top = 32.0f64.next_up();
+
+        assert_eq!(bounds(&values, Method::EqualInterval, 2), vec![1.0, 16.5, top]);
+        assert_eq!(bounds(&values, Method::Quantile, 3), vec![1.0, 10.0, 30.0, top]);
+        assert_eq!(bounds(&values, Method::Jenks, 3), vec![1.0, 10.0, 30.0, top]);
+        // mean 14.67, deviation 11.58
+        let sd = bounds(&values, Method::StdDev, 2);
+        assert_eq!(sd.len(), 3);
+        assert!((sd[1] - 14.666).abs() < 0.01);
+
+        // repeated values leave fewer classes
+        assert_eq!(bounds(&[4.0, 4.0, 4.0], Method::Quantile, 5), vec![4.0, 4.0f64.next_up()]);
+    }
+}
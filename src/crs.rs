@@ -0,0 +1,500 @@
+use serde::de::{self, Deserialize, Deserializer};
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug)]
+struct Ellipsoid {
+    a: f64,
+    f: f64,
+}
+
+const WGS84: Ellipsoid = Ellipsoid {
+    a: 6378137.0,
+    f: 1.0 / 298.257223563,
+};
+
+const GRS80: Ellipsoid = Ellipsoid {
+    a: 6378137.0,
+    f: 1.0 / 298.257222101,
+};
+
+const INTERNATIONAL_1924: Ellipsoid = Ellipsoid {
+    a: 6378388.0,
+    f: 1.0 / 297.0,
+};
+
+impl Ellipsoid {
+    fn e2(&self) -> f64 {
+        self.f * (2.0 - self.f)
+    }
+
+    fn e(&self) -> f64 {
+        self.e2().sqrt()
+    }
+
+    fn to_ecef(&self, lon: f64, lat: f64, h: f64) -> [f64; 3] {
+        let n = self.a / (1.0 - self.e2() * lat.sin().powi(2)).sqrt();
+        [
+            (n + h) * lat.cos() * lon.cos(),
+            (n + h) * lat.cos() * lon.sin(),
+            (n * (1.0 - self.e2()) + h) * lat.sin(),
+        ]
+    }
+
+    fn from_ecef(&self, p: [f64; 3]) -> (f64, f64, f64) {
+        let e2 = self.e2();
+        let lon = p[1].atan2(p[0]);
+        let r = (p[0] * p[0] + p[1] * p[1]).sqrt();
+        let mut lat = p[2].atan2(r * (1.0 - e2));
+        let mut h = 0.0;
+        for _ in 0..8 {
+            let n = self.a / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+            h = r / lat.cos() - n;
+            lat = p[2].atan2(r * (1.0 - e2 * n / (n + h)));
+        }
+        (lon, lat, h)
+    }
+}
+
+/// Seven parameters to WGS84, position vector convention, rotations in
+/// arc-seconds and scale in parts per million.
+#[derive(Copy, Clone, Debug)]
+struct Helmert {
+    t: [f64; 3],
+    r: [f64; 3],
+    ds: f64,
+}
+
+/// BD72 to WGS84, as in EPSG:15929.
+const BD72: Helmert = Helmert {
+    t: [-106.8686, 52.2978, -103.7239],
+    r: [0.3366, -0.457, 1.8422],
+    ds: -1.2747,
+};
+
+impl Helmert {
+    fn apply(&self, p: [f64; 3], sign: f64) -> [f64; 3] {
+        let arc = PI / (180.0 * 3600.0);
+        let (rx, ry, rz) = (
+            sign * self.r[0] * arc,
+            sign * self.r[1] * arc,
+            sign * self.r[2] * arc,
+        );
+        let s = 1.0 + sign * self.ds * 1e-6;
+        [
+            sign * self.t[0] + s * (p[0] - rz * p[1] + ry * p[2]),
+            sign * self.t[1] + s * (rz * p[0] + p[1] - rx * p[2]),
+            sign * self.t[2] + s * (-ry * p[0] + rx * p[1] + p[2]),
+        ]
+    }
+}
+
+/// Lambert Conic Conformal with two standard parallels, angles in degrees.
+#[derive(Copy, Clone, Debug)]
+struct Lcc {
+    ellipsoid: Ellipsoid,
+    lat0: f64,
+    lon0: f64,
+    lat1: f64,
+    lat2: f64,
+    x0: f64,
+    y0: f64,
+}
+
+const LAMBERT_72: Lcc = Lcc {
+    ellipsoid: INTERNATIONAL_1924,
+    lat0: 90.0,
+    lon0: 4.367486666666666,
+    lat1: 51.16666723333333,
+    lat2: 49.8333339,
+    x0: 150000.013,
+    y0: 5400088.438,
+};
+
+const LAMBERT_2008: Lcc = Lcc {
+    ellipsoid: GRS80,
+    lat0: 50.797815,
+    lon0: 4.359215833333333,
+    lat1: 49.83333333333334,
+    lat2: 51.16666666666666,
+    x0: 649328.0,
+    y0: 665262.0,
+};
+
+impl Lcc {
+    fn m(&self, lat: f64) -> f64 {
+        lat.cos() / (1.0 - self.ellipsoid.e2() * lat.sin().powi(2)).sqrt()
+    }
+
+    fn t(&self, lat: f64) -> f64 {
+        let e = self.ellipsoid.e();
+        let es = e * lat.sin();
+        (PI / 4.0 - lat / 2.0).tan() / ((1.0 - es) / (1.0 + es)).powf(e / 2.0)
+    }
+
+    /// Cone constant, F and radius at the latitude of origin.
+    fn constants(&self) -> (f64, f64, f64) {
+        let (lat1, lat2) = (self.lat1.to_radians(), self.lat2.to_radians());
+        let (m1, m2) = (self.m(lat1), self.m(lat2));
+        let (t1, t2) = (self.t(lat1), self.t(lat2));
+        let n = (m1.ln() - m2.ln()) / (t1.ln() - t2.ln());
+        let f = m1 / (n * t1.powf(n));
+        let r0 = self.ellipsoid.a * f * self.t(self.lat0.to_radians()).powf(n);
+        (n, f, r0)
+    }
+
+    fn forward(&self, lon: f64, lat: f64) -> (f64, f64) {
+        let (n, f, r0) = self.constants();
+        let r = self.ellipsoid.a * f * self.t(lat).powf(n);
+        let theta = n * (lon - self.lon0.to_radians());
+        (self.x0 + r * theta.sin(), self.y0 + r0 - r * theta.cos())
+    }
+
+    fn inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        let (n, f, r0) = self.constants();
+        let dx = x - self.x0;
+        let dy = r0 - (y - self.y0);
+        let r = n.signum() * (dx * dx + dy * dy).sqrt();
+        let t = (r / (self.ellipsoid.a * f)).powf(1.0 / n);
+        let theta = if n > 0.0 {
+            dx.atan2(dy)
+        } else {
+            (-dx).atan2(-dy)
+        };
+        let e = self.ellipsoid.e();
+        let mut lat = PI / 2.0 - 2.0 * t.atan();
+        for _ in 0..10 {
+            let es = e * lat.sin();
+            lat = PI / 2.0 - 2.0 * (t * ((1.0 - es) / (1.0 + es)).powf(e / 2.0)).atan();
+        }
+        (theta / n + self.lon0.to_radians(), lat)
+    }
+}
+
+/// Transverse Mercator after Krüger's series, as used for UTM.
+struct Tm {
+    lon0: f64,
+    k0: f64,
+    x0: f64,
+    y0: f64,
+}
+
+impl Tm {
+    fn utm(zone: u8, north: bool) -> Tm {
+        Tm {
+            lon0: (f64::from(zone) * 6.0 - 183.0).to_radians(),
+            k0: 0.9996,
+            x0: 500000.0,
+            y0: if north { 0.0 } else { 10000000.0 },
+        }
+    }
+
+    fn series() -> (f64, f64, [f64; 3], [f64; 3], [f64; 3]) {
+        let n = WGS84.f / (2.0 - WGS84.f);
+        let (n2, n3) = (n * n, n * n * n);
+        let big_a = WGS84.a / (1.0 + n) * (1.0 + n2 / 4.0 + n2 * n2 / 64.0);
+        let alpha = [
+            n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0,
+            13.0 * n2 / 48.0 - 3.0 * n3 / 5.0,
+            61.0 * n3 / 240.0,
+        ];
+        let beta = [
+            n / 2.0 - 2.0 * n2 / 3.0 + 37.0 * n3 / 96.0,
+            n2 / 48.0 + n3 / 15.0,
+            17.0 * n3 / 480.0,
+        ];
+        let delta = [
+            2.0 * n - 2.0 * n2 / 3.0 - 2.0 * n3,
+            7.0 * n2 / 3.0 - 8.0 * n3 / 5.0,
+            56.0 * n3 / 15.0,
+        ];
+        (n, big_a, alpha, beta, delta)
+    }
+
+    fn forward(&self, lon: f64, lat: f64) -> (f64, f64) {
+        let (n, big_a, alpha, _, _) = Tm::series();
+        let c = 2.0 * n.sqrt() / (1.0 + n);
+        let t = (lat.sin().atanh() - c * (c * lat.sin()).atanh()).sinh();
+        let dl = lon - self.lon0;
+        let xi = t.atan2(dl.cos());
+        let eta = (dl.sin() / (1.0 + t * t).sqrt()).atanh();
+        let (mut x, mut y) = (eta, xi);
+        for (j, a) in alpha.iter().enumerate() {
+            let k = 2.0 * (j as f64 + 1.0);
+            x += a * (k * xi).cos() * (k * eta).sinh();
+            y += a * (k * xi).sin() * (k * eta).cosh();
+        }
+        (self.x0 + self.k0 * big_a * x, self.y0 + self.k0 * big_a * y)
+    }
+
+    fn inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        let (_, big_a, _, beta, delta) = Tm::series();
+        let xi = (y - self.y0) / (self.k0 * big_a);
+        let eta = (x - self.x0) / (self.k0 * big_a);
+        let (mut xi_p, mut eta_p) = (xi, eta);
+        for (j, b) in beta.iter().enumerate() {
+            let k = 2.0 * (j as f64 + 1.0);
+            xi_p -= b * (k * xi).sin() * (k * eta).cosh();
+            eta_p -= b * (k * xi).cos() * (k * eta).sinh();
+        }
+        let chi = (xi_p.sin() / eta_p.cosh()).asin();
+        let mut lat = chi;
+        for (j, d) in delta.iter().enumerate() {
+            lat += d * (2.0 * (j as f64 + 1.0) * chi).sin();
+        }
+        (self.lon0 + eta_p.sinh().atan2(xi_p.cos()), lat)
+    }
+}
+
+const MERCATOR_RADIUS: f64 = 6378137.0;
+
+/// A coordinate reference system, written in manifests as `EPSG:4326`,
+/// `EPSG:3857`, `EPSG:326zz`/`EPSG:327zz` (UTM), `EPSG:31370`,
+/// `EPSG:3812`, `UTM:31N` or `ENU:lon,lat[,height]`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Crs {
+    Wgs84,
+    WebMercator,
+    Utm { zone: u8, north: bool },
+    Lambert72,
+    Lambert2008,
+    /// East-North-Up tangent plane at an origin given in WGS84 degrees.
+    Enu { lon: f64, lat: f64, height: f64 },
+}
+
+impl Crs {
+    /// Longitude and latitude in radians and ellipsoidal height on WGS84.
+    fn to_wgs84(&self, p: [f64; 3]) -> (f64, f64, f64) {
+        match *self {
+            Crs::Wgs84 => (p[0].to_radians(), p[1].to_radians(), p[2]),
+            Crs::WebMercator => (
+                p[0] / MERCATOR_RADIUS,
+                2.0 * (p[1] / MERCATOR_RADIUS).exp().atan() - PI / 2.0,
+                p[2],
+            ),
+            Crs::Utm { zone, north } => {
+                let (lon, lat) = Tm::utm(zone, north).inverse(p[0], p[1]);
+                (lon, lat, p[2])
+            }
+            Crs::Lambert72 => {
+                let (lon, lat) = LAMBERT_72.inverse(p[0], p[1]);
+                let ecef = BD72.apply(INTERNATIONAL_1924.to_ecef(lon, lat, 0.0), 1.0);
+                let (lon, lat, _) = WGS84.from_ecef(ecef);
+                (lon, lat, p[2])
+            }
+            Crs::Lambert2008 => {
+                let (lon, lat) = LAMBERT_2008.inverse(p[0], p[1]);
+                (lon, lat, p[2])
+            }
+            Crs::Enu { lon, lat, height } => {
+                let (lon0, lat0) = (lon.to_radians(), lat.to_radians());
+                let o = WGS84.to_ecef(lon0, lat0, height);
+                let (sl, cl, sp, cp) = (lon0.sin(), lon0.cos(), lat0.sin(), lat0.cos());
+                let ecef = [
+                    o[0] - sl * p[0] - sp * cl * p[1] + cp * cl * p[2],
+                    o[1] + cl * p[0] - sp * sl * p[1] + cp * sl * p[2],
+                    o[2] + cp * p[1] + sp * p[2],
+                ];
+                WGS84.from_ecef(ecef)
+            }
+        }
+    }
+
+    /// Longitude and latitude in degrees.
+    pub fn geographic(&self, p: [f64; 3]) -> (f64, f64) {
+        let (lon, lat, _) = self.to_wgs84(p);
+        (lon.to_degrees(), lat.to_degrees())
+    }
+
+    fn from_wgs84(&self, lon: f64, lat: f64, h: f64) -> [f64; 3] {
+        match *self {
+            Crs::Wgs84 => [lon.to_degrees(), lat.to_degrees(), h],
+            Crs::WebMercator => [
+                MERCATOR_RADIUS * lon,
+                MERCATOR_RADIUS * (PI / 4.0 + lat / 2.0).tan().ln(),
+                h,
+            ],
+            Crs::Utm { zone, north } => {
+                let (x, y) = Tm::utm(zone, north).forward(lon, lat);
+                [x, y, h]
+            }
+            Crs::Lambert72 => {
+                let ecef = BD72.apply(WGS84.to_ecef(lon, lat, 0.0), -1.0);
+                let (lon, lat, _) = INTERNATIONAL_1924.from_ecef(ecef);
+                let (x, y) = LAMBERT_72.forward(lon, lat);
+                [x, y, h]
+            }
+            Crs::Lambert2008 => {
+                let (x, y) = LAMBERT_2008.forward(lon, lat);
+                [x, y, h]
+            }
+            Crs::Enu {
+                lon: lon0,
+                lat: lat0,
+                height,
+            } => {
+                let (lon0, lat0) = (lon0.to_radians(), lat0.to_radians());
+                let o = WGS84.to_ecef(lon0, lat0, height);
+                let p = WGS84.to_ecef(lon, lat, h);
+                let d = [p[0] - o[0], p[1] - o[1], p[2] - o[2]];
+                let (sl, cl, sp, cp) = (lon0.sin(), lon0.cos(), lat0.sin(), lat0.cos());
+                [
+                    -sl * d[0] + cl * d[1],
+                    -sp * cl * d[0] - sp * sl * d[1] + cp * d[2],
+                    cp * cl * d[0] + cp * sl * d[1] + sp * d[2],
+                ]
+            }
+        }
+    }
+}
+
+impl FromStr for Crs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Crs, String> {
+        let upper = s.trim().to_uppercase();
+        let bad = || format!("unknown coordinate reference system \"{}\"", s);
+        if upper.starts_with("ENU:") {
+            let values = upper[4..]
+                .split(',')
+                .map(|v| v.trim().parse::<f64>().map_err(|_| bad()))
+                .collect::<Result<Vec<f64>, String>>()?;
+            return match values.len() {
+                2 | 3 => Ok(Crs::Enu {
+                    lon: values[0],
+                    lat: values[1],
+                    height: values.get(2).cloned().unwrap_or(0.0),
+                }),
+                _ => Err(bad()),
+            };
+        }
+        if upper.starts_with("UTM:") {
+            let zone = &upper[4..];
+            let (digits, hemisphere) = zone.split_at(zone.len().saturating_sub(1));
+            let zone = digits.parse::<u8>().map_err(|_| bad())?;
+            return match hemisphere {
+                "N" if zone >= 1 && zone <= 60 => Ok(Crs::Utm { zone, north: true }),
+                "S" if zone >= 1 && zone <= 60 => Ok(Crs::Utm { zone, north: false }),
+                _ => Err(bad()),
+            };
+        }
+        match upper.as_str() {
+            "EPSG:4326" | "WGS84" | "CRS:84" => Ok(Crs::Wgs84),
+            "EPSG:3857" => Ok(Crs::WebMercator),
+            "EPSG:31370" => Ok(Crs::Lambert72),
+            "EPSG:3812" => Ok(Crs::Lambert2008),
+            _ => {
+                let code = upper
+                    .trim_start_matches("EPSG:")
+                    .parse::<u32>()
+                    .map_err(|_| bad())?;
+                match code {
+                    32601..=32660 => Ok(Crs::Utm {
+                        zone: (code - 32600) as u8,
+                        north: true,
+                    }),
+                    32701..=32760 => Ok(Crs::Utm {
+                        zone: (code - 32700) as u8,
+                        north: false,
+                    }),
+                    _ => Err(bad()),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Crs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Crs::Wgs84 => write!(f, "EPSG:4326"),
+            Crs::WebMercator => write!(f, "EPSG:3857"),
+            Crs::Utm { zone, north: true } => write!(f, "EPSG:{}", 32600 + u32::from(zone)),
+            Crs::Utm { zone, north: false } => write!(f, "EPSG:{}", 32700 + u32::from(zone)),
+            Crs::Lambert72 => write!(f, "EPSG:31370"),
+            Crs::Lambert2008 => write!(f, "EPSG:3812"),
+            Crs::Enu { lon, lat, height } => write!(f, "ENU:{},{},{}", lon, lat, height),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Crs {
+    fn deserialize<D>(deserializer: D) -> Result<Crs, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<Crs>().map_err(de::Error::custom)
+    }
+}
+
+/// Moves points from a layer's system to the scene's.
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    pub source: Crs,
+    pub target: Crs,
+}
+
+impl Transform {
+    /// `None` when there is nothing to do.
+    pub fn between(source: Option<Crs>, target: Option<Crs>) -> Option<Transform> {
+        match (source, target) {
+            (Some(source), Some(target)) if source != target => Some(Transform { source, target }),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, p: [f64; 3]) -> [f64; 3] {
+        let (lon, lat, h) = self.source.to_wgs84(p);
+        self.target.from_wgs84(lon, lat, h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crs::{Crs, Transform, LAMBERT_72};
+
+    fn close(a: [f64; 3], b: [f64; 3], tolerance: f64) -> bool {
+        (0..3).all(|i| (a[i] - b[i]).abs() < tolerance)
+    }
+
+    #[test]
+    fn utm_central_meridian() {
+        let t = Transform::between(Some(Crs::Wgs84), Some(Crs::Utm { zone: 31, north: true }))
+            .unwrap();
+        assert!(close(t.apply([3.0, 0.0, 0.0]), [500000.0, 0.0, 0.0], 1e-3));
+        assert!(close(t.apply([3.0, 45.0, 0.0]), [500000.0, 4982950.4, 0.0], 0.1));
+    }
+
+    #[test]
+    fn lambert_72_projection() {
+        // EPSG guidance note 7-2 example, on the BD72 datum
+        let (x, y) = LAMBERT_72.forward(
+            (5.0 + 48.0 / 60.0 + 26.533 / 3600.0f64).to_radians(),
+            (50.0 + 40.0 / 60.0 + 46.461 / 3600.0f64).to_radians(),
+        );
+        assert!((x - 251763.20).abs() < 0.1);
+        assert!((y - 153034.13).abs() < 0.1);
+    }
+
+    #[test]
+    fn round_trips() {
+        let p = [4.3517, 50.8466, 25.0];
+        for crs in [
+            "EPSG:3857",
+            "EPSG:32631",
+            "EPSG:31370",
+            "EPSG:3812",
+            "ENU:4.35,50.85,10",
+        ]
+            .iter()
+        {
+            let target = crs.parse::<Crs>().unwrap();
+            let forward = Transform::between(Some(Crs::Wgs84), Some(target)).unwrap();
+            let back = Transform::between(Some(target), Some(Crs::Wgs84)).unwrap();
+            let q = back.apply(forward.apply(p));
+            assert!(close(p, q, 1e-6), "{} {:?}", crs, q);
+        }
+    }
+}
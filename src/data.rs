@@ -1,17 +1,34 @@
+use cache;
+use cityjson;
+use crs::{Crs, Transform};
+use dem;
+use dem::Dem;
 use geojson::{Feature, GeoJson, Geometry, PolygonType, Value};
-use lingua::get_properties;
-use lingua::plane_from_feature;
+use gpkg;
+use input;
+use light::Light;
+use lingua::{geometry_kind, plane_from_feature, GeometryError};
 use lingua::Properties;
-use lingua::{Plane, PlaneList};
-use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use lingua::{FeatureRecord, FeatureStore, Plane, PlaneKind, PlaneList, PlaneT, Point};
+use manifest::{DataFormat, LayerConfig, LayerOptions, Manifest, ManifestError};
+use obj;
+use region::Region;
+use shapefile;
+use rayon::prelude::*;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor,
+};
 use serde_json::Deserializer as JsonDeserializer;
 
-use std::cell::{Ref, RefCell};
-use std::fs;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::{cmp, fmt};
-use style::{load_style, StyleCollection, StyleList};
+use style::{load_style, StyleCollection, StyleError, StyleList};
 use time::precise_time_s;
+use validate;
 
 pub struct Counter {
     name: String,
@@ -37,7 +54,7 @@ impl Counter {
         if self.count == self.p {
             self.p += self.pstep;
             let d = precise_time_s() - self.t;
-            println!(
+            eprintln!(
                 "Count({}) -> {} {} {}",
                 self.name,
                 self.count,
@@ -49,122 +66,907 @@ impl Counter {
     }
 }
 
-#[derive(Clone)]
-pub struct DeState {
-    pub style: StyleList,
-    pub layer_index: usize,
+#[derive(Debug)]
+pub enum LoadError {
+    Manifest(ManifestError),
+    Io(PathBuf, io::Error),
+    Style(PathBuf, StyleError),
+    Data(PathBuf, serde_json::Error),
+    /// A malformed data file that is not JSON.
+    Format(PathBuf, String),
+    Region(String),
+    Dem(PathBuf, String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Manifest(e) => write!(f, "manifest: {}", e),
+            LoadError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            LoadError::Style(path, e) => write!(f, "style {}: {}", path.display(), e),
+            LoadError::Data(path, e) => write!(f, "data {}: {}", path.display(), e),
+            LoadError::Format(path, e) => write!(f, "data {}: {}", path.display(), e),
+            LoadError::Region(e) => write!(f, "region: {}", e),
+            LoadError::Dem(path, e) => write!(f, "elevation model {}: {}", path.display(), e),
+        }
+    }
+}
+
+impl From<ManifestError> for LoadError {
+    fn from(e: ManifestError) -> LoadError {
+        LoadError::Manifest(e)
+    }
+}
+
+/// What happened to the features of one layer while loading it.
+#[derive(Debug, Default)]
+pub struct LayerReport {
+    pub name: String,
+    pub features_read: usize,
+    /// Features that matched one of the configured styles.
+    pub features_styled: usize,
+    /// Features that only got the default style.
+    pub features_unmatched: usize,
+    pub features_invalid: usize,
+    /// Features dropped by the layer filter.
+    pub features_filtered: usize,
+    /// Features lying outside of the scene region.
+    pub features_outside: usize,
+    /// Draped features partly off the elevation model, left at their own
+    /// height there.
+    pub features_off_dem: usize,
+    pub skipped_geometries: BTreeMap<String, usize>,
+    pub error: Option<LoadError>,
 }
 
-thread_local! {
-    pub static STATE: RefCell<Option<DeState>> = RefCell::new(None);
+impl LayerReport {
+    pub fn new(name: &str) -> LayerReport {
+        LayerReport {
+            name: name.to_owned(),
+            ..LayerReport::default()
+        }
+    }
+
+    pub fn skip(&mut self, kind: &str) {
+        *self.skipped_geometries.entry(kind.to_owned()).or_insert(0) += 1;
+    }
+
+    fn merge(&mut self, other: LayerReport) {
+        self.features_read += other.features_read;
+        self.features_styled += other.features_styled;
+        self.features_unmatched += other.features_unmatched;
+        self.features_invalid += other.features_invalid;
+        self.features_filtered += other.features_filtered;
+        self.features_outside += other.features_outside;
+        self.features_off_dem += other.features_off_dem;
+        for (kind, n) in other.skipped_geometries {
+            *self.skipped_geometries.entry(kind).or_insert(0) += n;
+        }
+    }
+
+    /// A layer that failed to load. Invalid features are counted and left
+    /// out, they do not break the layer.
+    pub fn is_broken(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+impl fmt::Display for LayerReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "layer {}: read {}, styled {}, unmatched {}, invalid {}",
+            self.name,
+            self.features_read,
+            self.features_styled,
+            self.features_unmatched,
+            self.features_invalid,
+        )?;
+        if self.features_filtered > 0 {
+            write!(f, ", filtered {}", self.features_filtered)?;
+        }
+        if self.features_outside > 0 {
+            write!(f, ", outside {}", self.features_outside)?;
+        }
+        if self.features_off_dem > 0 {
+            write!(f, ", off dem {}", self.features_off_dem)?;
+        }
+        for (kind, n) in self.skipped_geometries.iter() {
+            write!(f, ", skipped {} {}", kind, n)?;
+        }
+        match self.error {
+            Some(ref e) => write!(f, ", error {}", e),
+            None => Ok(()),
+        }
+    }
 }
 
 pub struct Data {
+    pub manifest: Manifest,
+    /// Only loaded when some layer has to be read.
+    scene: Option<Scene>,
     pub styles: StyleCollection,
     pub planes: PlaneList,
+    /// Source features of each layer, by `layer_index`.
+    pub features: Vec<FeatureStore>,
+    pub reports: Vec<LayerReport>,
+}
+
+/// Scene-wide settings the layer loaders share.
+pub struct Scene {
+    pub crs: Option<Crs>,
+    pub region: Option<Region>,
+    pub dem: Option<Dem>,
 }
 
-#[derive(Deserialize)]
-struct FeatureList {
-    #[serde(deserialize_with = "deserialize_features")]
-    #[serde(rename(deserialize = "features"))]
-    planes: PlaneList,
+impl Scene {
+    fn from_manifest(manifest: &Manifest) -> Result<Scene, LoadError> {
+        let region = match manifest.region {
+            Some(ref config) => Some(Region::from_config(config).map_err(LoadError::Region)?),
+            None => None,
+        };
+        let dem = match manifest.dem {
+            Some(ref path) => {
+                eprintln!("Loading elevation model {}", path.display());
+                Some(Dem::from_file(path).map_err(|e| LoadError::Dem(path.clone(), e))?)
+            }
+            None => None,
+        };
+        Ok(Scene {
+            crs: manifest.crs,
+            region,
+            dem,
+        })
+    }
 }
 
-fn deserialize_features<'de, D>(deserializer: D) -> Result<PlaneList, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    struct FeatureListVisitor;
+/// Planes loaded for one layer, and how loading went.
+pub struct LayerData {
+    pub planes: PlaneList,
+    pub features: FeatureStore,
+    pub report: LayerReport,
+    /// The layer's styles, grown with the ones resolved from ramps.
+    pub style: StyleList,
+}
 
-    impl<'de> Visitor<'de> for FeatureListVisitor {
-        type Value = PlaneList;
+/// What a layer loader needs to know to turn features into planes; it
+/// doubles as the `DeserializeSeed` of a GeoJSON FeatureCollection.
+#[derive(Clone, Copy)]
+pub struct LayerSeed<'a> {
+    pub style: &'a StyleList,
+    pub layer_index: usize,
+    pub options: &'a LayerOptions,
+    /// From the layer's coordinate system to the scene's.
+    pub transform: Option<Transform>,
+    /// Polygons of terrain layers are ground.
+    pub ground: bool,
+    pub scene: &'a Scene,
+}
 
-        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("a sequence of features")
+impl<'a> LayerSeed<'a> {
+    pub fn new(
+        layer: &'a LayerConfig,
+        style: &'a StyleList,
+        layer_index: usize,
+        scene: &'a Scene,
+    ) -> LayerSeed<'a> {
+        LayerSeed {
+            style,
+            layer_index,
+            options: &layer.options,
+            transform: Transform::between(layer.crs, scene.crs),
+            ground: layer.format() == DataFormat::Dem,
+            scene,
         }
+    }
 
-        fn visit_seq<S>(self, mut seq: S) -> Result<PlaneList, S::Error>
-        where
-            S: SeqAccess<'de>,
-        {
-            let mut c = Counter::new("visit", 1000);
-            let mut planes: PlaneList = PlaneList::new(1000000);
+    pub fn empty(&self) -> LayerData {
+        LayerData {
+            // without a hint, the list grows with the features read
+            planes: PlaneList::new(self.options.capacity.unwrap_or(0)),
+            features: FeatureStore::new(),
+            report: LayerReport::default(),
+            style: self.style.configured(),
+        }
+    }
 
-            match STATE.with(|s| s.borrow().clone()) {
-                None => (),
-                Some(s) => {
-                    while let Some(value) = seq.next_element()? {
-                        match Feature::from_json_object(value) {
-                            Ok(ref f) => match s.style.select(&f.properties) {
-                                Some(style_index) => {
-                                    // println!("styled {} {}", s.layer_index, style_index);
-                                    // c.inc();
-                                    planes.push(plane_from_feature(f, s.layer_index, style_index));
+    /// Styles a feature and turns it into planes, accounting for it in the
+    /// layer report.
+    pub fn push_feature(&self, f: &Feature, data: &mut LayerData) {
+        if let Some(ref filter) = self.options.filter {
+            let geometry = f.geometry.as_ref().map(|g| geometry_kind(&g.value));
+            if !filter.matches(f.properties.as_ref(), geometry) {
+                data.report.features_filtered += 1;
+                return;
+            }
+        }
+        self.push_accepted(f, data);
+    }
+
+    fn push_accepted(&self, f: &Feature, data: &mut LayerData) {
+        let selected = data.style.select(&f.properties);
+        let report = &mut data.report;
+        match selected {
+            Some(style_index) => {
+                // println!("styled {} {}", self.layer_index, style_index);
+                let feature_index = data.features.len();
+                match plane_from_feature(f, self.layer_index, style_index, feature_index, self.options) {
+                    Ok(mut plane) => {
+                        if let Some(t) = self.transform {
+                            for p in plane.iter_mut() {
+                                p.map_points(|pt| {
+                                    let q = t.apply([pt.x, pt.y, pt.z]);
+                                    Point::new(q[0], q[1], q[2])
+                                });
+                            }
+                        }
+                        let mut plane = match self.scene.region {
+                            Some(ref region) => match region.apply(plane) {
+                                Some(plane) => plane,
+                                None => {
+                                    report.features_outside += 1;
+                                    return;
                                 }
-                                None => (),
                             },
-                            Err(_) => (),
+                            None => plane,
                         };
+                        if let (Some(ref dem), Some(ref drape)) = (&self.scene.dem, &self.options.drape) {
+                            if !dem.drape(&mut plane, drape, self.options.extrude.is_some()) {
+                                report.features_off_dem += 1;
+                            }
+                        }
+                        if self.options.repair {
+                            plane = validate::repair(plane);
+                            if plane.is_empty() {
+                                report.skip("degenerate");
+                                return;
+                            }
+                        }
+                        if self.ground {
+                            for p in plane.iter_mut().filter(|p| p.kind == PlaneKind::Polygon) {
+                                p.kind = PlaneKind::Ground;
+                            }
+                        }
+                        data.features.push(f);
+                        if data.style.is_fallback(style_index) {
+                            report.features_unmatched += 1;
+                        } else {
+                            report.features_styled += 1;
+                        }
+                        data.planes.push(plane);
+                    }
+                    Err(GeometryError::Skipped(kind)) => report.skip(kind),
+                    Err(GeometryError::Invalid(_)) => report.features_invalid += 1,
+                }
+            }
+            None => report.features_unmatched += 1,
+        }
+    }
+
+    pub fn push_object(&self, object: serde_json::Map<String, serde_json::Value>, data: &mut LayerData) {
+        data.report.features_read += 1;
+        if let Some(ref filter) = self.options.filter {
+            if !filter.matches_object(&object) {
+                data.report.features_filtered += 1;
+                return;
+            }
+        }
+        match Feature::from_json_object(object) {
+            Ok(ref f) => self.push_accepted(f, data),
+            Err(_) => data.report.features_invalid += 1,
+        }
+    }
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for LayerSeed<'a> {
+    type Value = LayerData;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<LayerData, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FeatureCollectionVisitor<'a>(LayerSeed<'a>);
+
+        impl<'de, 'a> Visitor<'de> for FeatureCollectionVisitor<'a> {
+            type Value = LayerData;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a feature collection")
+            }
+
+            fn visit_map<M>(self, mut map: M) -> Result<LayerData, M::Error>
+            where
+                M: MapAccess<'de>,
+            {
+                let mut layer = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == "features" {
+                        layer = Some(map.next_value_seed(FeatureListSeed(self.0))?);
+                    } else {
+                        map.next_value::<IgnoredAny>()?;
                     }
                 }
+                layer.ok_or_else(|| de::Error::missing_field("features"))
+            }
+        }
+
+        deserializer.deserialize_map(FeatureCollectionVisitor(self))
+    }
+}
+
+struct FeatureListSeed<'a>(LayerSeed<'a>);
+
+impl<'de, 'a> DeserializeSeed<'de> for FeatureListSeed<'a> {
+    type Value = LayerData;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<LayerData, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FeatureListVisitor<'a>(LayerSeed<'a>);
+
+        impl<'de, 'a> Visitor<'de> for FeatureListVisitor<'a> {
+            type Value = LayerData;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a sequence of features")
             }
 
-            Ok(planes)
+            fn visit_seq<S>(self, mut seq: S) -> Result<LayerData, S::Error>
+            where
+                S: SeqAccess<'de>,
+            {
+                let mut c = Counter::new("visit", 1000);
+                let mut layer = self.0.empty();
+                while let Some(object) = seq.next_element()? {
+                    // c.inc();
+                    self.0.push_object(object, &mut layer);
+                }
+                Ok(layer)
+            }
         }
+
+        deserializer.deserialize_seq(FeatureListVisitor(self.0))
     }
+}
 
-    let visitor = FeatureListVisitor;
-    deserializer.deserialize_seq(visitor)
+fn load_geojson(layer: &LayerConfig, seed: LayerSeed) -> Result<LayerData, LoadError> {
+    let reader = input::open(&layer.data).map_err(|e| LoadError::Io(layer.data.clone(), e))?;
+    eprintln!("Loading data {} ({})", layer.name, layer.data.display());
+    let mut deserializer = JsonDeserializer::from_reader(reader);
+    let data = seed
+        .deserialize(&mut deserializer)
+        .and_then(|data| deserializer.end().map(|_| data))
+        .map_err(|e| LoadError::Data(layer.data.clone(), e))?;
+    eprintln!("Loaded {}", layer.name);
+    Ok(data)
+}
+
+/// Reads a GeoJSON text sequence line by line, a line holding either a
+/// feature or a whole feature collection.
+fn load_geojson_seq(layer: &LayerConfig, seed: LayerSeed) -> Result<LayerData, LoadError> {
+    let mut reader = input::open(&layer.data).map_err(|e| LoadError::Io(layer.data.clone(), e))?;
+    eprintln!("Loading data {} ({})", layer.name, layer.data.display());
+    let mut data = seed.empty();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| LoadError::Io(layer.data.clone(), e))?;
+        if read == 0 {
+            break;
+        }
+        let start = line
+            .iter()
+            .position(|b| *b != 0x1e && !b.is_ascii_whitespace())
+            .unwrap_or(line.len());
+        if start == line.len() {
+            continue;
+        }
+        let mut object: serde_json::Map<String, serde_json::Value> =
+            match serde_json::from_slice(&line[start..]) {
+                Ok(object) => object,
+                Err(_) => {
+                    data.report.features_read += 1;
+                    data.report.features_invalid += 1;
+                    continue;
+                }
+            };
+        if object.get("type").and_then(|t| t.as_str()) == Some("FeatureCollection") {
+            if let Some(serde_json::Value::Array(features)) = object.remove("features") {
+                for feature in features {
+                    match feature {
+                        serde_json::Value::Object(feature) => seed.push_object(feature, &mut data),
+                        _ => {
+                            data.report.features_read += 1;
+                            data.report.features_invalid += 1;
+                        }
+                    }
+                }
+            }
+        } else {
+            seed.push_object(object, &mut data);
+        }
+    }
+    eprintln!("Loaded {}", layer.name);
+    Ok(data)
+}
+
+/// Standard input can not be hashed nor read twice, scenes reading it are
+/// never cached.
+fn cacheable(layers: &[&LayerConfig]) -> bool {
+    !layers.iter().any(|l| l.reads_stdin())
+}
+
+fn load_layer(
+    layer: &LayerConfig,
+    style: &StyleList,
+    index: usize,
+    scene: &Scene,
+) -> Result<LayerData, LoadError> {
+    let seed = LayerSeed::new(layer, style, index, scene);
+    match layer.format() {
+        DataFormat::GeoJson => load_geojson(layer, seed),
+        DataFormat::GeoJsonSeq => load_geojson_seq(layer, seed),
+        DataFormat::CityJson => cityjson::load(layer, seed),
+        DataFormat::Obj => obj::load(layer, seed),
+        DataFormat::Dem => dem::load(layer, seed),
+        DataFormat::Shapefile => shapefile::load(layer, seed),
+        DataFormat::GeoPackage => gpkg::load(layer, seed),
+    }
 }
 
 impl Data {
-    pub fn from_file(filename: &str) -> std::io::Result<Data> {
-        let records = std::fs::read_to_string(filename)?;
+    pub fn from_file<P: AsRef<Path>>(filename: P) -> Result<Data, LoadError> {
+        let manifest = Manifest::from_file(filename)?;
+        let layers = manifest.visible_layers();
         let mut planes: PlaneList = PlaneList::new(0);
-        let mut styles: StyleCollection = Vec::new();
-
-        records.lines().enumerate().for_each(|(index, r)| {
-            let mut file_names: Vec<&str> = r.split(":").collect();
-            match file_names.pop() {
-                Some(style_fn) => {
-                    println!("load_style {}", style_fn);
-                    let sj = load_style(style_fn).unwrap();
-                    let style = StyleList::from_config(&sj);
-
-                    STATE.with(|s| {
-                        *s.borrow_mut() = Some(DeState {
-                            style: style.clone(),
-                            layer_index: index,
-                        })
-                    });
-
-                    match file_names.pop() {
-                        Some(data_fn) => match ::std::fs::File::open(data_fn) {
-                            Ok(f) => {
-                                println!("Loading data {}", data_fn);
-                                // let deser = serde_json::Deserializer::from_reader(f);
-                                // let fl: FeatureList = deser.;
-                                let mut r: ::std::result::Result<FeatureList, ::serde_json::Error> = serde_json::from_reader(f);
-                                match r {
-                                    Ok(ref mut fl) => {
-                                        planes.merge(&mut fl.planes);
-                                        println!("Loaded");
-                                        },
-                                    Err(e)=> println!("Error {}", e),
-                                }
-                            }
-                            Err(e) => println!("Error {}", e),
-                        },
+        let mut features: Vec<FeatureStore> = layers.iter().map(|_| FeatureStore::new()).collect();
+        let mut reports: Vec<LayerReport> =
+            layers.iter().map(|l| LayerReport::new(&l.name)).collect();
+
+        let mut styles: StyleCollection = layers
+            .iter()
+            .zip(reports.iter_mut())
+            .map(|(layer, report)| {
+                eprintln!("load_style {}", layer.style.display());
+                match load_style(&layer.style).and_then(|sj| StyleList::from_config(&sj)) {
+                    Ok(sl) => sl.with_opacity(layer.opacity),
+                    Err(e) => {
+                        report.error = Some(LoadError::Style(layer.style.clone(), e));
+                        StyleList::new()
+                    }
+                }
+            }).collect();
+
+        let cache_path = manifest.cache_path();
+        // a layer left out for its style would be missing from the cache
+        let styled = reports.iter().all(|r| r.error.is_none());
+        let key = if styled && cacheable(&layers) {
+            cache::scene_key(&manifest, &layers).ok()
+        } else {
+            None
+        };
+        let cached = key.and_then(|key| match cache::read(&cache_path, key) {
+            Ok(cached) => cached,
+            Err(e) => {
+                eprintln!("Ignoring cache {}: {}", cache_path.display(), e);
+                None
+            }
+        });
+
+        let mut scene = None;
+        let fresh = cached.is_none();
+        match cached {
+            Some((cached_planes, cached_features, cached_reports)) => {
+                eprintln!("Loaded from cache {}", cache_path.display());
+                planes = cached_planes;
+                features = cached_features;
+                reports = cached_reports;
+            }
+            None => {
+                let loaded_scene = Scene::from_manifest(&manifest)?;
+                let loaded: Vec<Option<Result<LayerData, LoadError>>> = layers
+                    .par_iter()
+                    .enumerate()
+                    .map(|(index, layer)| match reports[index].error {
+                        Some(_) => None,
+                        None => Some(load_layer(layer, &styles[index], index, &loaded_scene)),
+                    }).collect();
+
+                let total = loaded
+                    .iter()
+                    .map(|result| match result {
+                        Some(Ok(ld)) => ld.planes.len(),
+                        _ => 0,
+                    }).sum();
+                planes = PlaneList::new(total);
+                for (((report, store), style), result) in reports
+                    .iter_mut()
+                    .zip(features.iter_mut())
+                    .zip(styles.iter_mut())
+                    .zip(loaded.into_iter())
+                {
+                    match result {
+                        Some(Ok(mut ld)) => {
+                            planes.merge(&mut ld.planes);
+                            *store = ld.features;
+                            *style = ld.style;
+                            report.merge(ld.report);
+                        }
+                        Some(Err(e)) => report.error = Some(e),
                         None => (),
                     }
+                }
+                scene = Some(loaded_scene);
+            }
+        }
+
+        let mut data = Data {
+            manifest,
+            scene,
+            planes,
+            features,
+            styles,
+            reports,
+        };
+        if fresh {
+            data.write_cache();
+        } else {
+            // cached style indices may come from other styles
+            for index in 0..data.styles.len() {
+                let style = data.styles[index].configured();
+                data.restyle(index, style);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Saves the scene unless a layer is broken or read from standard
+    /// input.
+    fn write_cache(&self) {
+        if self.is_broken() || !cacheable(&self.manifest.visible_layers()) {
+            return;
+        }
+        let cache_path = self.manifest.cache_path();
+        let written = cache::scene_key(&self.manifest, &self.manifest.visible_layers()).and_then(
+            |key| cache::write(&cache_path, key, &self.planes, &self.features, &self.reports),
+        );
+        if let Err(e) = written {
+            eprintln!("Could not write cache {}: {}", cache_path.display(), e);
+        }
+    }
+
+    fn layer(&self, layer_index: usize) -> LayerConfig {
+        self.manifest.visible_layers()[layer_index].clone()
+    }
+
+    /// Reads the style of a layer again and restyles its planes from the
+    /// feature store, without touching geometry. The cache is left alone,
+    /// it does not depend on styles.
+    pub fn reload_style(&mut self, layer_index: usize) -> Result<(), LoadError> {
+        let layer = self.layer(layer_index);
+        let style = load_style(&layer.style)
+            .and_then(|config| StyleList::from_config(&config))
+            .map_err(|e| LoadError::Style(layer.style.clone(), e))?
+            .with_opacity(layer.opacity);
+        self.restyle(layer_index, style);
+        Ok(())
+    }
 
-                    styles.push(style);
+    /// Sets the style of every plane of a layer from its feature, counting
+    /// styled and unmatched features anew.
+    fn restyle(&mut self, layer_index: usize, mut style: StyleList) {
+        let report = &mut self.reports[layer_index];
+        report.features_styled = 0;
+        report.features_unmatched = 0;
+        let fallback = style.fallback();
+        let indices: Vec<usize> = self.features[layer_index]
+            .iter()
+            .map(|record| {
+                let index = style.select(&record.properties).unwrap_or(fallback);
+                if style.is_fallback(index) {
+                    report.features_unmatched += 1;
+                } else {
+                    report.features_styled += 1;
                 }
-                None => (),
+                index
+            }).collect();
+        self.planes.for_each_mut(|p| {
+            if p.layer_index == layer_index {
+                p.style_index = indices[p.feature_index];
             }
         });
+        self.styles[layer_index] = style;
+    }
+
+    /// Reads the data of a layer again, other layers staying as they are.
+    pub fn reload_layer(&mut self, layer_index: usize) -> Result<(), LoadError> {
+        let layer = self.layer(layer_index);
+        if self.scene.is_none() {
+            self.scene = Some(Scene::from_manifest(&self.manifest)?);
+        }
+        let mut ld = {
+            let scene = self.scene.as_ref().unwrap();
+            load_layer(&layer, &self.styles[layer_index], layer_index, scene)?
+        };
+        self.planes.remove_layer(layer_index);
+        self.planes.merge(&mut ld.planes);
+        self.features[layer_index] = ld.features;
+        self.styles[layer_index] = ld.style;
+        let mut report = LayerReport::new(&layer.name);
+        report.merge(ld.report);
+        self.reports[layer_index] = report;
+        self.write_cache();
+        Ok(())
+    }
+
+    /// Reads the features of a layer with the fallback style alone, as
+    /// when its own style is missing or broken.
+    pub fn unstyled_features(&self, layer_index: usize) -> Result<FeatureStore, LoadError> {
+        let layer = self.layer(layer_index);
+        let loaded_scene;
+        let scene = match self.scene {
+            Some(ref scene) => scene,
+            None => {
+                loaded_scene = Scene::from_manifest(&self.manifest)?;
+                &loaded_scene
+            }
+        };
+        load_layer(&layer, &StyleList::unstyled(), layer_index, scene).map(|ld| ld.features)
+    }
+
+    /// The feature a plane was made from.
+    pub fn feature(&self, plane: &PlaneT) -> Option<&FeatureRecord> {
+        self.features
+            .get(plane.layer_index)
+            .and_then(|store| store.get(plane.feature_index))
+    }
+
+    /// The light of each layer, indexed like planes' `layer_index`.
+    pub fn lights(&self) -> Vec<Option<Light>> {
+        self.manifest
+            .visible_layers()
+            .iter()
+            .map(|l| l.light.as_ref().and_then(|c| Light::from_config(c).ok()))
+            .collect()
+    }
+
+    /// True if any layer is broken, see `LayerReport::is_broken`.
+    pub fn is_broken(&self) -> bool {
+        self.reports.iter().any(|r| r.is_broken())
+    }
+
+    /// Invalid features left out of all layers.
+    pub fn features_invalid(&self) -> usize {
+        self.reports.iter().map(|r| r.features_invalid).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data::{load_layer, Data, Scene};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+
+    const STYLE: &str = r#"{"kind":"continuous","propName":"h","intervals":[
+        {"low":0,"high":20,"fillColor":"red","strokeColor":"black","strokeWidth":1},
+        {"low":20,"high":100,"fillColor":"blue","strokeColor":"black","strokeWidth":1}]}"#;
+
+    fn square(h: f64) -> String {
+        format!(
+            r#"{{"type":"Feature","properties":{{"h":{}}},"geometry":{{"type":"Polygon",
+                "coordinates":[[[0,0,0],[1,0,0],[1,1,0],[0,0,0]]]}}}}"#,
+            h
+        )
+    }
 
-        Ok(Data { planes, styles })
+    #[test]
+    fn reload() {
+        let dir = env::temp_dir().join("cardboard-reload");
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("m.json.cache"));
+        fs::write(
+            dir.join("m.json"),
+            r#"{"layers":[{"name":"a","data":"a.geojson","style":"a.json"}]}"#,
+        ).unwrap();
+        fs::write(dir.join("a.json"), STYLE).unwrap();
+        fs::write(
+            dir.join("a.geojson"),
+            format!(
+                r#"{{"type":"FeatureCollection","features":[{},{}]}}"#,
+                square(10.0),
+                square(50.0)
+            ),
+        ).unwrap();
+
+        let mut data = Data::from_file(dir.join("m.json")).unwrap();
+        let styles = |data: &Data| -> Vec<usize> {
+            data.planes.flattened().iter().map(|p| p.style_index).collect()
+        };
+        assert_eq!(styles(&data), vec![0, 1]);
+
+        let cache = dir.join("m.json.cache");
+        let written = fs::read(&cache).unwrap();
+        let style = STYLE.replace(":20", ":5");
+        fs::write(dir.join("a.json"), style).unwrap();
+        data.reload_style(0).unwrap();
+        assert_eq!(styles(&data), vec![1, 1]);
+        // restyling leaves the cache as it was, and still good
+        assert_eq!(fs::read(&cache).unwrap(), written);
+        let cached = Data::from_file(dir.join("m.json")).unwrap();
+        assert_eq!(styles(&cached), vec![1, 1]);
+        assert_eq!(cached.reports[0].features_styled, 2);
+
+        fs::write(
+            dir.join("a.geojson"),
+            format!(r#"{{"type":"FeatureCollection","features":[{}]}}"#, square(1.0)),
+        ).unwrap();
+        data.reload_layer(0).unwrap();
+        assert_eq!(styles(&data), vec![0]);
+        assert_eq!(data.reports[0].features_read, 1);
+    }
+
+    #[test]
+    fn parallel_load_order() {
+        let dir = env::temp_dir().join("cardboard-order");
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("m.json.cache"));
+        fs::write(dir.join("a.json"), STYLE).unwrap();
+        // layers of different sizes, listed out of paint order
+        for (name, count) in [("a", 200), ("b", 3), ("c", 50)].iter() {
+            let features: Vec<String> = (0..*count).map(|i| square(f64::from(i))).collect();
+            fs::write(
+                dir.join(format!("{}.geojson", name)),
+                format!(
+                    r#"{{"type":"FeatureCollection","features":[{}]}}"#,
+                    features.join(",")
+                ),
+            ).unwrap();
+        }
+        fs::write(
+            dir.join("m.json"),
+            r#"{"layers":[
+                {"name":"a","data":"a.geojson","style":"a.json"},
+                {"name":"b","data":"b.geojson","style":"a.json","priority":2},
+                {"name":"c","data":"c.geojson","style":"a.json","priority":1}]}"#,
+        ).unwrap();
+
+        let data = Data::from_file(dir.join("m.json")).unwrap();
+        let order: Vec<(usize, usize, usize)> = data
+            .planes
+            .flattened()
+            .iter()
+            .map(|p| (p.layer_index, p.feature_index, p.style_index))
+            .collect();
+        assert_eq!(order.len(), 253);
+
+        // the same as loading one layer after the other
+        let scene = Scene::from_manifest(&data.manifest).unwrap();
+        let mut sequential = Vec::new();
+        for (index, layer) in data.manifest.visible_layers().iter().enumerate() {
+            let ld = load_layer(layer, &data.styles[index], index, &scene).unwrap();
+            sequential.extend(
+                ld.planes
+                    .flattened()
+                    .iter()
+                    .map(|p| (p.layer_index, p.feature_index, p.style_index)),
+            );
+        }
+        assert_eq!(order, sequential);
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(order, sorted);
+        assert_eq!(data.reports[0].name, "b");
+    }
+
+    #[test]
+    fn report_counts() {
+        let dir = env::temp_dir().join("cardboard-report");
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("m.json.cache"));
+        fs::write(
+            dir.join("m.json"),
+            r#"{"region":{"bbox":[-10,-10,10,10]},
+                "layers":[{"name":"a","data":"a.geojson","style":"a.json",
+                "options":{"filter":["<", "h", 1000]}}]}"#,
+        ).unwrap();
+        fs::write(dir.join("a.json"), STYLE).unwrap();
+        let broken = r#"{"type":"Feature","properties":{"h":1},"geometry":{"type":"Polygon",
+            "coordinates":[[[0],[1,0,0],[1,1,0],[0]]]}}"#;
+        let far = square(10.0).replace(
+            "[0,0,0],[1,0,0],[1,1,0],[0,0,0]",
+            "[50,50,0],[51,50,0],[51,51,0],[50,50,0]",
+        );
+        fs::write(
+            dir.join("a.geojson"),
+            format!(
+                r#"{{"type":"FeatureCollection","features":[{},{},{},{},{}]}}"#,
+                square(10.0),
+                square(500.0),
+                square(2000.0),
+                broken,
+                far
+            ),
+        ).unwrap();
+
+        let data = Data::from_file(dir.join("m.json")).unwrap();
+        let report = &data.reports[0];
+        assert_eq!(report.features_read, 5);
+        assert_eq!(report.features_styled, 1);
+        assert_eq!(report.features_unmatched, 1);
+        assert_eq!(report.features_filtered, 1);
+        assert_eq!(report.features_invalid, 1);
+        assert_eq!(report.features_outside, 1);
+        // an invalid feature leaves the layer usable, and cached
+        assert!(!data.is_broken());
+        assert_eq!(data.features_invalid(), 1);
+        assert!(dir.join("m.json.cache").exists());
+    }
+
+    #[test]
+    fn geojson_seq() {
+        let dir = env::temp_dir().join("cardboard-seq");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("m.json"),
+            r#"{"layers":[{"name":"a","data":"a.geojsonl.gz","style":"a.json",
+                "options":{"filter":["<", "h", 40]}}]}"#,
+        ).unwrap();
+        fs::write(dir.join("a.json"), STYLE).unwrap();
+        let line = |h: f64| square(h).replace('\n', " ");
+        let text = format!(
+            "\x1e{}\n\n{{\"type\":\n{}\r\n{{\"type\":\"FeatureCollection\",\"features\":[{},{}]}}",
+            line(10.0),
+            line(50.0),
+            line(30.0),
+            line(1.0)
+        );
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        fs::write(dir.join("a.geojsonl.gz"), encoder.finish().unwrap()).unwrap();
+
+        let data = Data::from_file(dir.join("m.json")).unwrap();
+        let report = &data.reports[0];
+        // the truncated line is counted and the following ones still read
+        assert!(report.error.is_none());
+        assert_eq!(report.features_read, 5);
+        assert_eq!(report.features_invalid, 1);
+        assert_eq!(report.features_filtered, 1);
+        assert_eq!(data.planes.flattened().len(), 3);
+    }
+
+    #[test]
+    fn lights_follow_visible_layers() {
+        let dir = env::temp_dir().join("cardboard-lights");
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("m.json.cache"));
+        fs::write(dir.join("a.json"), STYLE).unwrap();
+        fs::write(
+            dir.join("a.geojson"),
+            format!(r#"{{"type":"FeatureCollection","features":[{}]}}"#, square(10.0)),
+        ).unwrap();
+        // priorities put "lit" before "flat", and "hidden" is left out
+        fs::write(
+            dir.join("m.json"),
+            r#"{"layers":[
+                {"name":"hidden","data":"a.geojson","style":"a.json","visible":false,
+                    "light":{"azimuth":0}},
+                {"name":"flat","data":"a.geojson","style":"a.json"},
+                {"name":"lit","data":"a.geojson","style":"a.json","priority":3,
+                    "light":{"azimuth":90}}]}"#,
+        ).unwrap();
+
+        let data = Data::from_file(dir.join("m.json")).unwrap();
+        let lights = data.lights();
+        assert_eq!(lights.len(), 2);
+        for plane in data.planes.flattened() {
+            let name = &data.reports[plane.layer_index].name;
+            assert_eq!(lights[plane.layer_index].is_some(), name == "lit");
+        }
     }
 }
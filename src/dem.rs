@@ -0,0 +1,532 @@
+use data::{LayerData, LayerSeed, LoadError};
+use geojson::feature::Id;
+use geojson::{Feature, Geometry, Value};
+use lingua::{PlaneKind, PlaneT, Point};
+use manifest::{DrapeOptions, FootprintBase, LayerConfig};
+use serde_json::{Map, Value as JsonValue};
+use std::fs;
+use std::path::Path;
+
+/// Property carrying the mean elevation of a terrain quad.
+pub const ELEVATION: &str = "elevation";
+/// Property carrying the slope of a terrain quad, in degrees.
+pub const SLOPE: &str = "slope";
+
+/// An elevation raster in scene coordinates, rows running from north to
+/// south.
+#[derive(Debug, Clone)]
+pub struct Dem {
+    /// West edge of the raster.
+    pub x0: f64,
+    /// North edge of the raster.
+    pub y0: f64,
+    pub dx: f64,
+    pub dy: f64,
+    pub ncols: usize,
+    pub nrows: usize,
+    values: Vec<f64>,
+    nodata: Option<f64>,
+}
+
+impl Dem {
+    pub fn from_file(path: &Path) -> Result<Dem, String> {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        let ext = path
+            .extension()
+            .map_or_else(String::new, |e| e.to_string_lossy().to_lowercase());
+        match ext.as_str() {
+            "tif" | "tiff" => read_tiff(&bytes),
+            _ => read_ascii(&String::from_utf8_lossy(&bytes)),
+        }
+    }
+
+    /// Elevation of a cell, `None` for missing data.
+    pub fn value(&self, col: usize, row: usize) -> Option<f64> {
+        if col >= self.ncols || row >= self.nrows {
+            return None;
+        }
+        let v = self.values[row * self.ncols + col];
+        match self.nodata {
+            Some(nodata) if v == nodata => None,
+            _ if v.is_nan() => None,
+            _ => Some(v),
+        }
+    }
+
+    /// Centre of a cell.
+    pub fn position(&self, col: usize, row: usize) -> (f64, f64) {
+        (
+            self.x0 + (col as f64 + 0.5) * self.dx,
+            self.y0 - (row as f64 + 0.5) * self.dy,
+        )
+    }
+
+    /// Bilinear interpolation between cell centres, `None` outside of the
+    /// raster or when the nearest cell has no data.
+    pub fn sample(&self, x: f64, y: f64) -> Option<f64> {
+        let fx = (x - self.x0) / self.dx - 0.5;
+        let fy = (self.y0 - y) / self.dy - 0.5;
+        if fx < -0.5 || fy < -0.5 || fx > self.ncols as f64 - 0.5 || fy > self.nrows as f64 - 0.5 {
+            return None;
+        }
+        let clamp = |v: f64, n: usize| v.max(0.0).min((n - 1) as f64);
+        let (fx, fy) = (clamp(fx, self.ncols), clamp(fy, self.nrows));
+        let (c0, r0) = (fx.floor() as usize, fy.floor() as usize);
+        let (c1, r1) = ((c0 + 1).min(self.ncols - 1), (r0 + 1).min(self.nrows - 1));
+        let (tx, ty) = (fx - c0 as f64, fy - r0 as f64);
+        match (
+            self.value(c0, r0),
+            self.value(c1, r0),
+            self.value(c0, r1),
+            self.value(c1, r1),
+        ) {
+            (Some(a), Some(b), Some(c), Some(d)) => {
+                Some((a * (1.0 - tx) + b * tx) * (1.0 - ty) + (c * (1.0 - tx) + d * tx) * ty)
+            }
+            _ => self.value(fx.round() as usize, fy.round() as usize),
+        }
+    }
+
+    /// Sets the planes of one feature on the ground. Extruded features are
+    /// moved up as a whole, other vertices follow the terrain. Vertices off
+    /// the raster keep their own height, and make it return `false`.
+    pub fn drape(&self, planes: &mut Vec<PlaneT>, options: &DrapeOptions, extruded: bool) -> bool {
+        if extruded {
+            let points = planes.iter().flat_map(|p| p.points.iter());
+            let heights: Vec<f64> = points
+                .clone()
+                .filter_map(|p| self.sample(p.x, p.y))
+                .collect();
+            if heights.is_empty() {
+                return false;
+            }
+            let base = match options.footprint {
+                FootprintBase::Lowest => heights.iter().cloned().fold(::std::f64::MAX, f64::min),
+                FootprintBase::Mean => heights.iter().sum::<f64>() / heights.len() as f64,
+            };
+            let grounded = heights.len() == points.count();
+            for p in planes.iter_mut() {
+                p.map_points(|pt| Point::new(pt.x, pt.y, pt.z + base));
+            }
+            return grounded;
+        }
+        let step = options.densify.unwrap_or_else(|| self.dx.min(self.dy));
+        let mut grounded = true;
+        for p in planes.iter_mut() {
+            if p.kind != PlaneKind::Marker {
+                p.points = densify(&p.points, step);
+                p.holes = p.holes.iter().map(|h| densify(h, step)).collect();
+            }
+            grounded &= p
+                .points
+                .iter()
+                .chain(p.holes.iter().flat_map(|h| h.iter()))
+                .all(|pt| self.sample(pt.x, pt.y).is_some());
+            p.map_points(|pt| {
+                Point::new(pt.x, pt.y, pt.z + self.sample(pt.x, pt.y).unwrap_or(0.0))
+            });
+        }
+        grounded
+    }
+}
+
+/// A quad joining the centres of four cells `step` apart, `None` if one of
+/// them has no data.
+fn terrain_quad(dem: &Dem, col: usize, row: usize, step: usize) -> Option<Feature> {
+    let corners = [
+        (col, row),
+        (col + step, row),
+        (col + step, row + step),
+        (col, row + step),
+    ];
+    let mut ring = Vec::with_capacity(5);
+    let mut z = [0.0; 4];
+    for (i, &(c, r)) in corners.iter().enumerate() {
+        z[i] = dem.value(c, r)?;
+        let (x, y) = dem.position(c, r);
+        ring.push(vec![x, y, z[i]]);
+    }
+    ring.push(ring[0].clone());
+
+    let (w, h) = (step as f64 * dem.dx, step as f64 * dem.dy);
+    let dzdx = (z[1] + z[2] - z[0] - z[3]) / (2.0 * w);
+    let dzdy = (z[0] + z[1] - z[2] - z[3]) / (2.0 * h);
+    let slope = (dzdx * dzdx + dzdy * dzdy).sqrt().atan().to_degrees();
+
+    let mut props = Map::new();
+    props.insert(ELEVATION.to_owned(), JsonValue::from(z.iter().sum::<f64>() / 4.0));
+    props.insert(SLOPE.to_owned(), JsonValue::from(slope));
+    Some(Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(Value::Polygon(vec![ring]))),
+        id: Some(Id::String(format!("{}:{}", col, row))),
+        properties: Some(props),
+        foreign_members: None,
+    })
+}
+
+/// Loads an elevation raster as a grid of quads, one feature each, with
+/// their mean elevation and slope as properties.
+pub fn load(layer: &LayerConfig, seed: LayerSeed) -> Result<LayerData, LoadError> {
+    eprintln!("Loading terrain {} ({})", layer.name, layer.data.display());
+    let dem = Dem::from_file(&layer.data).map_err(|e| LoadError::Dem(layer.data.clone(), e))?;
+    let step = layer.options.step.unwrap_or(1).max(1);
+    let mut data = seed.empty();
+
+    let mut row = 0;
+    while row + step < dem.nrows {
+        let mut col = 0;
+        while col + step < dem.ncols {
+            data.report.features_read += 1;
+            match terrain_quad(&dem, col, row, step) {
+                Some(feature) => seed.push_feature(&feature, &mut data),
+                None => data.report.skip("nodata"),
+            }
+            col += step;
+        }
+        row += step;
+    }
+
+    eprintln!("Loaded {}", layer.name);
+    Ok(data)
+}
+
+/// Adds vertices so that no segment is longer than `step` in plan.
+fn densify(points: &[Point], step: f64) -> Vec<Point> {
+    if step <= 0.0 {
+        return points.to_vec();
+    }
+    let mut result = Vec::with_capacity(points.len());
+    for pair in points.windows(2) {
+        let (p, q) = (pair[0], pair[1]);
+        let length = ((q.x - p.x).powi(2) + (q.y - p.y).powi(2)).sqrt();
+        let n = (length / step).ceil().max(1.0) as usize;
+        for i in 0..n {
+            result.push(p + (q - p) * (i as f64 / n as f64));
+        }
+    }
+    result.extend(points.last());
+    result
+}
+
+fn read_ascii(content: &str) -> Result<Dem, String> {
+    let mut tokens = content.split_whitespace().peekable();
+    let mut header = Vec::new();
+    while let Some(token) = tokens.peek().cloned() {
+        if token.parse::<f64>().is_ok() {
+            break;
+        }
+        tokens.next();
+        let value = tokens
+            .next()
+            .and_then(|v| v.parse::<f64>().ok())
+            .ok_or_else(|| format!("bad value for {}", token))?;
+        header.push((token.to_lowercase(), value));
+    }
+    let get = |key: &str| header.iter().find(|h| h.0 == key).map(|h| h.1);
+    let ncols = get("ncols").ok_or("missing ncols")? as usize;
+    let nrows = get("nrows").ok_or("missing nrows")? as usize;
+    let (dx, dy) = match (get("cellsize"), get("dx"), get("dy")) {
+        (Some(c), _, _) => (c, c),
+        (None, Some(dx), Some(dy)) => (dx, dy),
+        _ => return Err("missing cellsize".to_owned()),
+    };
+    let x0 = match (get("xllcorner"), get("xllcenter")) {
+        (Some(x), _) => x,
+        (None, Some(x)) => x - dx / 2.0,
+        _ => return Err("missing xllcorner".to_owned()),
+    };
+    let yll = match (get("yllcorner"), get("yllcenter")) {
+        (Some(y), _) => y,
+        (None, Some(y)) => y - dy / 2.0,
+        _ => return Err("missing yllcorner".to_owned()),
+    };
+    let values = tokens
+        .map(|t| t.parse::<f64>().map_err(|_| format!("bad elevation {}", t)))
+        .collect::<Result<Vec<f64>, String>>()?;
+    if values.len() != ncols * nrows {
+        return Err(format!(
+            "expected {} elevations, got {}",
+            ncols * nrows,
+            values.len()
+        ));
+    }
+    Ok(Dem {
+        x0,
+        y0: yll + nrows as f64 * dy,
+        dx,
+        dy,
+        ncols,
+        nrows,
+        values,
+        nodata: get("nodata_value"),
+    })
+}
+
+struct Tiff<'a> {
+    bytes: &'a [u8],
+    little: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn slice(&self, offset: usize, n: usize) -> Result<&'a [u8], String> {
+        self.bytes
+            .get(offset..offset + n)
+            .ok_or_else(|| "truncated TIFF".to_owned())
+    }
+
+    fn uint(&self, offset: usize, n: usize) -> Result<u64, String> {
+        let b = self.slice(offset, n)?;
+        let mut v = 0u64;
+        for i in 0..n {
+            let byte = if self.little { b[n - 1 - i] } else { b[i] };
+            v = (v << 8) | u64::from(byte);
+        }
+        Ok(v)
+    }
+
+    /// Values of a sample at `offset`, of the given TIFF sample format and
+    /// size in bytes.
+    fn sample(&self, offset: usize, format: u64, size: usize) -> Result<f64, String> {
+        let v = self.uint(offset, size)?;
+        Ok(match (format, size) {
+            (3, 4) => f64::from(f32::from_bits(v as u32)),
+            (3, 8) => f64::from_bits(v),
+            (2, 1) => f64::from(v as u8 as i8),
+            (2, 2) => f64::from(v as u16 as i16),
+            (2, 4) => f64::from(v as u32 as i32),
+            (1, _) => v as f64,
+            _ => return Err(format!("unsupported sample format {} on {} bytes", format, size)),
+        })
+    }
+
+    /// The values of a directory entry as numbers.
+    fn entry(&self, offset: usize) -> Result<(u16, Vec<f64>, String), String> {
+        let tag = self.uint(offset, 2)? as u16;
+        let kind = self.uint(offset + 2, 2)?;
+        let count = self.uint(offset + 4, 4)? as usize;
+        let (size, format) = match kind {
+            1 | 2 | 7 => (1, 1),
+            3 => (2, 1),
+            4 => (4, 1),
+            6 => (1, 2),
+            8 => (2, 2),
+            9 => (4, 2),
+            11 => (4, 3),
+            12 => (8, 3),
+            _ => return Ok((tag, Vec::new(), String::new())),
+        };
+        let data = if size * count <= 4 {
+            offset + 8
+        } else {
+            self.uint(offset + 8, 4)? as usize
+        };
+        if kind == 2 {
+            let text = String::from_utf8_lossy(self.slice(data, count)?);
+            return Ok((tag, Vec::new(), text.trim_end_matches('\0').to_owned()));
+        }
+        let values = (0..count)
+            .map(|i| self.sample(data + i * size, format, size))
+            .collect::<Result<Vec<f64>, String>>()?;
+        Ok((tag, values, String::new()))
+    }
+}
+
+/// Baseline uncompressed GeoTIFF, first band of a stripped or tiled image
+/// placed by a tie point and a pixel scale.
+fn read_tiff(bytes: &[u8]) -> Result<Dem, String> {
+    let little = match bytes.get(0..4) {
+        Some(b"II*\0") => true,
+        Some(b"MM\0*") => false,
+        _ => return Err("not a TIFF file".to_owned()),
+    };
+    let tiff = Tiff { bytes, little };
+    let ifd = tiff.uint(4, 4)? as usize;
+    let n = tiff.uint(ifd, 2)? as usize;
+    let mut tags = Vec::with_capacity(n);
+    for i in 0..n {
+        tags.push(tiff.entry(ifd + 2 + i * 12)?);
+    }
+    let values = |tag: u16| tags.iter().find(|t| t.0 == tag).map(|t| &t.1);
+    let first = |tag: u16| values(tag).and_then(|v| v.get(0).cloned());
+
+    let width = first(256).ok_or("missing image width")? as usize;
+    let height = first(257).ok_or("missing image length")? as usize;
+    let size = first(258).unwrap_or(8.0) as usize / 8;
+    let spp = first(277).unwrap_or(1.0) as usize;
+    let format = first(339).unwrap_or(1.0) as u64;
+    if first(259).unwrap_or(1.0) != 1.0 {
+        return Err("compressed TIFF files are not supported".to_owned());
+    }
+    if spp > 1 && first(284).unwrap_or(1.0) != 1.0 {
+        return Err("planar TIFF files are not supported".to_owned());
+    }
+    let scale = values(33550).ok_or("missing ModelPixelScale")?;
+    let tie = values(33922).ok_or("missing ModelTiepoint")?;
+    if scale.len() < 2 || tie.len() < 6 {
+        return Err("bad georeferencing".to_owned());
+    }
+
+    // (offset of the pixel's block, row and column in the block, block width)
+    let locate: Box<dyn Fn(usize, usize) -> Option<(usize, usize, usize, usize)>> =
+        match (first(322), first(323), values(324), values(273)) {
+            (Some(tw), Some(th), Some(offsets), _) => {
+                let (tw, th) = (tw as usize, th as usize);
+                let across = (width + tw - 1) / tw;
+                Box::new(move |row, col| {
+                    offsets
+                        .get((row / th) * across + col / tw)
+                        .map(|o| (*o as usize, row % th, col % tw, tw))
+                })
+            }
+            (_, _, _, Some(offsets)) => {
+                let rps = first(278).map_or(height, |r| r as usize);
+                Box::new(move |row, col| {
+                    offsets
+                        .get(row / rps)
+                        .map(|o| (*o as usize, row % rps, col, width))
+                })
+            }
+            _ => return Err("missing strip or tile offsets".to_owned()),
+        };
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in 0..height {
+        for col in 0..width {
+            let (block, r, c, w) = locate(row, col).ok_or("missing image block")?;
+            let offset = block + ((r * w + c) * spp) * size;
+            pixels.push(tiff.sample(offset, format, size)?);
+        }
+    }
+
+    let nodata = tags
+        .iter()
+        .find(|t| t.0 == 42113)
+        .and_then(|t| t.2.trim().parse::<f64>().ok());
+    Ok(Dem {
+        x0: tie[3] - tie[0] * scale[0],
+        y0: tie[4] + tie[1] * scale[1],
+        dx: scale[0],
+        dy: scale[1],
+        ncols: width,
+        nrows: height,
+        values: pixels,
+        nodata,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use dem::{read_ascii, read_tiff, terrain_quad};
+    use lingua::{PlaneT, Point};
+    use manifest::DrapeOptions;
+
+    #[test]
+    fn ascii_grid() {
+        let dem = read_ascii(
+            "ncols 3\nnrows 2\nxllcorner 100\nyllcorner 200\ncellsize 10\nNODATA_value -9999\n\
+             1 2 3\n4 5 -9999\n",
+        ).unwrap();
+        assert_eq!(dem.sample(105.0, 215.0), Some(1.0));
+        assert_eq!(dem.sample(110.0, 210.0), Some(3.0));
+        // next to a missing cell the nearest one is used, here the missing one
+        assert_eq!(dem.sample(124.0, 204.0), None);
+        assert_eq!(dem.sample(99.0, 205.0), None);
+    }
+
+    #[test]
+    fn drape_off_raster() {
+        let dem = read_ascii(
+            "ncols 2\nnrows 1\nxllcorner 0\nyllcorner 0\ncellsize 10\nNODATA_value -9999\n\
+             100 100\n",
+        ).unwrap();
+        let triangle = |far: f64| {
+            vec![PlaneT::polygon(
+                0,
+                0,
+                vec![
+                    Point::new(5.0, 5.0, 1.0),
+                    Point::new(15.0, 5.0, 1.0),
+                    Point::new(15.0, far, 1.0),
+                    Point::new(5.0, 5.0, 1.0),
+                ],
+                Vec::new(),
+            )]
+        };
+        let options = DrapeOptions {
+            densify: Some(100.0),
+            ..DrapeOptions::default()
+        };
+
+        let mut inside = triangle(8.0);
+        assert!(dem.drape(&mut inside, &options, false));
+        assert!(inside[0].points.iter().all(|p| p.z == 101.0));
+
+        // the vertex off the raster stays where it was
+        let mut across = triangle(50.0);
+        assert!(!dem.drape(&mut across, &options, false));
+        let z: Vec<f64> = across[0].points.iter().map(|p| p.z).collect();
+        assert_eq!(z, vec![101.0, 101.0, 1.0, 101.0]);
+        let mut extruded = triangle(50.0);
+        assert!(!dem.drape(&mut extruded, &options, true));
+        assert!(extruded[0].points.iter().all(|p| p.z == 101.0));
+    }
+
+    #[test]
+    fn terrain_quads() {
+        let dem = read_ascii(
+            "ncols 3\nnrows 3\nxllcorner 0\nyllcorner 0\ncellsize 10\nNODATA_value -9999\n\
+             10 20 30\n10 20 30\n10 20 -9999\n",
+        ).unwrap();
+        let quad = terrain_quad(&dem, 0, 0, 1).unwrap();
+        let props = quad.properties.unwrap();
+        assert_eq!(props["elevation"], 15.0);
+        assert!((props["slope"].as_f64().unwrap() - 45.0).abs() < 1e-9);
+        assert!(terrain_quad(&dem, 1, 1, 1).is_none());
+        assert!(terrain_quad(&dem, 0, 0, 2).is_none());
+    }
+
+    #[test]
+    fn geotiff_strips() {
+        fn entry(tag: u16, kind: u16, count: u32, value: u32) -> Vec<u8> {
+            let mut e = Vec::new();
+            e.extend_from_slice(&tag.to_le_bytes());
+            e.extend_from_slice(&kind.to_le_bytes());
+            e.extend_from_slice(&count.to_le_bytes());
+            e.extend_from_slice(&value.to_le_bytes());
+            e
+        }
+        let entries: Vec<Vec<u8>> = vec![
+            entry(256, 3, 1, 2),
+            entry(257, 3, 1, 2),
+            entry(258, 3, 1, 32),
+            entry(273, 4, 1, 200),
+            entry(339, 3, 1, 3),
+            entry(33550, 12, 3, 100),
+            entry(33922, 12, 6, 124),
+        ];
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for e in entries.iter() {
+            tiff.extend_from_slice(e);
+        }
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.resize(100, 0);
+        for v in &[2.0f64, 2.0, 0.0] {
+            tiff.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+        for v in &[0.0f64, 0.0, 0.0, 1000.0, 2000.0, 0.0] {
+            tiff.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+        tiff.resize(200, 0);
+        for v in &[10.0f32, 20.0, 30.0, 40.0] {
+            tiff.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+
+        let dem = read_tiff(&tiff).unwrap();
+        assert_eq!((dem.x0, dem.y0, dem.ncols), (1000.0, 2000.0, 2));
+        assert_eq!(dem.sample(1003.0, 1997.0), Some(40.0));
+        assert_eq!(dem.sample(1002.0, 1998.0), Some(25.0));
+    }
+}
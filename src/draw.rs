@@ -1,7 +1,12 @@
 use camera::Camera;
 use geom::transform2d;
+use light::Light;
 use lingua::PlaneFlat;
 use lingua::Point;
+use lingua::{PlaneKind, Point2D};
+use shadow::{self, Shadows};
+use style::Color;
+use sun::Sun;
 use nalgebra as na;
 use nalgebra::distance_squared;
 use operation::{OpList, Operation};
@@ -10,7 +15,10 @@ use rayon::prelude::*;
 use std::cmp;
 use time::PreciseTime;
 
-struct Dist(OrderedFloat<f64>, usize);
+/// Farthest first, then ground first, then lowest priority first. Terrain
+/// sorts by depth like anything else, so that a hill hides what is behind
+/// it, ground only going first among planes as far from the eye.
+struct Dist(OrderedFloat<f64>, bool, usize);
 
 // fn sort_planes(p: Point, pl: &PlaneFlat) -> Vec<usize> {
 //     let mut indices: Vec<usize> = Vec::with_capacity(pl.len());
@@ -58,6 +66,21 @@ struct Dist(OrderedFloat<f64>, usize);
 // pub type PlanePainter = Fn(usize) -> Vec<Operation>;
 // pub type FlattenedOps = Flatten<Map<std::slice::Iter<'_, usize>>>;
 
+fn push_path<F>(points: &[Point], project: &F, ops: &mut Vec<Operation>)
+where
+    F: Fn(&Point) -> Point2D,
+{
+    let mut started = false;
+    points.iter().map(project).for_each(|translated| {
+        if started {
+            ops.push(Operation::Line(translated));
+        } else {
+            started = true;
+            ops.push(Operation::Move(translated));
+        }
+    });
+}
+
 fn draw_index(
     index: usize,
     pl: &PlaneFlat,
@@ -67,29 +90,58 @@ fn draw_index(
     clip_z: f64,
     scale: f64,
     tr: &na::Matrix3<f64>,
+    lights: &[Option<Light>],
+    towards_eye: &na::Vector3<f64>,
+    shadows: Option<(&Shadows, Color)>,
 ) -> Vec<Operation> {
     let mut ops: Vec<Operation> = Vec::new();
-    let mut started = false;
     let plane = &pl[index];
-    let als = plane
-        .points
-        .iter()
-        .map(|pt| view_projection.transform_point(pt));
     let is_in_front = plane.points.iter().any(|p| view.transform_point(p).z < 0.0);
 
     if is_in_front {
-        ops.push(Operation::Begin);
-        als.for_each(|aligned_point3d| {
-            let translated = transform2d(&aligned_point3d, &corrective, scale, &tr);
-            if started {
-                ops.push(Operation::Line(translated.to_owned()));
-            } else {
-                started = true;
-                ops.push(Operation::Move(translated.to_owned()));
+        let project = |pt: &Point| {
+            let aligned_point3d = view_projection.transform_point(pt);
+            transform2d(&aligned_point3d, &corrective, scale, &tr)
+        };
+        match plane.kind {
+            PlaneKind::Marker => plane.points.iter().for_each(|pt| {
+                ops.push(Operation::Marker(
+                    project(pt),
+                    plane.layer_index,
+                    plane.style_index,
+                ))
+            }),
+            PlaneKind::Line => {
+                ops.push(Operation::Begin);
+                push_path(&plane.points, &project, &mut ops);
+                ops.push(Operation::Stroke(plane.layer_index, plane.style_index));
             }
-        });
-        ops.push(Operation::Close);
-        ops.push(Operation::Paint(plane.layer_index, plane.style_index));
+            PlaneKind::Polygon | PlaneKind::Ground => {
+                ops.push(Operation::Begin);
+                for ring in Some(&plane.points).into_iter().chain(plane.holes.iter()) {
+                    push_path(ring, &project, &mut ops);
+                    ops.push(Operation::Close);
+                }
+                let shade = lights
+                    .get(plane.layer_index)
+                    .and_then(|l| l.as_ref())
+                    .map_or(1.0, |light| light.shade(&plane.points, towards_eye));
+                ops.push(Operation::Paint(plane.layer_index, plane.style_index, shade));
+                if let Some((outlines, color)) = shadows.filter(|s| !s.0.is_empty()) {
+                    ops.push(Operation::Begin);
+                    for ring in Some(&plane.points).into_iter().chain(plane.holes.iter()) {
+                        push_path(ring, &project, &mut ops);
+                        ops.push(Operation::Close);
+                    }
+                    ops.push(Operation::Clip);
+                    for ring in outlines {
+                        push_path(ring, &project, &mut ops);
+                        ops.push(Operation::Close);
+                    }
+                    ops.push(Operation::Shadow(color));
+                }
+            }
+        }
     };
     ops
 }
@@ -102,6 +154,12 @@ pub struct DrawConfig {
     clip_z: f64,
     scale: f64,
     tr: na::Matrix3<f64>,
+    /// Indexed by layer.
+    lights: Vec<Option<Light>>,
+    towards_eye: na::Vector3<f64>,
+    /// Indexed like the planes, empty without a sun.
+    shadows: Vec<Shadows>,
+    shadow_color: Color,
 }
 
 pub trait Drawable {
@@ -128,7 +186,7 @@ impl<'a> Drawable for PlaneFlat<'a> {
                 let d = plane.points.iter().fold(OrderedFloat(0.0), |acc, v| {
                     cmp::max(OrderedFloat(distance_squared(&p, v)), acc)
                 });
-                Dist(d, plane.layer_index)
+                Dist(d, plane.kind == PlaneKind::Ground, plane.layer_index)
             }).collect();
 
         println!("Distances in {}", start.to(PreciseTime::now()));
@@ -141,10 +199,12 @@ impl<'a> Drawable for PlaneFlat<'a> {
                 cmp::Ordering::Greater
             } else if da.0 > db.0 {
                 cmp::Ordering::Less
+            } else if da.1 != db.1 {
+                db.1.cmp(&da.1)
             } else {
-                if da.1 < db.1 {
+                if da.2 < db.2 {
                     cmp::Ordering::Greater
-                } else if da.1 > db.1 {
+                } else if da.2 > db.2 {
                     cmp::Ordering::Less
                 } else {
                     cmp::Ordering::Equal
@@ -173,6 +233,12 @@ impl<'a> Drawable for PlaneFlat<'a> {
                     config.clip_z,
                     config.scale,
                     &config.tr,
+                    &config.lights,
+                    &config.towards_eye,
+                    config
+                        .shadows
+                        .get(index.to_owned())
+                        .map(|s| (s, config.shadow_color)),
                 )
             }).flatten()
             .for_each(f);
@@ -180,7 +246,13 @@ impl<'a> Drawable for PlaneFlat<'a> {
     }
 }
 
-pub fn get_draw_config(pl: &PlaneFlat, cam: &Camera, width: f64) -> DrawConfig {
+pub fn get_draw_config(
+    pl: &PlaneFlat,
+    cam: &Camera,
+    width: f64,
+    lights: Vec<Option<Light>>,
+    sun: Option<&Sun>,
+) -> DrawConfig {
     let dist = na::distance(&cam.eye, &cam.target).abs();
     let scale = dist / 2.0;
 
@@ -220,6 +292,14 @@ pub fn get_draw_config(pl: &PlaneFlat, cam: &Camera, width: f64) -> DrawConfig {
 
     let clip_z = view.transform_point(&cam.eye).z;
 
+    let towards_eye = cam.eye - cam.target;
+    let shadows = sun.map_or_else(Vec::new, |sun| {
+        let start = PreciseTime::now();
+        let shadows = shadow::cast(pl, sun, &towards_eye);
+        println!("Shadows in {}", start.to(PreciseTime::now()));
+        shadows
+    });
+
     DrawConfig {
         indices: pl.sorted_indices(cam.eye),
         view,
@@ -228,5 +308,170 @@ pub fn get_draw_config(pl: &PlaneFlat, cam: &Camera, width: f64) -> DrawConfig {
         clip_z,
         scale: translation,
         tr,
+        lights,
+        towards_eye,
+        shadows,
+        shadow_color: sun.map_or_else(Color::new, |sun| sun.shadow_color),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cairo::{Context, Format, ImageSurface};
+    use camera::Camera;
+    use draw::{get_draw_config, Drawable};
+    use geojson::Feature;
+    use lingua::{plane_from_feature, PlaneFlat, PlaneKind, PlaneT, Point};
+    use manifest::LayerOptions;
+    use operation::{paint_op, Operation};
+    use serde_json;
+    use style::{PolygonStyleConfig, StyleList};
+
+    fn quad(points: [[f64; 3]; 4], kind: PlaneKind) -> PlaneT {
+        let mut plane = PlaneT::polygon(
+            0,
+            0,
+            points.iter().map(|p| Point::new(p[0], p[1], p[2])).collect(),
+            Vec::new(),
+        );
+        plane.kind = kind;
+        plane
+    }
+
+    #[test]
+    fn terrain_occludes() {
+        // looking north from the south, a hill slope stands in front of a
+        // wall, and flat ground stretches behind it
+        let eye = Point::new(5.0, -50.0, 10.0);
+        let wall = quad(
+            [[0.0, 20.0, 0.0], [10.0, 20.0, 0.0], [10.0, 20.0, 5.0], [0.0, 20.0, 5.0]],
+            PlaneKind::Polygon,
+        );
+        let hill = quad(
+            [[0.0, 0.0, 0.0], [10.0, 0.0, 0.0], [10.0, 5.0, 8.0], [0.0, 5.0, 8.0]],
+            PlaneKind::Ground,
+        );
+        let behind = quad(
+            [[0.0, 20.0, 0.0], [10.0, 20.0, 0.0], [10.0, 40.0, 0.0], [0.0, 40.0, 0.0]],
+            PlaneKind::Ground,
+        );
+        let planes: PlaneFlat = vec![&hill, &wall, &behind];
+        assert_eq!(planes.sorted_indices(eye), vec![2, 1, 0]);
+    }
+
+    fn feature(geometry: &str) -> Feature {
+        serde_json::from_str(&format!(
+            r#"{{"type":"Feature","properties":{{}},"geometry":{}}}"#,
+            geometry
+        )).unwrap()
+    }
+
+    /// Draws planes seen from the south, on a 100 pixels wide image.
+    fn draw(planes: &PlaneFlat) -> Vec<Operation> {
+        let camera = Camera::new(Point::new(5.0, -30.0, 40.0), Point::new(5.0, 5.0, 0.0));
+        let config = get_draw_config(planes, &camera, 100.0, Vec::new(), None);
+        let mut ops = Vec::new();
+        planes.draw(&config, |op| ops.push(op));
+        ops
+    }
+
+    /// One letter per operation.
+    fn letters(ops: &[Operation]) -> String {
+        ops.iter()
+            .map(|op| match op {
+                Operation::Move(_) => 'M',
+                Operation::Line(_) => 'L',
+                Operation::Begin => 'B',
+                Operation::Close => 'C',
+                Operation::Paint(..) => 'P',
+                Operation::Stroke(..) => 'S',
+                Operation::Marker(..) => 'K',
+                Operation::Clip => 'X',
+                Operation::Shadow(_) => 'H',
+            }).collect()
+    }
+
+    #[test]
+    fn holes_are_sub_paths() {
+        let f = feature(
+            r#"{"type":"Polygon","coordinates":[
+                [[0,0],[10,0],[10,10],[0,10],[0,0]],
+                [[3,3],[3,7],[7,7],[7,3],[3,3]]]}"#,
+        );
+        let plane = plane_from_feature(&f, 0, 0, 0, &LayerOptions::default()).unwrap();
+        assert_eq!(plane.len(), 1);
+        assert_eq!(plane[0].holes.len(), 1);
+        assert_eq!(plane[0].holes[0][1], Point::new(3.0, 7.0, 0.0));
+
+        // the hole closes its own sub-path, both filled by one paint
+        let ops = draw(&vec![&plane[0]]);
+        assert_eq!(letters(&ops), "BMLLLLCMLLLLCP");
+
+        // filled even-odd, the hole is left empty
+        let config: PolygonStyleConfig = serde_json::from_str(
+            r#"{"kind":"simple","strokeColor":"transparent","fillColor":"red","strokeWidth":0}"#,
+        ).unwrap();
+        let styles = vec![StyleList::from_config(&config).unwrap()];
+        let mut surface = ImageSurface::create(Format::ARgb32, 100, 100).unwrap();
+        {
+            let context = Context::new(&surface);
+            ops.iter().for_each(|op| paint_op(op, &styles, &context));
+        }
+        let points: Vec<(f64, f64)> = ops
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Move(p) | Operation::Line(p) => Some((p.x, p.y)),
+                _ => None,
+            }).collect();
+        let centre = |ring: &[(f64, f64)]| {
+            let n = ring.len() as f64;
+            let (x, y) = ring.iter().fold((0.0, 0.0), |(x, y), p| (x + p.0, y + p.1));
+            (x / n, y / n)
+        };
+        let hole = centre(&points[5..9]);
+        let ring = (points[0].0 * 0.8 + hole.0 * 0.2, points[0].1 * 0.8 + hole.1 * 0.2);
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().unwrap();
+        let alpha = |(x, y): (f64, f64)| data[y as usize * stride + x as usize * 4 + 3];
+        assert_eq!(alpha(hole), 0);
+        assert_eq!(alpha(ring), 255);
+    }
+
+    #[test]
+    fn lines_and_markers() {
+        let options = LayerOptions::default();
+        let line = feature(r#"{"type":"LineString","coordinates":[[0,0],[5,5,2],[10,0]]}"#);
+        let line = plane_from_feature(&line, 1, 2, 3, &options).unwrap();
+        assert_eq!(line.len(), 1);
+        assert_eq!(line[0].kind, PlaneKind::Line);
+        assert_eq!(line[0].points[1], Point::new(5.0, 5.0, 2.0));
+        assert_eq!((line[0].layer_index, line[0].style_index, line[0].feature_index), (1, 2, 3));
+
+        let points = feature(r#"{"type":"MultiPoint","coordinates":[[1,1],[2,2,4]]}"#);
+        let markers = plane_from_feature(&points, 1, 4, 5, &options).unwrap();
+        assert_eq!(markers.len(), 2);
+        assert!(markers.iter().all(|m| m.kind == PlaneKind::Marker && m.points.len() == 1));
+        assert_eq!(markers[1].points[0], Point::new(2.0, 2.0, 4.0));
+        assert!(plane_from_feature(
+            &feature(r#"{"type":"LineString","coordinates":[[0,0]]}"#),
+            0,
+            0,
+            0,
+            &options
+        ).is_err());
+
+        // a line is stroked open, a marker drawn on its own
+        let ops = draw(&vec![&line[0]]);
+        assert_eq!(letters(&ops), "BMLLS");
+        match ops[4] {
+            Operation::Stroke(1, 2) => (),
+            _ => panic!("expected a stroke with the line's style"),
+        }
+        let ops = draw(&vec![&markers[0], &markers[1]]);
+        assert_eq!(letters(&ops), "KK");
+        assert!(ops.iter().all(|op| match op {
+            Operation::Marker(_, 1, 4) => true,
+            _ => false,
+        }));
     }
 }
@@ -0,0 +1,153 @@
+use lingua::{number_property, Plane, PlaneT, Point, Properties};
+use manifest::ExtrudeOptions;
+
+/// Vertical extent of a block, relative to the ground under its footprint.
+#[derive(Copy, Clone, Debug)]
+pub struct Extrusion {
+    pub base: f64,
+    pub height: f64,
+}
+
+impl Extrusion {
+    pub fn from_properties(options: &ExtrudeOptions, props: &Properties) -> Extrusion {
+        let height = options
+            .height
+            .as_ref()
+            .and_then(|name| number_property(props, name))
+            .unwrap_or(options.default_height);
+        let base = options
+            .base
+            .as_ref()
+            .and_then(|name| number_property(props, name))
+            .unwrap_or(0.0);
+        Extrusion { base, height }
+    }
+}
+
+fn lift(pt: &Point, dz: f64) -> Point {
+    Point::new(pt.x, pt.y, pt.z + dz)
+}
+
+/// Builds a wall for every edge of every ring, and a roof carrying the
+/// interior rings. The first ring is the exterior one.
+pub fn extrude_rings(
+    rings: &[Vec<Point>],
+    extrusion: &Extrusion,
+    layer_index: usize,
+    style_index: usize,
+) -> Plane {
+    let mut plane: Plane = Vec::new();
+    let lift_ring = |ring: &Vec<Point>, dz: f64| -> Vec<Point> {
+        ring.iter().map(|pt| lift(pt, dz)).collect()
+    };
+
+    if extrusion.height > extrusion.base {
+        for ring in rings {
+            for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+                if a.x == b.x && a.y == b.y {
+                    continue;
+                }
+                plane.push(PlaneT::polygon(
+                    layer_index,
+                    style_index,
+                    vec![
+                        lift(a, extrusion.base),
+                        lift(b, extrusion.base),
+                        lift(b, extrusion.height),
+                        lift(a, extrusion.height),
+                    ],
+                    Vec::new(),
+                ));
+            }
+        }
+    }
+
+    if let Some((exterior, interiors)) = rings.split_first() {
+        let top = extrusion.height.max(extrusion.base);
+        plane.push(PlaneT::polygon(
+            layer_index,
+            style_index,
+            lift_ring(exterior, top),
+            interiors.iter().map(|r| lift_ring(r, top)).collect(),
+        ));
+    }
+
+    plane
+}
+
+#[cfg(test)]
+mod tests {
+    use extrude::{extrude_rings, Extrusion};
+    use lingua::{Point, Properties};
+    use manifest::ExtrudeOptions;
+    use serde_json;
+
+    fn ring(points: &[(f64, f64)], z: f64) -> Vec<Point> {
+        points.iter().map(|&(x, y)| Point::new(x, y, z)).collect()
+    }
+
+    fn props(json: &str) -> Properties {
+        Some(serde_json::from_str(json).unwrap())
+    }
+
+    fn options(base: Option<&str>) -> ExtrudeOptions {
+        ExtrudeOptions {
+            height: Some("h".to_owned()),
+            base: base.map(|b| b.to_owned()),
+            default_height: 3.0,
+        }
+    }
+
+    #[test]
+    fn walls_and_roof() {
+        let rings = vec![
+            ring(&[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0), (0.0, 0.0)], 2.0),
+            ring(&[(1.0, 1.0), (2.0, 1.0), (1.0, 2.0), (1.0, 1.0)], 2.0),
+        ];
+        let extrusion = Extrusion::from_properties(&options(None), &props(r#"{"h": 10}"#));
+        let plane = extrude_rings(&rings, &extrusion, 1, 5);
+
+        // four walls for the exterior ring, three for the hole, then the roof
+        assert_eq!(plane.len(), 8);
+        assert!(plane.iter().all(|p| p.layer_index == 1 && p.style_index == 5));
+        for wall in &plane[..7] {
+            let zs: Vec<f64> = wall.points.iter().map(|p| p.z).collect();
+            assert_eq!(zs, vec![2.0, 2.0, 12.0, 12.0]);
+            assert!(wall.holes.is_empty());
+        }
+        let roof = &plane[7];
+        assert_eq!(roof.points.len(), 5);
+        assert!(roof.points.iter().all(|p| p.z == 12.0));
+        assert_eq!(roof.holes.len(), 1);
+        assert!(roof.holes[0].iter().all(|p| p.z == 12.0));
+    }
+
+    #[test]
+    fn base_and_default_height() {
+        let rings = vec![ring(&[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, 0.0)], 0.0)];
+        let heights = |o: &ExtrudeOptions, json: &str| -> Vec<(f64, f64)> {
+            let extrusion = Extrusion::from_properties(o, &props(json));
+            extrude_rings(&rings, &extrusion, 0, 0)
+                .iter()
+                .map(|p| {
+                    let zs = p.points.iter().map(|pt| pt.z);
+                    (
+                        zs.clone().fold(::std::f64::MAX, f64::min),
+                        zs.fold(::std::f64::MIN, f64::max),
+                    )
+                }).collect()
+        };
+
+        // three walls then the flat roof
+        let block = |base: f64, top: f64| vec![(base, top), (base, top), (base, top), (top, top)];
+
+        // a base lifts the bottom of the walls, numeric strings count
+        let with_base = options(Some("b"));
+        assert_eq!(heights(&with_base, r#"{"h": "9", "b": 4}"#), block(4.0, 9.0));
+        // missing or non-numeric heights fall back to the default
+        assert_eq!(heights(&with_base, r#"{"b": 1}"#), block(1.0, 3.0));
+        assert_eq!(heights(&options(None), r#"{"h": "tall"}"#), block(0.0, 3.0));
+        // a base above the top leaves the roof alone
+        assert_eq!(heights(&with_base, r#"{"h": 2, "b": 5}"#), vec![(5.0, 5.0)]);
+    }
+}
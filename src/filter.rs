@@ -0,0 +1,202 @@
+use serde::de::{self, Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+
+/// A predicate over a feature, written in manifests as an array whose first
+/// element names the operator:
+///
+/// - `["==", "prop", value]`, `["!=", ...]`, `["<", ...]`, `["<=", ...]`,
+///   `[">", ...]`, `[">=", ...]`
+/// - `["in", "prop", [value, ...]]`
+/// - `["has", "prop"]`
+/// - `["geometry", "Polygon", "MultiPolygon", ...]`
+/// - `["and", filter, ...]`, `["or", filter, ...]`, `["not", filter]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Compare(Comparison, String, Value),
+    In(String, Vec<Value>),
+    Has(String),
+    Geometry(Vec<String>),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+fn as_number(v: &Value) -> Option<f64> {
+    match v {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+/// Numbers compare as numbers, numeric strings included, strings and
+/// booleans compare with their own kind, anything else does not compare.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => match (as_number(a), as_number(b)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => None,
+        },
+    }
+}
+
+fn parse_list(items: &[Value]) -> Result<Vec<Filter>, String> {
+    items.iter().map(Filter::parse).collect()
+}
+
+fn property_name(args: &[Value], op: &str) -> Result<String, String> {
+    args.get(0)
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_owned())
+        .ok_or_else(|| format!("\"{}\" expects a property name", op))
+}
+
+impl Filter {
+    pub fn parse(value: &Value) -> Result<Filter, String> {
+        let items = value
+            .as_array()
+            .ok_or_else(|| format!("expected an array, got {}", value))?;
+        let op = items
+            .get(0)
+            .and_then(|v| v.as_str())
+            .ok_or("expected an operator as first element")?;
+        let args = &items[1..];
+        let comparison = match op {
+            "==" => Some(Comparison::Eq),
+            "!=" => Some(Comparison::Ne),
+            "<" => Some(Comparison::Lt),
+            "<=" => Some(Comparison::Le),
+            ">" => Some(Comparison::Gt),
+            ">=" => Some(Comparison::Ge),
+            _ => None,
+        };
+        if let Some(c) = comparison {
+            if args.len() != 2 {
+                return Err(format!("\"{}\" expects a property name and a value", op));
+            }
+            return Ok(Filter::Compare(c, property_name(args, op)?, args[1].clone()));
+        }
+        match op {
+            "in" => match args.get(1) {
+                Some(Value::Array(values)) if args.len() == 2 => {
+                    Ok(Filter::In(property_name(args, op)?, values.clone()))
+                }
+                _ => Err("\"in\" expects a property name and an array of values".to_owned()),
+            },
+            "has" if args.len() == 1 => Ok(Filter::Has(property_name(args, op)?)),
+            "geometry" => args
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(|s| s.to_owned())
+                        .ok_or_else(|| "\"geometry\" expects geometry type names".to_owned())
+                }).collect::<Result<Vec<String>, String>>()
+                .map(Filter::Geometry),
+            "and" => parse_list(args).map(Filter::And),
+            "or" => parse_list(args).map(Filter::Or),
+            "not" if args.len() == 1 => Filter::parse(&args[0]).map(|f| Filter::Not(Box::new(f))),
+            "has" | "not" => Err(format!("\"{}\" expects a single argument", op)),
+            _ => Err(format!("unknown filter operator \"{}\"", op)),
+        }
+    }
+
+    /// Missing properties only satisfy `!=` and negations.
+    pub fn matches(&self, properties: Option<&Map<String, Value>>, geometry: Option<&str>) -> bool {
+        let get = |name: &str| properties.and_then(|p| p.get(name));
+        match self {
+            Filter::Compare(c, name, value) => match get(name).map(|v| compare(v, value)) {
+                Some(Some(o)) => match c {
+                    Comparison::Eq => o == Ordering::Equal,
+                    Comparison::Ne => o != Ordering::Equal,
+                    Comparison::Lt => o == Ordering::Less,
+                    Comparison::Le => o != Ordering::Greater,
+                    Comparison::Gt => o == Ordering::Greater,
+                    Comparison::Ge => o != Ordering::Less,
+                },
+                _ => *c == Comparison::Ne,
+            },
+            Filter::In(name, values) => get(name).map_or(false, |v| {
+                values
+                    .iter()
+                    .any(|value| compare(v, value) == Some(Ordering::Equal))
+            }),
+            Filter::Has(name) => get(name).map_or(false, |v| !v.is_null()),
+            Filter::Geometry(kinds) => geometry.map_or(false, |g| kinds.iter().any(|k| k == g)),
+            Filter::And(filters) => filters.iter().all(|f| f.matches(properties, geometry)),
+            Filter::Or(filters) => filters.iter().any(|f| f.matches(properties, geometry)),
+            Filter::Not(f) => !f.matches(properties, geometry),
+        }
+    }
+
+    /// Tests a feature as found in a GeoJSON file, before it is parsed.
+    pub fn matches_object(&self, object: &Map<String, Value>) -> bool {
+        let properties = object.get("properties").and_then(|p| p.as_object());
+        let geometry = object
+            .get("geometry")
+            .and_then(|g| g.get("type"))
+            .and_then(|t| t.as_str());
+        self.matches(properties, geometry)
+    }
+}
+
+impl<'de> Deserialize<'de> for Filter {
+    fn deserialize<D>(deserializer: D) -> Result<Filter, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        Filter::parse(&value).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use filter::Filter;
+    use serde_json;
+
+    fn filter(s: &str) -> Filter {
+        Filter::parse(&serde_json::from_str(s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn evaluate() {
+        let object = serde_json::from_str(
+            r#"{"type": "Feature",
+                "properties": {"h": "12.5", "commune": "Ixelles", "kind": null},
+                "geometry": {"type": "Polygon", "coordinates": []}}"#,
+        ).unwrap();
+        let yes = |s: &str| assert!(filter(s).matches_object(&object), "{}", s);
+        let no = |s: &str| assert!(!filter(s).matches_object(&object), "{}", s);
+
+        yes(r#"[">", "h", 10]"#);
+        no(r#"["<=", "h", 10]"#);
+        yes(r#"["in", "commune", ["Ixelles", "Uccle"]]"#);
+        no(r#"["==", "missing", 1]"#);
+        yes(r#"["!=", "missing", 1]"#);
+        no(r#"["has", "kind"]"#);
+        yes(r#"["geometry", "Polygon", "MultiPolygon"]"#);
+        yes(r#"["and", [">=", "h", 12.5], ["not", ["geometry", "Point"]]]"#);
+        no(r#"["or", ["==", "commune", "Uccle"], ["<", "h", 3]]"#);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for s in &[r#"["~", "a", 1]"#, r#"["in", "a", 1]"#, r#"["==", 1, 1]"#, r#"{}"#] {
+            assert!(Filter::parse(&serde_json::from_str(s).unwrap()).is_err(), "{}", s);
+        }
+    }
+}
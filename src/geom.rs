@@ -17,6 +17,17 @@ pub fn deg_to_rad(a: f64) -> f64 {
     a * std::f64::consts::PI / 180.0
 }
 
+/// Unit vector towards a direction given in degrees, azimuth clockwise
+/// from north (+y) and elevation above the horizon.
+pub fn direction(azimuth: f64, elevation: f64) -> na::Vector3<f64> {
+    let (azimuth, elevation) = (deg_to_rad(azimuth), deg_to_rad(elevation));
+    na::Vector3::new(
+        azimuth.sin() * elevation.cos(),
+        azimuth.cos() * elevation.cos(),
+        elevation.sin(),
+    )
+}
+
 pub fn vertical_axis() -> na::Unit<na::Vector3<f64>> {
     na::Unit::new_normalize(na::Vector3::new(0.0, 0.0, 1.0))
 }
@@ -0,0 +1,271 @@
+use data::{LayerData, LayerSeed, LoadError};
+use geojson::feature::Id;
+use geojson::{Feature, Geometry, Value};
+use manifest::LayerConfig;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde_json::{Map, Number, Value as JsonValue};
+use wkb;
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sql_error(e: rusqlite::Error) -> String {
+    e.to_string()
+}
+
+/// The feature table to read, the requested one or the only one.
+fn feature_table(conn: &Connection, requested: Option<&String>) -> Result<String, String> {
+    let mut stmt = conn
+        .prepare("SELECT table_name FROM gpkg_contents WHERE data_type = 'features'")
+        .map_err(sql_error)?;
+    let tables = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .and_then(|rows| rows.collect::<Result<Vec<String>, _>>())
+        .map_err(sql_error)?;
+    match requested {
+        Some(table) if tables.contains(table) => Ok(table.clone()),
+        Some(table) => Err(format!(
+            "no feature table \"{}\", found {}",
+            table,
+            tables.join(", ")
+        )),
+        None if tables.len() == 1 => Ok(tables[0].clone()),
+        None if tables.is_empty() => Err("no feature table".to_owned()),
+        None => Err(format!(
+            "several feature tables, pick one with the table option: {}",
+            tables.join(", ")
+        )),
+    }
+}
+
+fn primary_key(conn: &Connection, table: &str) -> Result<Option<String>, String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", quote(table)))
+        .map_err(sql_error)?;
+    let columns = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(1)?, row.get::<_, i64>(5)?)))
+        .and_then(|rows| rows.collect::<Result<Vec<(String, i64)>, _>>())
+        .map_err(sql_error)?;
+    Ok(columns.into_iter().find(|c| c.1 > 0).map(|c| c.0))
+}
+
+/// Strips the GeoPackage header off a geometry, `None` standing for an
+/// empty one.
+fn read_geometry(blob: &[u8]) -> Result<Option<Value>, String> {
+    if blob.len() < 8 || &blob[..2] != b"GP" {
+        return Err("not a GeoPackage geometry".to_owned());
+    }
+    let flags = blob[3];
+    if flags & 0x10 != 0 {
+        return Ok(None);
+    }
+    if flags & 0x20 != 0 {
+        return Err("extended geometry types are not supported".to_owned());
+    }
+    let envelope = match (flags >> 1) & 0x07 {
+        0 => 0,
+        1 => 32,
+        2 | 3 => 48,
+        4 => 64,
+        e => return Err(format!("bad envelope indicator {}", e)),
+    };
+    let wkb = blob
+        .get(8 + envelope..)
+        .ok_or_else(|| "truncated geometry".to_owned())?;
+    wkb::read(wkb).map(Some)
+}
+
+fn property(value: ValueRef) -> Option<JsonValue> {
+    match value {
+        ValueRef::Null => Some(JsonValue::Null),
+        ValueRef::Integer(i) => Some(JsonValue::Number(i.into())),
+        ValueRef::Real(f) => Some(Number::from_f64(f).map_or(JsonValue::Null, JsonValue::Number)),
+        ValueRef::Text(t) => Some(JsonValue::String(String::from_utf8_lossy(t).into_owned())),
+        ValueRef::Blob(_) => None,
+    }
+}
+
+fn read_table(layer: &LayerConfig, seed: &LayerSeed, data: &mut LayerData) -> Result<(), String> {
+    let conn = Connection::open_with_flags(&layer.data, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(sql_error)?;
+    let table = feature_table(&conn, layer.options.table.as_ref())?;
+    let geometry_column: String = conn
+        .query_row(
+            "SELECT column_name FROM gpkg_geometry_columns WHERE table_name = ?1",
+            [&table],
+            |row| row.get(0),
+        ).map_err(|e| format!("{}: {}", table, e))?;
+    let key = primary_key(&conn, &table)?;
+
+    let bbox = match (layer.options.bbox, seed.transform) {
+        (Some(b), _) => Some(b),
+        (None, None) => seed.scene.region.as_ref().map(|r| r.bounds()),
+        (None, Some(_)) => None,
+    };
+    let index = format!("rtree_{}_{}", table, geometry_column);
+    let indexed: Option<i64> = conn
+        .query_row("SELECT 1 FROM sqlite_master WHERE name = ?1", [&index], |row| row.get(0))
+        .optional()
+        .map_err(sql_error)?;
+    let mut sql = format!("SELECT * FROM {}", quote(&table));
+    let mut params: Vec<f64> = Vec::new();
+    match (bbox, key.as_ref(), indexed) {
+        (Some(b), Some(key), Some(_)) => {
+            sql.push_str(&format!(
+                " WHERE {} IN (SELECT id FROM {} \
+                 WHERE maxx >= ?1 AND minx <= ?3 AND maxy >= ?2 AND miny <= ?4)",
+                quote(key),
+                quote(&index)
+            ));
+            params.extend_from_slice(&b);
+        }
+        (Some(_), _, _) => eprintln!("No spatial index on {}, reading every feature", table),
+        (None, _, _) => (),
+    }
+
+    let mut stmt = conn.prepare(&sql).map_err(sql_error)?;
+    let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+    let mut rows = stmt
+        .query(rusqlite::params_from_iter(params.iter()))
+        .map_err(sql_error)?;
+    while let Some(row) = rows.next().map_err(sql_error)? {
+        data.report.features_read += 1;
+        let mut id = None;
+        let mut blob = None;
+        let mut properties = Map::new();
+        for (i, name) in columns.iter().enumerate() {
+            let value = row.get_ref(i).map_err(sql_error)?;
+            if *name == geometry_column {
+                if let ValueRef::Blob(b) = value {
+                    blob = Some(b);
+                }
+            } else if Some(name) == key.as_ref() {
+                if let ValueRef::Integer(n) = value {
+                    id = Some(n);
+                }
+            } else if let Some(v) = property(value) {
+                properties.insert(name.clone(), v);
+            }
+        }
+        let geometry = match blob.map(read_geometry) {
+            Some(Ok(geometry)) => geometry,
+            Some(Err(_)) => {
+                data.report.features_invalid += 1;
+                continue;
+            }
+            None => None,
+        };
+        seed.push_feature(
+            &Feature {
+                bbox: None,
+                geometry: geometry.map(Geometry::new),
+                id: id.map(|n| Id::Number(n.into())),
+                properties: Some(properties),
+                foreign_members: None,
+            },
+            data,
+        );
+    }
+    Ok(())
+}
+
+/// Loads a feature table of a GeoPackage, its columns becoming properties
+/// and its primary key the feature id.
+pub fn load(layer: &LayerConfig, seed: LayerSeed) -> Result<LayerData, LoadError> {
+    eprintln!("Loading GeoPackage {} ({})", layer.name, layer.data.display());
+    let mut data = seed.empty();
+    read_table(layer, &seed, &mut data).map_err(|e| LoadError::Format(layer.data.clone(), e))?;
+    eprintln!("Loaded {}", layer.name);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use data::Data;
+    use rusqlite::Connection;
+    use std::env;
+    use std::fs;
+
+    /// A GeoPackage polygon Z with an envelope, as written by GDAL.
+    fn square(x: f64, h: f64) -> Vec<u8> {
+        let mut b = b"GP\0\x03".to_vec();
+        b.extend_from_slice(&4326i32.to_le_bytes());
+        for v in &[x, x + 1.0, 0.0, 1.0] {
+            b.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+        b.push(1);
+        b.extend_from_slice(&1003u32.to_le_bytes());
+        b.extend_from_slice(&1u32.to_le_bytes());
+        b.extend_from_slice(&4u32.to_le_bytes());
+        for p in &[[x, 0.0], [x + 1.0, 0.0], [x + 1.0, 1.0], [x, 0.0]] {
+            for v in &[p[0], p[1], h] {
+                b.extend_from_slice(&v.to_bits().to_le_bytes());
+            }
+        }
+        b
+    }
+
+    #[test]
+    fn indexed_table() {
+        let dir = env::temp_dir().join("cardboard-gpkg");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.gpkg");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(dir.join("m.json.cache"));
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE gpkg_contents (table_name TEXT, data_type TEXT);
+             CREATE TABLE gpkg_geometry_columns (table_name TEXT, column_name TEXT);
+             INSERT INTO gpkg_contents VALUES ('buildings', 'features'), ('roads', 'features');
+             INSERT INTO gpkg_geometry_columns VALUES ('buildings', 'geom');
+             CREATE TABLE buildings (fid INTEGER PRIMARY KEY, geom BLOB, h REAL, name TEXT);
+             CREATE VIRTUAL TABLE rtree_buildings_geom USING rtree(id, minx, maxx, miny, maxy);",
+        ).unwrap();
+        for (fid, x, h) in &[(1, 0.0, 10.0), (2, 10.0, 50.0), (3, 20.0, 30.0)] {
+            conn.execute(
+                "INSERT INTO buildings VALUES (?1, ?2, ?3, 'b')",
+                (fid, square(*x, *h), h),
+            ).unwrap();
+            conn.execute(
+                "INSERT INTO rtree_buildings_geom VALUES (?1, ?2, ?3, 0, 1)",
+                (fid, x, x + 1.0),
+            ).unwrap();
+        }
+        // a geometry cut short
+        conn.execute(
+            "INSERT INTO buildings VALUES (4, ?1, 40, 'b')",
+            [&square(15.0, 40.0)[..60]],
+        ).unwrap();
+        conn.execute("INSERT INTO rtree_buildings_geom VALUES (4, 15, 16, 0, 1)", []).unwrap();
+        fs::write(
+            dir.join("a.json"),
+            r#"{"kind":"continuous","propName":"h","intervals":[
+                {"low":0,"high":20,"fillColor":"red","strokeColor":"black","strokeWidth":1},
+                {"low":20,"high":100,"fillColor":"blue","strokeColor":"black","strokeWidth":1}]}"#,
+        ).unwrap();
+        let manifest = |options: &str| {
+            fs::write(
+                dir.join("m.json"),
+                format!(
+                    r#"{{"layers":[{{"name":"a","data":"a.gpkg","style":"a.json","options":{}}}]}}"#,
+                    options
+                ),
+            ).unwrap();
+            Data::from_file(dir.join("m.json")).unwrap()
+        };
+
+        let data = manifest("{}");
+        assert!(data.reports[0].error.is_some());
+
+        let data = manifest(r#"{"table":"buildings","bbox":[5,-1,25,2]}"#);
+        assert!(data.reports[0].error.is_none());
+        assert_eq!(data.reports[0].features_read, 3);
+        assert_eq!(data.reports[0].features_invalid, 1);
+        let planes = data.planes.flattened();
+        assert_eq!(planes[0].points[0].z, 50.0);
+        let record = data.feature(&planes[0]).unwrap();
+        assert_eq!(record.properties.as_ref().unwrap()["name"], "b");
+    }
+}
@@ -0,0 +1,50 @@
+use flate2::bufread::MultiGzDecoder;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// The data path standing for standard input.
+pub const STDIN: &str = "-";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn decompress<R: BufRead + 'static>(mut reader: R) -> io::Result<Box<dyn BufRead>> {
+    let gzipped = reader.fill_buf()?.starts_with(&GZIP_MAGIC);
+    if gzipped {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// A buffered reader over a data file or standard input, gzip streams
+/// being decompressed on the fly whatever the file is named.
+pub fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    if path == Path::new(STDIN) {
+        decompress(BufReader::new(io::stdin()))
+    } else {
+        decompress(BufReader::new(fs::File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use input::decompress;
+    use std::io::{Cursor, Read, Write};
+
+    #[test]
+    fn gzip_detection() {
+        let text = b"{\"type\": \"Feature\"}\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        for bytes in vec![gzipped, text.to_vec()] {
+            let mut out = Vec::new();
+            decompress(Cursor::new(bytes)).unwrap().read_to_end(&mut out).unwrap();
+            assert_eq!(out, text.to_vec());
+        }
+    }
+}
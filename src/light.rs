@@ -0,0 +1,125 @@
+use geom::direction;
+use lingua::Point;
+use manifest::LightConfig;
+use nalgebra as na;
+use style::Color;
+use validate::normal;
+
+/// A light at infinity and how much of it faces catch.
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    /// Towards the light.
+    direction: na::Unit<na::Vector3<f64>>,
+    ambient: f64,
+    diffuse: f64,
+}
+
+impl Light {
+    pub fn from_config(config: &LightConfig) -> Result<Light, String> {
+        if !(config.ambient >= 0.0 && config.diffuse >= 0.0) {
+            return Err("light ambient and diffuse must be positive".to_owned());
+        }
+        let vector = match config.direction {
+            Some(d) => na::Vector3::new(d[0], d[1], d[2]),
+            None => direction(config.azimuth, config.elevation),
+        };
+        if !(vector.norm() > 0.0) {
+            return Err("light direction must not be null".to_owned());
+        }
+        Ok(Light {
+            direction: na::Unit::new_normalize(vector),
+            ambient: config.ambient,
+            diffuse: config.diffuse,
+        })
+    }
+
+    /// The factor a face's fill is shaded by, the face being seen from
+    /// `towards_eye`. Faces whose normal cannot be told get the ambient
+    /// term alone.
+    pub fn shade(&self, points: &[Point], towards_eye: &na::Vector3<f64>) -> f64 {
+        let n = normal(points);
+        let mut n = na::Vector3::new(n[0], n[1], n[2]);
+        let length = n.norm();
+        if !(length > 0.0) {
+            return self.ambient;
+        }
+        n /= length;
+        if n.dot(towards_eye) < 0.0 {
+            n = -n;
+        }
+        self.ambient + self.diffuse * n.dot(&self.direction).max(0.0)
+    }
+}
+
+/// Darkens a colour towards black below 1, lightens it towards white
+/// above, alpha untouched.
+pub fn shade_color(color: Color, factor: f64) -> Color {
+    let channel = |c: f64| {
+        if factor <= 1.0 {
+            c * factor
+        } else {
+            (c + (1.0 - c) * (factor - 1.0)).min(1.0)
+        }
+    };
+    Color::rgba(
+        channel(color.red),
+        channel(color.green),
+        channel(color.blue),
+        color.alpha,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use light::{shade_color, Light};
+    use lingua::Point;
+    use manifest::LightConfig;
+    use nalgebra as na;
+    use style::Color;
+
+    fn config(direction: Option<[f64; 3]>) -> LightConfig {
+        LightConfig {
+            direction,
+            azimuth: 315.0,
+            elevation: 45.0,
+            ambient: 0.4,
+            diffuse: 0.6,
+        }
+    }
+
+    #[test]
+    fn shading() {
+        let roof = [
+            Point::new(0.0, 0.0, 5.0),
+            Point::new(1.0, 0.0, 5.0),
+            Point::new(1.0, 1.0, 5.0),
+            Point::new(0.0, 1.0, 5.0),
+        ];
+        // a wall facing south, wound either way
+        let wall = [
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(0.0, 0.0, 5.0),
+            Point::new(1.0, 0.0, 5.0),
+            Point::new(1.0, 0.0, 0.0),
+        ];
+        let reversed: Vec<Point> = wall.iter().rev().cloned().collect();
+        let eye = na::Vector3::new(1.0, -1.0, 1.0);
+
+        let zenith = Light::from_config(&config(Some([0.0, 0.0, 2.0]))).unwrap();
+        assert!((zenith.shade(&roof, &eye) - 1.0).abs() < 1e-9);
+        assert!((zenith.shade(&wall, &eye) - 0.4).abs() < 1e-9);
+
+        // from the north west, the south wall is in the shade
+        let north_west = Light::from_config(&config(None)).unwrap();
+        assert!((north_west.shade(&wall, &eye) - 0.4).abs() < 1e-9);
+        assert_eq!(north_west.shade(&wall, &eye), north_west.shade(&reversed, &eye));
+        assert!(north_west.shade(&roof, &eye) > north_west.shade(&wall, &eye));
+
+        assert!(Light::from_config(&config(Some([0.0, 0.0, 0.0]))).is_err());
+
+        let c = shade_color(Color::rgba(0.5, 1.0, 0.0, 0.5), 0.5);
+        assert_eq!((c.red, c.green, c.blue, c.alpha), (0.25, 0.5, 0.0, 0.5));
+        let c = shade_color(Color::rgb(0.5, 1.0, 0.0), 1.5);
+        assert_eq!((c.red, c.green, c.blue), (0.75, 1.0, 0.5));
+    }
+}
@@ -1,9 +1,11 @@
-use geojson::{Feature, GeoJson, Geometry, PolygonType, Value};
+use extrude::{extrude_rings, Extrusion};
+use geojson::feature::Id;
+use geojson::{Feature, Geometry, PolygonType, Value};
+use manifest::LayerOptions;
 use nalgebra::geometry::{Point2, Point3};
 use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
 use serde_json;
 use std::iter::Flatten;
-use std::iter::FromIterator;
 use std::slice::Iter;
 
 pub type Properties = std::option::Option<serde_json::Map<std::string::String, serde_json::Value>>;
@@ -11,11 +13,76 @@ pub type Properties = std::option::Option<serde_json::Map<std::string::String, s
 pub type Point = Point3<f64>;
 pub type Point2D = Point2<f64>;
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PlaneKind {
+    /// A closed ring, filled and stroked.
+    Polygon,
+    /// An open path, stroked only.
+    Line,
+    /// A symbol drawn at its single point.
+    Marker,
+    /// A polygon of the terrain, filled and stroked before anything that
+    /// is not ground.
+    Ground,
+}
+
 #[derive(Clone)]
 pub struct PlaneT {
     pub layer_index: usize,
     pub style_index: usize,
+    /// Index of the source feature in its layer's `FeatureStore`.
+    pub feature_index: usize,
+    pub kind: PlaneKind,
     pub points: Vec<Point>,
+    /// Interior rings, painted with the even-odd rule.
+    pub holes: Vec<Vec<Point>>,
+}
+
+impl PlaneT {
+    pub fn polygon(
+        layer_index: usize,
+        style_index: usize,
+        points: Vec<Point>,
+        holes: Vec<Vec<Point>>,
+    ) -> PlaneT {
+        PlaneT {
+            layer_index,
+            style_index,
+            feature_index: 0,
+            kind: PlaneKind::Polygon,
+            points,
+            holes,
+        }
+    }
+
+    pub fn line(layer_index: usize, style_index: usize, points: Vec<Point>) -> PlaneT {
+        PlaneT {
+            layer_index,
+            style_index,
+            feature_index: 0,
+            kind: PlaneKind::Line,
+            points,
+            holes: Vec::new(),
+        }
+    }
+
+    pub fn marker(layer_index: usize, style_index: usize, point: Point) -> PlaneT {
+        PlaneT {
+            layer_index,
+            style_index,
+            feature_index: 0,
+            kind: PlaneKind::Marker,
+            points: vec![point],
+            holes: Vec::new(),
+        }
+    }
+
+    /// Applies `f` to every point, holes included.
+    pub fn map_points<F: Fn(&Point) -> Point>(&mut self, f: F) {
+        for pt in self.points.iter_mut().chain(self.holes.iter_mut().flat_map(|h| h.iter_mut())) {
+            *pt = f(pt);
+        }
+    }
 }
 
 // pub enum Plane {
@@ -89,6 +156,16 @@ impl PlaneList {
         self.0.append(&mut other.0)
     }
 
+    /// Drops the planes of a layer.
+    pub fn remove_layer(&mut self, layer_index: usize) {
+        self.0
+            .retain(|plane| plane.iter().all(|t| t.layer_index != layer_index))
+    }
+
+    pub fn for_each_mut<F: FnMut(&mut PlaneT)>(&mut self, mut f: F) {
+        self.0.iter_mut().flat_map(|p| p.iter_mut()).for_each(|t| f(t))
+    }
+
     // pub fn iter(&self) -> PlaneIter {
     //     let inner = self
     //         .0
@@ -118,36 +195,179 @@ impl PlaneList {
 //     }
 // }
 
-pub type PropList = Vec<Properties>;
+/// What is kept of a feature once it has been turned into planes.
+#[derive(Clone, Debug)]
+pub struct FeatureRecord {
+    pub id: Option<Id>,
+    pub properties: Properties,
+}
+
+/// The features of one layer, indexed by `PlaneT::feature_index`.
+#[derive(Clone, Debug, Default)]
+pub struct FeatureStore(Vec<FeatureRecord>);
+
+impl FeatureStore {
+    pub fn new() -> FeatureStore {
+        FeatureStore(Vec::new())
+    }
 
-fn plane_from_polygon(poly: &PolygonType, layer_index: usize, style_index: usize) -> PlaneT {
-    let exterior_ring = &poly[0];
-    PlaneT {
-        layer_index,
-        style_index,
-        points: exterior_ring
-            .iter()
-            .map(|pos| Point::new(pos[0], pos[1], pos[2]))
-            .collect(),
+    /// Records a feature and returns its index.
+    pub fn push(&mut self, f: &Feature) -> usize {
+        self.0.push(FeatureRecord {
+            id: f.id.clone(),
+            properties: f.properties.clone(),
+        });
+        self.0.len() - 1
+    }
+
+    pub fn push_record(&mut self, record: FeatureRecord) {
+        self.0.push(record);
+    }
+
+    pub fn get(&self, index: usize) -> Option<&FeatureRecord> {
+        self.0.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> Iter<FeatureRecord> {
+        self.0.iter()
     }
 }
 
-fn plane_from_geometry(geom: &Geometry, layer_index: usize, style_index: usize) -> Plane {
-    match geom.value {
-        Value::Polygon(ref v) => vec![plane_from_polygon(v, layer_index, style_index)],
-        Value::MultiPolygon(ref v) => Plane::from_iter(
-            v.iter()
-                .map(|poly| plane_from_polygon(poly, layer_index, style_index)),
-        ),
-        _ => Vec::new(),
+/// Why a feature did not yield any plane.
+#[derive(Debug)]
+pub enum GeometryError {
+    /// A geometry type we do not know how to turn into planes.
+    Skipped(&'static str),
+    Invalid(String),
+}
+
+pub fn geometry_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Point(_) => "Point",
+        Value::MultiPoint(_) => "MultiPoint",
+        Value::LineString(_) => "LineString",
+        Value::MultiLineString(_) => "MultiLineString",
+        Value::Polygon(_) => "Polygon",
+        Value::MultiPolygon(_) => "MultiPolygon",
+        Value::GeometryCollection(_) => "GeometryCollection",
+    }
+}
+
+/// A numeric property, also accepting numbers written as strings.
+pub fn number_property(props: &Properties, name: &str) -> Option<f64> {
+    props.as_ref().and_then(|p| p.get(name)).and_then(|v| {
+        v.as_f64()
+            .or_else(|| v.as_str().and_then(|s| s.trim().parse::<f64>().ok()))
+    })
+}
+
+fn position_point(pos: &[f64]) -> Result<Point, GeometryError> {
+    match pos.len() {
+        0 | 1 => Err(GeometryError::Invalid(format!(
+            "expected at least 2 coordinates, got {}",
+            pos.len()
+        ))),
+        2 => Ok(Point::new(pos[0], pos[1], 0.0)),
+        _ => Ok(Point::new(pos[0], pos[1], pos[2])),
+    }
+}
+
+fn ring_points(ring: &[Vec<f64>]) -> Result<Vec<Point>, GeometryError> {
+    ring.iter().map(|pos| position_point(pos)).collect()
+}
+
+fn plane_from_line(
+    line: &[Vec<f64>],
+    layer_index: usize,
+    style_index: usize,
+) -> Result<PlaneT, GeometryError> {
+    if line.len() < 2 {
+        return Err(GeometryError::Invalid(
+            "line with less than 2 positions".to_owned(),
+        ));
     }
+    Ok(PlaneT::line(layer_index, style_index, ring_points(line)?))
 }
 
-pub fn plane_from_feature(f: &Feature, layer_index: usize, style_index: usize) -> Plane {
-    match f.geometry {
-        Some(ref geom) => plane_from_geometry(geom, layer_index, style_index),
-        None => Vec::new(),
+fn plane_from_polygon(
+    poly: &PolygonType,
+    layer_index: usize,
+    style_index: usize,
+    extrusion: Option<&Extrusion>,
+    plane: &mut Plane,
+) -> Result<(), GeometryError> {
+    if poly.is_empty() {
+        return Err(GeometryError::Invalid("polygon without rings".to_owned()));
     }
+    let mut rings = Vec::with_capacity(poly.len());
+    for ring in poly {
+        rings.push(ring_points(ring)?);
+    }
+
+    match extrusion {
+        Some(e) => plane.append(&mut extrude_rings(&rings, e, layer_index, style_index)),
+        None => {
+            let points = rings.remove(0);
+            plane.push(PlaneT::polygon(layer_index, style_index, points, rings))
+        }
+    }
+    Ok(())
+}
+
+fn plane_from_geometry(
+    geom: &Geometry,
+    layer_index: usize,
+    style_index: usize,
+    extrusion: Option<&Extrusion>,
+) -> Result<Plane, GeometryError> {
+    let mut plane: Plane = Vec::new();
+    match geom.value {
+        Value::Polygon(ref v) => {
+            plane_from_polygon(v, layer_index, style_index, extrusion, &mut plane)?
+        }
+        Value::MultiPolygon(ref v) => for poly in v {
+            plane_from_polygon(poly, layer_index, style_index, extrusion, &mut plane)?
+        },
+        Value::LineString(ref v) => plane.push(plane_from_line(v, layer_index, style_index)?),
+        Value::MultiLineString(ref v) => for line in v {
+            plane.push(plane_from_line(line, layer_index, style_index)?)
+        },
+        Value::Point(ref v) => plane.push(PlaneT::marker(
+            layer_index,
+            style_index,
+            position_point(v)?,
+        )),
+        Value::MultiPoint(ref v) => for pos in v {
+            plane.push(PlaneT::marker(layer_index, style_index, position_point(pos)?))
+        },
+        ref v => return Err(GeometryError::Skipped(geometry_kind(v))),
+    };
+    Ok(plane)
+}
+
+pub fn plane_from_feature(
+    f: &Feature,
+    layer_index: usize,
+    style_index: usize,
+    feature_index: usize,
+    options: &LayerOptions,
+) -> Result<Plane, GeometryError> {
+    let extrusion = options
+        .extrude
+        .as_ref()
+        .map(|e| Extrusion::from_properties(e, &f.properties));
+    let mut plane = match f.geometry {
+        Some(ref geom) => plane_from_geometry(geom, layer_index, style_index, extrusion.as_ref())?,
+        None => return Err(GeometryError::Skipped("null")),
+    };
+    for p in plane.iter_mut() {
+        p.feature_index = feature_index;
+    }
+    Ok(plane)
 }
 
 // pub fn get_planes(gj: &GeoJson, layer_index: usize) -> PlaneList {
@@ -163,15 +383,6 @@ pub fn plane_from_feature(f: &Feature, layer_index: usize, style_index: usize) -
 //     }
 // }
 
-pub fn get_properties(gj: &GeoJson) -> PropList {
-    match gj {
-        GeoJson::FeatureCollection(fc) => {
-            fc.features.iter().map(|f| f.clone().properties).collect()
-        }
-        _ => vec![],
-    }
-}
-
 // pub fn make_cross(pt: Point, cin: f64, cout: f64) -> Plane {
 //     let x = pt.x;
 //     let y = pt.y;
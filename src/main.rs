@@ -1,11 +1,13 @@
 extern crate cairo;
 extern crate cairo_sys;
+extern crate flate2;
 extern crate geojson;
 extern crate libc;
 extern crate nalgebra;
 extern crate num_cpus;
 extern crate ordered_float;
 extern crate rayon;
+extern crate rusqlite;
 extern crate sdl2;
 extern crate serde_json;
 extern crate svgtypes;
@@ -16,36 +18,106 @@ extern crate serde;
 extern crate serde_derive;
 
 mod bbox;
+mod cache;
 mod camera;
 mod capture;
+mod cityjson;
+mod classify;
+mod crs;
 mod data;
+mod dem;
 mod draw;
+mod extrude;
+mod filter;
 mod geom;
+mod gpkg;
 mod handlers;
+mod light;
+mod input;
 mod lingua;
+mod manifest;
+mod obj;
 mod operation;
+mod ramp;
+mod region;
+mod shadow;
+mod shapefile;
 mod style;
+mod sun;
 mod surface_data;
 mod ui_cli;
 mod ui_sdl;
+mod validate;
+mod watch;
+mod wkb;
 
 use bbox::BBox;
 use camera::Camera;
 use data::Data;
 use lingua::Point;
 use std::env;
+use std::fs;
 use std::process;
 
 fn run(args: Vec<String>) {
     let command = &args[1];
-    let layers = match Data::from_file(&args[2]) {
+    let mut layers = match Data::from_file(&args[2]) {
         Ok(data) => data,
         Err(e) => {
-            println!("{:?}", e);
+            eprintln!("{}", e);
             process::exit(1)
         }
     };
 
+    layers.reports.iter().for_each(|r| eprintln!("{}", r));
+
+    if "validate" == command {
+        if args[3..].iter().any(|a| a.starts_with("--") && a != "--repair") {
+            eprintln!("validate <manifest> [--repair] [output]");
+            eprintln!("  --repair  also count the problems repairing would leave, the data being");
+            eprintln!("            left as is; set a layer's \"repair\" option to repair on load");
+            process::exit(1);
+        }
+        let repair = args[3..].iter().any(|a| a == "--repair");
+        let report = validate::validate(&layers, repair);
+        let json = serde_json::to_string_pretty(&report).unwrap();
+        match args[3..].iter().find(|a| *a != "--repair") {
+            Some(output) => fs::write(output, json).unwrap_or_else(|e| {
+                eprintln!("Could not write {}: {}", output, e);
+                process::exit(1)
+            }),
+            None => println!("{}", json),
+        }
+        process::exit(if report.is_valid() { 0 } else { 2 });
+    }
+
+    if "classify" == command {
+        if args.len() < 8 {
+            eprintln!("classify <manifest> <layer> <property> <method> <classes> <palette> [output]");
+            process::exit(1);
+        }
+        let style = args[5]
+            .parse::<classify::Method>()
+            .and_then(|method| {
+                let classes = args[6]
+                    .parse::<usize>()
+                    .map_err(|_| format!("bad class count \"{}\"", args[6]))?;
+                classify::classify(&layers, &args[3], &args[4], method, classes, &args[7])
+            }).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1)
+            });
+        let json = serde_json::to_string_pretty(&style).unwrap();
+        match args.get(8) {
+            Some(output) => fs::write(output, json).unwrap_or_else(|e| {
+                eprintln!("Could not write {}: {}", output, e);
+                process::exit(1)
+            }),
+            None => println!("{}", json),
+        }
+        return;
+    }
+
     let planes = layers.planes.flattened();
 
     println!("N {}", planes.len());
@@ -59,8 +131,18 @@ fn run(args: Vec<String>) {
 
     if "view" == command {
         let mut ui = ui_sdl::UiSdl::new(600, 600);
-        ui.run(&planes, &layers.styles, initial_camera);
+        ui.run(&mut layers, initial_camera);
     } else if "print" == command {
+        if layers.is_broken() {
+            println!("Broken layer(s), not printing");
+            process::exit(2);
+        }
+        // with --strict, any invalid feature stops printing
+        let invalid = layers.features_invalid();
+        if invalid > 0 && args[12..].iter().any(|a| a == "--strict") {
+            println!("{} invalid feature(s), not printing", invalid);
+            process::exit(2);
+        }
         let width = args[3].parse::<u32>().unwrap_or(595);
         let height = args[4].parse::<u32>().unwrap_or(841);
         let eye_x = args[5].parse::<f64>().unwrap_or(initial_camera.eye.x);
@@ -70,6 +152,25 @@ fn run(args: Vec<String>) {
         let target_y = args[9].parse::<f64>().unwrap_or(initial_camera.target.y);
         let target_z = args[10].parse::<f64>().unwrap_or(initial_camera.target.z);
         let output = &args[11];
+        let sun_time = args[12..]
+            .iter()
+            .position(|a| a == "--sun")
+            .and_then(|i| args.get(13 + i));
+        let sun = match (layers.manifest.sun.clone(), sun_time) {
+            (None, None) => None,
+            (config, time) => Some(
+                sun::Sun::from_config(
+                    &config.unwrap_or_default(),
+                    time.map(|t| t.as_str()),
+                    layers.manifest.crs,
+                    &center,
+                    bbox.width(),
+                ).unwrap_or_else(|e| {
+                    println!("{}", e);
+                    process::exit(1)
+                }),
+            ),
+        };
 
         println!(
             "camera {} {} {} {} {} {}",
@@ -79,6 +180,8 @@ fn run(args: Vec<String>) {
         ui.run(
             &planes,
             &layers.styles,
+            layers.lights(),
+            sun,
             Some(Camera::new(
                 Point::new(eye_x, eye_y, eye_z),
                 Point::new(target_x, target_y, target_z),
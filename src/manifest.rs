@@ -0,0 +1,645 @@
+use crs::Crs;
+use filter::Filter;
+use input::STDIN;
+use light::Light;
+use serde_json;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Turns flat footprints into blocks, heights being read from feature
+/// properties.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ExtrudeOptions {
+    /// Property holding the height of the top of the block above ground.
+    #[serde(default)]
+    pub height: Option<String>,
+    /// Property holding the height of the bottom of the block above ground.
+    #[serde(default)]
+    pub base: Option<String>,
+    /// Used when the height property is missing or not a number.
+    #[serde(default)]
+    pub default_height: f64,
+}
+
+/// Which terrain height an extruded footprint stands on.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FootprintBase {
+    Lowest,
+    Mean,
+}
+
+impl Default for FootprintBase {
+    fn default() -> FootprintBase {
+        FootprintBase::Lowest
+    }
+}
+
+/// Puts features on the scene's elevation model.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DrapeOptions {
+    #[serde(default)]
+    pub footprint: FootprintBase,
+    /// Longest segment left in lines and flat polygons, the raster cell
+    /// size when unset.
+    #[serde(default)]
+    pub densify: Option<f64>,
+}
+
+/// Free-form knobs attached to a single layer.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LayerOptions {
+    /// Number of features to reserve room for before reading the data file.
+    #[serde(default)]
+    pub capacity: Option<usize>,
+    #[serde(default)]
+    pub extrude: Option<ExtrudeOptions>,
+    /// Level of detail picked from CityJSON objects, the highest one when
+    /// unset.
+    #[serde(default)]
+    pub lod: Option<f64>,
+    /// Added to OBJ vertices to place a model in the scene.
+    #[serde(default)]
+    pub offset: Option<[f64; 3]>,
+    /// Features not matching it are dropped as they are read.
+    #[serde(default)]
+    pub filter: Option<Filter>,
+    /// Needs a scene `dem`.
+    #[serde(default)]
+    pub drape: Option<DrapeOptions>,
+    /// Raster cells along each side of a terrain quad.
+    #[serde(default)]
+    pub step: Option<usize>,
+    /// Fix what can be fixed in the geometry, see `validate::repair`.
+    #[serde(default)]
+    pub repair: bool,
+    /// Feature table of a GeoPackage, needed when it holds several.
+    #[serde(default)]
+    pub table: Option<String>,
+    /// `[minX, minY, maxX, maxY]` in data coordinates. Only the features of
+    /// a GeoPackage whose spatial index entry meets it are read, the scene
+    /// region standing for it when the layer is not reprojected.
+    #[serde(default)]
+    pub bbox: Option<[f64; 4]>,
+}
+
+/// How a layer's data file is encoded.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DataFormat {
+    GeoJson,
+    /// One GeoJSON feature per line (RFC 8142), record separators allowed.
+    GeoJsonSeq,
+    CityJson,
+    Obj,
+    /// An elevation raster drawn as terrain.
+    Dem,
+    /// An ESRI Shapefile, attributes read from the `.dbf` next to it.
+    Shapefile,
+    /// A feature table of an OGC GeoPackage.
+    GeoPackage,
+}
+
+impl DataFormat {
+    /// Formats read through a stream, gzip compressed files included.
+    pub fn streams(self) -> bool {
+        match self {
+            DataFormat::GeoJson
+            | DataFormat::GeoJsonSeq
+            | DataFormat::CityJson
+            | DataFormat::Obj => true,
+            DataFormat::Dem | DataFormat::Shapefile | DataFormat::GeoPackage => false,
+        }
+    }
+}
+
+/// The part of the scene to load, in scene coordinates.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RegionConfig {
+    /// `[minX, minY, maxX, maxY]`
+    #[serde(default)]
+    pub bbox: Option<[f64; 4]>,
+    /// GeoJSON file whose first polygon outlines the region.
+    #[serde(default)]
+    pub polygon: Option<PathBuf>,
+    /// Cut planes at the boundary rather than keeping every feature that
+    /// touches the region whole.
+    #[serde(default)]
+    pub clip: bool,
+}
+
+/// A directional light shading the faces of a layer, given as a direction
+/// or as an azimuth and an elevation.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LightConfig {
+    /// Vector pointing towards the light, in scene coordinates. Azimuth
+    /// and elevation are ignored when it is set.
+    #[serde(default)]
+    pub direction: Option<[f64; 3]>,
+    /// Degrees clockwise from north (+y).
+    #[serde(default = "default_azimuth")]
+    pub azimuth: f64,
+    /// Degrees above the horizon.
+    #[serde(default = "default_elevation")]
+    pub elevation: f64,
+    /// Share of the fill left on faces turned away from the light.
+    #[serde(default = "default_ambient")]
+    pub ambient: f64,
+    /// Share added on faces turned towards the light; fills are lightened
+    /// when it and `ambient` add up to more than 1.
+    #[serde(default = "default_diffuse")]
+    pub diffuse: f64,
+}
+
+fn default_azimuth() -> f64 {
+    315.0
+}
+
+fn default_elevation() -> f64 {
+    45.0
+}
+
+fn default_ambient() -> f64 {
+    0.4
+}
+
+fn default_diffuse() -> f64 {
+    0.6
+}
+
+/// When and where the sun casting shadows in printed plates stands.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SunConfig {
+    /// `YYYY-MM-DDTHH:MM[:SS]` followed by `Z` or an offset such as
+    /// `+02:00`, UTC when there is neither. `print --sun` overrides it.
+    #[serde(default)]
+    pub time: Option<String>,
+    /// `[longitude, latitude]` in degrees, the centre of the scene when
+    /// the scene has a `crs`.
+    #[serde(default)]
+    pub location: Option<[f64; 2]>,
+    /// Painted over the shaded part of faces, its alpha setting how dark
+    /// shadows get.
+    #[serde(default = "default_shadow_color")]
+    pub shadow_color: String,
+}
+
+impl Default for SunConfig {
+    fn default() -> SunConfig {
+        SunConfig {
+            time: None,
+            location: None,
+            shadow_color: default_shadow_color(),
+        }
+    }
+}
+
+fn default_shadow_color() -> String {
+    "rgba(0, 0, 0, 0.4)".to_owned()
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn default_opacity() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LayerConfig {
+    pub name: String,
+    /// Read from standard input when `-`.
+    pub data: PathBuf,
+    pub style: PathBuf,
+    /// Guessed from the data file name when unset.
+    #[serde(default)]
+    pub format: Option<DataFormat>,
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+    /// Higher priorities are painted over lower ones when planes are at
+    /// the same distance from the eye.
+    #[serde(default)]
+    pub priority: i64,
+    /// Multiplies the alpha of every colour of the layer's style.
+    #[serde(default = "default_opacity")]
+    pub opacity: f64,
+    /// Shades polygon fills by the angle of their face to the light.
+    #[serde(default)]
+    pub light: Option<LightConfig>,
+    /// Coordinate system of the data, reprojected to the scene's.
+    #[serde(default)]
+    pub crs: Option<Crs>,
+    #[serde(default)]
+    pub options: LayerOptions,
+}
+
+/// The JSON manifest as written, layers being checked one by one to report
+/// errors against the offending entry.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct ManifestFile {
+    layers: Vec<serde_json::Value>,
+    #[serde(default)]
+    cache: Option<PathBuf>,
+    #[serde(default)]
+    crs: Option<Crs>,
+    #[serde(default)]
+    region: Option<RegionConfig>,
+    #[serde(default)]
+    dem: Option<PathBuf>,
+    #[serde(default)]
+    sun: Option<SunConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub path: PathBuf,
+    pub layers: Vec<LayerConfig>,
+    pub cache: Option<PathBuf>,
+    /// Coordinate system of the scene. Layers are only reprojected when
+    /// both they and the scene have one.
+    pub crs: Option<Crs>,
+    pub region: Option<RegionConfig>,
+    /// Elevation raster, ESRI ASCII grid or GeoTIFF, in scene coordinates.
+    pub dem: Option<PathBuf>,
+    pub sun: Option<SunConfig>,
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(PathBuf, io::Error),
+    Json(PathBuf, serde_json::Error),
+    Entry {
+        index: usize,
+        name: Option<String>,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ManifestError::Json(path, e) => write!(f, "{}: {}", path.display(), e),
+            ManifestError::Entry {
+                index,
+                name: Some(name),
+                reason,
+            } => write!(f, "layer #{} \"{}\": {}", index, name, reason),
+            ManifestError::Entry {
+                index,
+                name: None,
+                reason,
+            } => write!(f, "layer #{}: {}", index, reason),
+        }
+    }
+}
+
+fn entry_error(index: usize, name: Option<String>, reason: String) -> ManifestError {
+    ManifestError::Entry {
+        index,
+        name,
+        reason,
+    }
+}
+
+impl LayerConfig {
+    pub fn format(&self) -> DataFormat {
+        self.format.unwrap_or_else(|| {
+            let name = self
+                .data
+                .file_name()
+                .map_or_else(String::new, |n| n.to_string_lossy().to_lowercase());
+            let name = name.trim_end_matches(".gz");
+            if name.ends_with(".cityjson") || name.ends_with(".city.json") {
+                DataFormat::CityJson
+            } else if name.ends_with(".obj") {
+                DataFormat::Obj
+            } else if name.ends_with(".asc") || name.ends_with(".tif") || name.ends_with(".tiff") {
+                DataFormat::Dem
+            } else if name.ends_with(".gpkg") {
+                DataFormat::GeoPackage
+            } else if name.ends_with(".shp") {
+                DataFormat::Shapefile
+            } else if [".geojsons", ".geojsonl", ".geojsonseq", ".ndjson", ".jsonl"]
+                .iter()
+                .any(|ext| name.ends_with(ext))
+            {
+                DataFormat::GeoJsonSeq
+            } else {
+                DataFormat::GeoJson
+            }
+        })
+    }
+
+    /// The `.dbf` attributes of a Shapefile layer, when there are some.
+    pub fn attribute_table(&self) -> Option<PathBuf> {
+        if self.format() != DataFormat::Shapefile {
+            return None;
+        }
+        ["dbf", "DBF"]
+            .iter()
+            .map(|ext| self.data.with_extension(ext))
+            .find(|p| p.exists())
+    }
+
+    /// Whether the data file is named as gzip compressed.
+    pub fn compressed(&self) -> bool {
+        self.data
+            .extension()
+            .map_or(false, |e| e.to_string_lossy().eq_ignore_ascii_case("gz"))
+    }
+
+    /// Compressed data can only be read by streaming formats.
+    fn check_compression(&self) -> Result<(), String> {
+        if self.compressed() && !self.format().streams() {
+            Err("compressed input is not supported for this format".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn reads_stdin(&self) -> bool {
+        self.data == Path::new(STDIN)
+    }
+
+    /// Every file the layer's data is read from.
+    pub fn data_files(&self) -> Vec<PathBuf> {
+        if self.reads_stdin() {
+            return Vec::new();
+        }
+        let mut files = vec![self.data.clone()];
+        files.extend(self.attribute_table());
+        files
+    }
+
+    fn resolve(mut self, base: &Path) -> LayerConfig {
+        if !self.reads_stdin() {
+            self.data = base.join(&self.data);
+        }
+        self.style = base.join(&self.style);
+        self
+    }
+}
+
+impl Manifest {
+    pub fn from_file<P: AsRef<Path>>(filename: P) -> Result<Manifest, ManifestError> {
+        let path = filename.as_ref().to_path_buf();
+        let content =
+            fs::read_to_string(&path).map_err(|e| ManifestError::Io(path.clone(), e))?;
+
+        if content.trim_start().starts_with('{') {
+            Manifest::from_json(path, &content)
+        } else {
+            Manifest::from_lines(path, &content)
+        }
+    }
+
+    fn from_json(path: PathBuf, content: &str) -> Result<Manifest, ManifestError> {
+        let root: ManifestFile =
+            serde_json::from_str(content).map_err(|e| ManifestError::Json(path.clone(), e))?;
+        let base = path
+            .parent()
+            .map_or_else(PathBuf::new, |p| p.to_path_buf());
+
+        let mut names = HashSet::new();
+        let mut layers = Vec::with_capacity(root.layers.len());
+        for (index, entry) in root.layers.iter().enumerate() {
+            let name = entry
+                .get("name")
+                .and_then(|n| n.as_str())
+                .map(|n| n.to_owned());
+            let layer = serde_json::from_value::<LayerConfig>(entry.clone())
+                .map_err(|e| entry_error(index, name.clone(), e.to_string()))?;
+            if !names.insert(layer.name.clone()) {
+                return Err(entry_error(
+                    index,
+                    name,
+                    "duplicate layer name".to_owned(),
+                ));
+            }
+            if !(layer.opacity >= 0.0 && layer.opacity <= 1.0) {
+                return Err(entry_error(
+                    index,
+                    name,
+                    "opacity must be between 0 and 1".to_owned(),
+                ));
+            }
+            if let Some(ref light) = layer.light {
+                Light::from_config(light).map_err(|e| entry_error(index, name.clone(), e))?;
+            }
+            layer
+                .check_compression()
+                .map_err(|e| entry_error(index, name.clone(), e))?;
+            layers.push(layer.resolve(&base));
+        }
+
+        Ok(Manifest {
+            cache: root.cache.map(|c| base.join(c)),
+            crs: root.crs,
+            region: root.region.map(|mut r| {
+                r.polygon = r.polygon.map(|p| base.join(p));
+                r
+            }),
+            dem: root.dem.map(|d| base.join(d)),
+            sun: root.sun,
+            path,
+            layers,
+        })
+    }
+
+    /// The original format: one `data.geojson:style.json` record per line,
+    /// paths relative to the working directory.
+    fn from_lines(path: PathBuf, content: &str) -> Result<Manifest, ManifestError> {
+        let mut layers = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let record = line.trim();
+            if record.is_empty() {
+                continue;
+            }
+            let mut parts = record.rsplitn(2, ':');
+            let style = parts.next().unwrap_or("");
+            let data = match parts.next() {
+                Some(data) => data,
+                None => {
+                    return Err(entry_error(
+                        index,
+                        None,
+                        format!("expected data:style, got \"{}\"", record),
+                    ))
+                }
+            };
+            let name = Path::new(data)
+                .file_stem()
+                .map_or_else(|| format!("layer-{}", index), |s| s.to_string_lossy().into_owned());
+            let layer = LayerConfig {
+                name,
+                data: PathBuf::from(data),
+                style: PathBuf::from(style),
+                format: None,
+                visible: true,
+                priority: 0,
+                opacity: 1.0,
+                light: None,
+                crs: None,
+                options: LayerOptions::default(),
+            };
+            layer
+                .check_compression()
+                .map_err(|e| entry_error(index, Some(layer.name.clone()), e))?;
+            layers.push(layer);
+        }
+
+        Ok(Manifest {
+            path,
+            layers,
+            cache: None,
+            crs: None,
+            region: None,
+            dem: None,
+            sun: None,
+        })
+    }
+
+    /// Where the binary scene cache lives, next to the manifest unless
+    /// configured otherwise.
+    pub fn cache_path(&self) -> PathBuf {
+        match self.cache {
+            Some(ref path) => path.clone(),
+            None => {
+                let mut name = self.path.as_os_str().to_owned();
+                name.push(".cache");
+                PathBuf::from(name)
+            }
+        }
+    }
+
+    /// Visible layers in paint order, their position in the returned list
+    /// being the `layer_index` carried by planes.
+    pub fn visible_layers(&self) -> Vec<&LayerConfig> {
+        let mut layers: Vec<&LayerConfig> = self.layers.iter().filter(|l| l.visible).collect();
+        layers.sort_by(|a, b| b.priority.cmp(&a.priority));
+        layers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crs::Crs;
+    use manifest::{DataFormat, Manifest, ManifestError};
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse_json() {
+        let s = r#"{"layers": [
+            {"name": "ground", "data": "ground.geojson", "style": "ground.json"},
+            {"name": "buildings", "data": "/abs/b.geojson", "style": "b.json", "priority": 2},
+            {"name": "hidden", "data": "h.geojson", "style": "h.json", "visible": false}
+        ]}"#;
+        let m = Manifest::from_json(PathBuf::from("scene/manifest.json"), s).unwrap();
+        assert_eq!(m.layers[0].data, PathBuf::from("scene/ground.geojson"));
+        assert_eq!(m.layers[1].data, PathBuf::from("/abs/b.geojson"));
+        let names: Vec<&str> = m
+            .visible_layers()
+            .iter()
+            .map(|l| l.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["buildings", "ground"]);
+    }
+
+    #[test]
+    fn parse_json_crs() {
+        let s = r#"{"crs": "EPSG:31370", "layers": [
+            {"name": "osm", "data": "osm.geojson", "style": "osm.json", "crs": "EPSG:4326"},
+            {"name": "local", "data": "l.geojson", "style": "l.json", "crs": "ENU:4.35,50.85"}
+        ]}"#;
+        let m = Manifest::from_json(PathBuf::from("manifest.json"), s).unwrap();
+        assert_eq!(m.crs, Some(Crs::Lambert72));
+        assert_eq!(m.layers[0].crs, Some(Crs::Wgs84));
+        assert_eq!(
+            m.layers[1].crs,
+            Some(Crs::Enu {
+                lon: 4.35,
+                lat: 50.85,
+                height: 0.0
+            })
+        );
+        let s = r#"{"layers": [
+            {"name": "osm", "data": "osm.geojson", "style": "osm.json", "crs": "EPSG:9999"}
+        ]}"#;
+        assert!(Manifest::from_json(PathBuf::from("manifest.json"), s).is_err());
+    }
+
+    #[test]
+    fn parse_json_filter() {
+        let s = r#"{"layers": [
+            {"name": "a", "data": "a.geojson", "style": "a.json",
+             "options": {"filter": [">", "height", 10]}},
+            {"name": "b", "data": "b.geojson", "style": "b.json",
+             "options": {"filter": ["between", "height", 1, 2]}}
+        ]}"#;
+        match Manifest::from_json(PathBuf::from("manifest.json"), s) {
+            Err(ManifestError::Entry { index, reason, .. }) => {
+                assert_eq!(index, 1);
+                assert!(reason.contains("between"), "{}", reason);
+            }
+            _ => panic!("expected an entry error"),
+        }
+    }
+
+    #[test]
+    fn parse_json_entry_error() {
+        let s = r#"{"layers": [
+            {"name": "ground", "data": "ground.geojson", "style": "ground.json"},
+            {"name": "buildings", "data": "b.geojson"}
+        ]}"#;
+        match Manifest::from_json(PathBuf::from("manifest.json"), s) {
+            Err(ManifestError::Entry { index, name, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(name, Some("buildings".to_owned()));
+            }
+            _ => panic!("expected an entry error"),
+        }
+    }
+
+    #[test]
+    fn parse_json_compressed() {
+        let s = r#"{"layers": [
+            {"name": "a", "data": "a.geojsonl.gz", "style": "a.json"},
+            {"name": "b", "data": "b.shp.gz", "style": "b.json"}
+        ]}"#;
+        match Manifest::from_json(PathBuf::from("manifest.json"), s) {
+            Err(ManifestError::Entry { index, reason, .. }) => {
+                assert_eq!(index, 1);
+                assert!(reason.contains("compressed"), "{}", reason);
+            }
+            _ => panic!("expected an entry error"),
+        }
+        let s = "a.geojsonl.gz:a.json\nb.city.json.gz:b.json\n";
+        let m = Manifest::from_lines(PathBuf::from("layers.txt"), s).unwrap();
+        assert_eq!(m.layers[0].format(), DataFormat::GeoJsonSeq);
+        assert_eq!(m.layers[1].format(), DataFormat::CityJson);
+        assert!(Manifest::from_lines(PathBuf::from("layers.txt"), "c.tif.gz:c.json").is_err());
+    }
+
+    #[test]
+    fn parse_lines() {
+        let s = "C:\\data\\a.geojson:a.json\n\nb.geojson:b.json\n";
+        let m = Manifest::from_lines(PathBuf::from("layers.txt"), s).unwrap();
+        assert_eq!(m.layers.len(), 2);
+        assert_eq!(m.layers[0].data, PathBuf::from("C:\\data\\a.geojson"));
+        assert_eq!(m.layers[0].style, PathBuf::from("a.json"));
+        assert_eq!(m.layers[1].name, "b");
+    }
+}
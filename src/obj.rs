@@ -0,0 +1,225 @@
+use data::{LayerData, LayerSeed, LoadError};
+use geojson::{Feature, Geometry, PolygonType, Value};
+use input;
+use manifest::LayerConfig;
+use serde_json;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+
+/// Property carrying the name of the OBJ group (`g`) of a face.
+pub const GROUP: &str = "group";
+/// Property carrying the material (`usemtl`) of a face.
+pub const MATERIAL: &str = "material";
+
+fn parse_vertex<'a, I>(fields: I, offset: &[f64; 3]) -> Result<Vec<f64>, String>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let coords = fields
+        .take(3)
+        .map(|f| f.parse::<f64>().map_err(|_| format!("bad coordinate \"{}\"", f)))
+        .collect::<Result<Vec<f64>, String>>()?;
+    if coords.len() < 3 {
+        return Err("expected 3 coordinates".to_owned());
+    }
+    Ok(coords.iter().zip(offset.iter()).map(|(c, o)| c + o).collect())
+}
+
+/// Resolves the vertex part of a `v`, `v/vt`, `v//vn` or `v/vt/vn`
+/// reference, negative indices counting back from the last vertex.
+fn vertex_index(reference: &str, count: usize) -> Result<usize, String> {
+    let v = reference.split('/').next().unwrap_or("");
+    let index = v
+        .parse::<i64>()
+        .map_err(|_| format!("bad vertex reference \"{}\"", reference))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        Err(format!("vertex index {} out of range", index))
+    } else {
+        Ok(resolved as usize)
+    }
+}
+
+fn group_feature(group: &str, material: &str, polys: Vec<PolygonType>) -> Feature {
+    let mut props = serde_json::Map::new();
+    props.insert(GROUP.to_owned(), JsonValue::String(group.to_owned()));
+    props.insert(MATERIAL.to_owned(), JsonValue::String(material.to_owned()));
+    Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(Value::MultiPolygon(polys))),
+        id: None,
+        properties: Some(props),
+        foreign_members: None,
+    }
+}
+
+/// Loads the faces of a Wavefront OBJ file, one feature per group and
+/// material pair so each can be styled on its own. Malformed vertices and
+/// faces are counted as invalid and left out.
+pub fn load(layer: &LayerConfig, seed: LayerSeed) -> Result<LayerData, LoadError> {
+    let reader = input::open(&layer.data).map_err(|e| LoadError::Io(layer.data.clone(), e))?;
+    eprintln!("Loading OBJ {} ({})", layer.name, layer.data.display());
+    let offset = layer.options.offset.unwrap_or([0.0, 0.0, 0.0]);
+
+    let mut data = seed.empty();
+    // a malformed vertex keeps its slot so later indices still line up
+    let mut vertices: Vec<Option<Vec<f64>>> = Vec::new();
+    let mut group = String::from("default");
+    let mut material = String::new();
+    let mut faces: BTreeMap<(String, String), Vec<PolygonType>> = BTreeMap::new();
+
+    for line in reader.lines() {
+        let line = line.map_err(|e| LoadError::Io(layer.data.clone(), e))?;
+        let mut fields = line.split_whitespace();
+        let bad = match fields.next() {
+            Some("v") => {
+                let vertex = parse_vertex(fields, &offset).ok();
+                let bad = vertex.is_none();
+                vertices.push(vertex);
+                bad
+            }
+            Some("f") => {
+                let ring = fields
+                    .map(|r| {
+                        vertex_index(r, vertices.len()).and_then(|i| {
+                            vertices[i]
+                                .clone()
+                                .ok_or_else(|| format!("vertex {} is malformed", i + 1))
+                        })
+                    })
+                    .collect::<Result<Vec<Vec<f64>>, String>>();
+                match ring {
+                    Ok(ref ring) if ring.len() < 3 => true,
+                    Ok(mut ring) => {
+                        let first = ring[0].clone();
+                        ring.push(first);
+                        faces
+                            .entry((group.clone(), material.clone()))
+                            .or_insert_with(Vec::new)
+                            .push(vec![ring]);
+                        false
+                    }
+                    Err(_) => true,
+                }
+            }
+            Some("g") | Some("o") => {
+                let name: Vec<&str> = fields.collect();
+                if !name.is_empty() {
+                    group = name.join(" ");
+                }
+                false
+            }
+            Some("usemtl") => {
+                material = fields.collect::<Vec<&str>>().join(" ");
+                false
+            }
+            _ => false,
+        };
+        if bad {
+            data.report.features_read += 1;
+            data.report.features_invalid += 1;
+        }
+    }
+
+    for ((group, material), polys) in faces.into_iter() {
+        data.report.features_read += 1;
+        seed.push_feature(&group_feature(&group, &material, polys), &mut data);
+    }
+
+    eprintln!("Loaded {}", layer.name);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use data::Data;
+    use obj::{vertex_index, GROUP, MATERIAL};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn vertex_references() {
+        assert_eq!(vertex_index("1", 4), Ok(0));
+        assert_eq!(vertex_index("3/1", 4), Ok(2));
+        assert_eq!(vertex_index("2//7", 4), Ok(1));
+        assert_eq!(vertex_index("-1/2/3", 4), Ok(3));
+        assert!(vertex_index("5", 4).is_err());
+        assert!(vertex_index("0", 4).is_err());
+    }
+
+    #[test]
+    fn groups_and_materials() {
+        let dir = env::temp_dir().join("cardboard-obj");
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("m.json.cache"));
+        fs::write(
+            dir.join("a.obj"),
+            "v 0 0 0\nv 2 0 0\nv 2 2 0\nv 0 2 0\nv 1 3 0\nv 1 x 0\n\
+             g roof\nusemtl red\nf 1 2 3 4 5\nf 1 2\nf 1 2 9\nf 1 2 6\n\
+             usemtl blue\nf 1/1 2//2 3/3/3\ng wall\nf -6 -5 -4\n",
+        ).unwrap();
+        fs::write(
+            dir.join("s.json"),
+            r#"{"kind":"discrete","propName":"material","groups":[
+                {"values":["red"],"fillColor":"red","strokeColor":"black","strokeWidth":1},
+                {"values":["blue"],"fillColor":"blue","strokeColor":"black","strokeWidth":1}]}"#,
+        ).unwrap();
+        fs::write(
+            dir.join("m.json"),
+            r#"{"layers":[{"name":"a","data":"a.obj","style":"s.json",
+                "options":{"offset":[10,20,5]}}]}"#,
+        ).unwrap();
+        let data = Data::from_file(dir.join("m.json")).unwrap();
+
+        // a bad vertex and three bad faces are counted, the rest loads
+        let report = &data.reports[0];
+        assert!(report.error.is_none());
+        assert_eq!(report.features_invalid, 4);
+        assert_eq!(report.features_styled, 3);
+        assert_eq!(report.features_read, 7);
+
+        // one feature per group and material
+        let keys: Vec<(String, String)> = data.features[0]
+            .iter()
+            .map(|f| {
+                let props = f.properties.as_ref().unwrap();
+                (
+                    props[GROUP].as_str().unwrap().to_owned(),
+                    props[MATERIAL].as_str().unwrap().to_owned(),
+                )
+            }).collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("roof".to_owned(), "blue".to_owned()),
+                ("roof".to_owned(), "red".to_owned()),
+                ("wall".to_owned(), "blue".to_owned()),
+            ]
+        );
+
+        // styled by material, the pentagon kept whole and offset
+        let planes = data.planes.flattened();
+        assert_eq!(planes.len(), 3);
+        let style = |i: usize| planes.iter().find(|p| p.feature_index == i).unwrap().style_index;
+        assert_eq!(style(0), style(2));
+        assert_ne!(style(0), style(1));
+        let pentagon = planes.iter().find(|p| p.feature_index == 1).unwrap();
+        let corners: Vec<(f64, f64, f64)> =
+            pentagon.points.iter().map(|p| (p.x, p.y, p.z)).collect();
+        assert_eq!(
+            &corners[..5],
+            &[
+                (10.0, 20.0, 5.0),
+                (12.0, 20.0, 5.0),
+                (12.0, 22.0, 5.0),
+                (10.0, 22.0, 5.0),
+                (11.0, 23.0, 5.0)
+            ]
+        );
+    }
+}
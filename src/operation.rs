@@ -1,25 +1,42 @@
-use cairo::Context;
+use cairo::{Context, FillRule};
+use light::shade_color;
 use lingua::Point2D;
-use style::{StyleCollection, StyleGetter};
+use std::f64::consts::PI;
+use style::{Color, Style, StyleCollection, StyleGetter};
 
 pub enum Operation {
     Move(Point2D),
     Line(Point2D),
     Begin,
     Close,
-    Paint(usize, usize),
+    /// Fills and strokes the current path, the fill shaded by the factor.
+    Paint(usize, usize, f64),
+    /// Strokes the current path, leaving it open.
+    Stroke(usize, usize),
+    Marker(Point2D, usize, usize),
+    /// Restricts painting to the current path, filled even-odd, until the
+    /// next `Shadow`.
+    Clip,
+    /// Fills the current path with the non-zero rule, then lifts the clip.
+    Shadow(Color),
 }
 
 pub type OpList = Vec<Operation>;
 
+fn stroke_color(s: &Style) -> Option<Color> {
+    s.strokeColor.or(s.fillColor)
+}
+
 pub fn paint_op(op: &Operation, style: &StyleCollection, context: &Context) {
     match op {
         Operation::Begin => context.new_path(),
         Operation::Close => context.close_path(),
         Operation::Move(p) => context.move_to(p.x, p.y),
         Operation::Line(p) => context.line_to(p.x, p.y),
-        Operation::Paint(li, si) => style.get_for(li, si).map_or((), |s| {
+        Operation::Paint(li, si, shade) => style.get_for(li, si).map_or((), |s| {
             s.fillColor.map(|color| {
+                let color = shade_color(color, *shade);
+                context.set_fill_rule(FillRule::EvenOdd);
                 context.set_source_rgba(color.red, color.green, color.blue, color.alpha);
                 context.fill_preserve();
             });
@@ -30,6 +47,37 @@ pub fn paint_op(op: &Operation, style: &StyleCollection, context: &Context) {
                 context.stroke();
             });
         }),
+        Operation::Stroke(li, si) => style.get_for(li, si).map_or((), |s| {
+            stroke_color(s).map(|color| {
+                context.set_line_width(s.strokeWidth);
+                context.set_source_rgba(color.red, color.green, color.blue, color.alpha);
+                context.stroke();
+            });
+        }),
+        Operation::Marker(p, li, si) => style.get_for(li, si).map_or((), |s| {
+            context.new_path();
+            context.arc(p.x, p.y, s.markerSize / 2.0, 0.0, 2.0 * PI);
+            s.fillColor.map(|color| {
+                context.set_source_rgba(color.red, color.green, color.blue, color.alpha);
+                context.fill_preserve();
+            });
+            s.strokeColor.map(|color| {
+                context.set_line_width(s.strokeWidth);
+                context.set_source_rgba(color.red, color.green, color.blue, color.alpha);
+                context.stroke();
+            });
+            context.new_path();
+        }),
+        Operation::Clip => {
+            context.set_fill_rule(FillRule::EvenOdd);
+            context.clip();
+        }
+        Operation::Shadow(color) => {
+            context.set_fill_rule(FillRule::Winding);
+            context.set_source_rgba(color.red, color.green, color.blue, color.alpha);
+            context.fill();
+            context.reset_clip();
+        }
     }
 }
 
@@ -51,9 +99,11 @@ pub fn paint_op_debug(op: &Operation, style: &StyleCollection, context: &Context
             println!("LINE {} {}", p.x, p.y);
             context.line_to(p.x, p.y)
         }
-        Operation::Paint(li, si) => style.get_for(li, si).map_or((), |s| {
+        Operation::Paint(li, si, shade) => style.get_for(li, si).map_or((), |s| {
             s.fillColor.map(|color| {
+                let color = shade_color(color, *shade);
                 println!("FILL {} {} {}", color.red, color.green, color.blue,);
+                context.set_fill_rule(FillRule::EvenOdd);
                 context.set_source_rgba(color.red, color.green, color.blue, color.alpha);
                 context.fill_preserve();
             });
@@ -68,5 +118,28 @@ pub fn paint_op_debug(op: &Operation, style: &StyleCollection, context: &Context
                 context.stroke();
             });
         }),
+        Operation::Stroke(li, si) => style.get_for(li, si).map_or((), |s| {
+            stroke_color(s).map(|color| {
+                println!(
+                    "STROKE OPEN {} {} {} {}",
+                    s.strokeWidth, color.red, color.green, color.blue,
+                );
+                context.set_line_width(s.strokeWidth);
+                context.set_source_rgba(color.red, color.green, color.blue, color.alpha);
+                context.stroke();
+            });
+        }),
+        Operation::Marker(p, _, _) => {
+            println!("MARKER {} {}", p.x, p.y);
+            paint_op(op, style, context)
+        }
+        Operation::Clip => {
+            println!("CLIP");
+            paint_op(op, style, context)
+        }
+        Operation::Shadow(color) => {
+            println!("SHADOW {} {} {} {}", color.red, color.green, color.blue, color.alpha);
+            paint_op(op, style, context)
+        }
     }
 }
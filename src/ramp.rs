@@ -0,0 +1,422 @@
+use style::Color;
+
+/// How values between two stops are mapped.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    Linear,
+    /// `{"exponential": base}`, values further along growing faster when
+    /// the base is above 1.
+    Exponential(f64),
+    /// The value of the stop at or below, no blending.
+    Step,
+}
+
+impl Default for Interpolation {
+    fn default() -> Interpolation {
+        Interpolation::Linear
+    }
+}
+
+/// Where colours are blended. Lab and HCL give even perceived steps, HCL
+/// going around the hue circle.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpace {
+    Rgb,
+    Lab,
+    Hcl,
+}
+
+impl Default for ColorSpace {
+    fn default() -> ColorSpace {
+        ColorSpace::Rgb
+    }
+}
+
+/// A colour as written in an interpolated style: a single colour,
+/// `[[value, colour], ...]` stops, or a named palette spread over a domain.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ColorRampConfig {
+    Fixed(String),
+    Stops(Vec<(f64, String)>),
+    Palette {
+        palette: String,
+        domain: [f64; 2],
+        #[serde(default)]
+        reverse: bool,
+    },
+}
+
+/// A width as written in an interpolated style, fixed or `[[value, width], ...]`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum WidthRampConfig {
+    Fixed(f64),
+    Stops(Vec<(f64, f64)>),
+}
+
+#[derive(Clone, Debug)]
+pub struct ColorRamp {
+    stops: Vec<(f64, Color)>,
+    interpolation: Interpolation,
+    space: ColorSpace,
+}
+
+#[derive(Clone, Debug)]
+pub struct WidthRamp {
+    stops: Vec<(f64, f64)>,
+    interpolation: Interpolation,
+}
+
+const VIRIDIS: &[&str] = &[
+    "#440154", "#472d7b", "#3b528b", "#2c728e", "#21918c", "#28ae80", "#5ec962", "#addc30",
+    "#fde725",
+];
+const MAGMA: &[&str] = &[
+    "#000004", "#1c1044", "#4f127b", "#812581", "#b5367a", "#e55964", "#fb8761", "#fec287",
+    "#fcfdbf",
+];
+const INFERNO: &[&str] = &[
+    "#000004", "#1f0c48", "#550f6d", "#88226a", "#ba3655", "#e35933", "#f98e09", "#f9cb35",
+    "#fcffa4",
+];
+const PLASMA: &[&str] = &[
+    "#0d0887", "#47039f", "#7301a8", "#9c179e", "#bd3786", "#d8576b", "#ed7953", "#fba238",
+    "#f0f921",
+];
+const BLUES: &[&str] = &[
+    "#f7fbff", "#deebf7", "#c6dbef", "#9ecae1", "#6baed6", "#4292c6", "#2171b5", "#08519c",
+    "#08306b",
+];
+const GREENS: &[&str] = &[
+    "#f7fcf5", "#e5f5e0", "#c7e9c0", "#a1d99b", "#74c476", "#41ab5d", "#238b45", "#006d2c",
+    "#00441b",
+];
+const GREYS: &[&str] = &[
+    "#ffffff", "#f0f0f0", "#d9d9d9", "#bdbdbd", "#969696", "#737373", "#525252", "#252525",
+    "#000000",
+];
+const ORANGES: &[&str] = &[
+    "#fff5eb", "#fee6ce", "#fdd0a2", "#fdae6b", "#fd8d3c", "#f16913", "#d94801", "#a63603",
+    "#7f2704",
+];
+const PURPLES: &[&str] = &[
+    "#fcfbfd", "#efedf5", "#dadaeb", "#bcbddc", "#9e9ac8", "#807dba", "#6a51a3", "#54278f",
+    "#3f007d",
+];
+const REDS: &[&str] = &[
+    "#fff5f0", "#fee0d2", "#fcbba1", "#fc9272", "#fb6a4a", "#ef3b2c", "#cb181d", "#a50f15",
+    "#67000d",
+];
+const YL_GN_BU: &[&str] = &[
+    "#ffffd9", "#edf8b1", "#c7e9b4", "#7fcdbb", "#41b6c4", "#1d91c0", "#225ea8", "#253494",
+    "#081d58",
+];
+const YL_OR_RD: &[&str] = &[
+    "#ffffcc", "#ffeda0", "#fed976", "#feb24c", "#fd8d3c", "#fc4e2a", "#e31a1c", "#bd0026",
+    "#800026",
+];
+const RD_BU: &[&str] = &[
+    "#67001f", "#b2182b", "#d6604d", "#f4a582", "#fddbc7", "#f7f7f7", "#d1e5f0", "#92c5de",
+    "#4393c3", "#2166ac", "#053061",
+];
+const RD_YL_BU: &[&str] = &[
+    "#a50026", "#d73027", "#f46d43", "#fdae61", "#fee090", "#ffffbf", "#e0f3f8", "#abd9e9",
+    "#74add1", "#4575b4", "#313695",
+];
+const SPECTRAL: &[&str] = &[
+    "#9e0142", "#d53e4f", "#f46d43", "#fdae61", "#fee08b", "#ffffbf", "#e6f598", "#abdda4",
+    "#66c2a5", "#3288bd", "#5e4fa2",
+];
+
+/// Matplotlib's perceptual maps and ColorBrewer schemes, names being
+/// matched without regard to case.
+fn palette(name: &str) -> Option<&'static [&'static str]> {
+    Some(match name.to_lowercase().as_str() {
+        "viridis" => VIRIDIS,
+        "magma" => MAGMA,
+        "inferno" => INFERNO,
+        "plasma" => PLASMA,
+        "blues" => BLUES,
+        "greens" => GREENS,
+        "greys" => GREYS,
+        "oranges" => ORANGES,
+        "purples" => PURPLES,
+        "reds" => REDS,
+        "ylgnbu" => YL_GN_BU,
+        "ylorrd" => YL_OR_RD,
+        "rdbu" => RD_BU,
+        "rdylbu" => RD_YL_BU,
+        "spectral" => SPECTRAL,
+        _ => return None,
+    })
+}
+
+fn check_order<T>(stops: &[(f64, T)]) -> Result<(), String> {
+    if stops.is_empty() {
+        return Err("a ramp needs at least one stop".to_owned());
+    }
+    if stops.windows(2).any(|w| !(w[0].0 < w[1].0)) {
+        return Err("ramp stops must be in increasing order".to_owned());
+    }
+    Ok(())
+}
+
+/// The stops around `value` and how far along from the first to the
+/// second it lies, values beyond the ends sticking to them.
+fn locate(values: &[f64], value: f64, interpolation: Interpolation) -> (usize, usize, f64) {
+    let last = values.len() - 1;
+    if value <= values[0] {
+        return (0, 0, 0.0);
+    }
+    if value >= values[last] {
+        return (last, last, 0.0);
+    }
+    let i = values.iter().rposition(|v| *v <= value).unwrap_or(0);
+    let (a, b) = (values[i], values[i + 1]);
+    let t = match interpolation {
+        Interpolation::Step => return (i, i, 0.0),
+        Interpolation::Exponential(base) if (base - 1.0).abs() > 1e-9 => {
+            (base.powf(value - a) - 1.0) / (base.powf(b - a) - 1.0)
+        }
+        _ => (value - a) / (b - a),
+    };
+    (i, i + 1, t)
+}
+
+impl WidthRamp {
+    pub fn from_config(config: &WidthRampConfig, interpolation: Interpolation) -> Result<WidthRamp, String> {
+        let stops = match config {
+            WidthRampConfig::Fixed(w) => vec![(0.0, *w)],
+            WidthRampConfig::Stops(stops) => stops.clone(),
+        };
+        check_order(&stops)?;
+        Ok(WidthRamp {
+            stops,
+            interpolation,
+        })
+    }
+
+    pub fn at(&self, value: f64) -> f64 {
+        let values: Vec<f64> = self.stops.iter().map(|s| s.0).collect();
+        let (i, j, t) = locate(&values, value, self.interpolation);
+        self.stops[i].1 + (self.stops[j].1 - self.stops[i].1) * t
+    }
+}
+
+impl ColorRamp {
+    pub fn from_config(
+        config: &ColorRampConfig,
+        interpolation: Interpolation,
+        space: ColorSpace,
+    ) -> Result<ColorRamp, String> {
+        let stops = match config {
+            ColorRampConfig::Fixed(c) => vec![(0.0, Color::from_string(c)?)],
+            ColorRampConfig::Stops(stops) => stops
+                .iter()
+                .map(|(v, c)| Color::from_string(c).map(|c| (*v, c)))
+                .collect::<Result<Vec<(f64, Color)>, String>>()?,
+            ColorRampConfig::Palette {
+                palette: name,
+                domain,
+                reverse,
+            } => {
+                let colors = palette(name).ok_or_else(|| format!("unknown palette \"{}\"", name))?;
+                if !(domain[0] < domain[1]) {
+                    return Err("a palette domain goes from low to high".to_owned());
+                }
+                let step = (domain[1] - domain[0]) / (colors.len() - 1) as f64;
+                let mut colors: Vec<Color> = colors
+                    .iter()
+                    .map(|c| Color::from_string(c))
+                    .collect::<Result<Vec<Color>, String>>()?;
+                if *reverse {
+                    colors.reverse();
+                }
+                colors
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, c)| (domain[0] + step * i as f64, c))
+                    .collect()
+            }
+        };
+        check_order(&stops)?;
+        Ok(ColorRamp {
+            stops,
+            interpolation,
+            space,
+        })
+    }
+
+    pub fn at(&self, value: f64) -> Color {
+        let values: Vec<f64> = self.stops.iter().map(|s| s.0).collect();
+        let (i, j, t) = locate(&values, value, self.interpolation);
+        if i == j {
+            self.stops[i].1
+        } else {
+            mix(self.stops[i].1, self.stops[j].1, t, self.space)
+        }
+    }
+
+    /// Scales the alpha of every stop.
+    pub fn fade(&mut self, opacity: f64) {
+        for stop in self.stops.iter_mut() {
+            stop.1.alpha *= opacity;
+        }
+    }
+}
+
+fn to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(c: f64) -> f64 {
+    let c = if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    c.max(0.0).min(1.0)
+}
+
+// D65 white point
+const XN: f64 = 0.950_47;
+const ZN: f64 = 1.088_83;
+const DELTA: f64 = 6.0 / 29.0;
+
+fn lab_f(t: f64) -> f64 {
+    if t > DELTA * DELTA * DELTA {
+        t.cbrt()
+    } else {
+        t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+    }
+}
+
+fn lab_f_inv(t: f64) -> f64 {
+    if t > DELTA {
+        t * t * t
+    } else {
+        3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+    }
+}
+
+fn to_lab(c: Color) -> [f64; 3] {
+    let (r, g, b) = (to_linear(c.red), to_linear(c.green), to_linear(c.blue));
+    let x = 0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175_0 * b;
+    let z = 0.019_333_9 * r + 0.119_192_0 * g + 0.950_304_1 * b;
+    let (fx, fy, fz) = (lab_f(x / XN), lab_f(y), lab_f(z / ZN));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn from_lab(lab: [f64; 3], alpha: f64) -> Color {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let x = XN * lab_f_inv(fy + lab[1] / 500.0);
+    let y = lab_f_inv(fy);
+    let z = ZN * lab_f_inv(fy - lab[2] / 200.0);
+    let r = 3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z;
+    let g = -0.969_266_0 * x + 1.876_010_8 * y + 0.041_556_0 * z;
+    let b = 0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z;
+    Color::rgba(from_linear(r), from_linear(g), from_linear(b), alpha)
+}
+
+/// Lightness, chroma and hue in degrees.
+fn to_hcl(c: Color) -> [f64; 3] {
+    let lab = to_lab(c);
+    let hue = lab[2].atan2(lab[1]).to_degrees();
+    [lab[0], lab[1].hypot(lab[2]), if hue < 0.0 { hue + 360.0 } else { hue }]
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+fn mix(a: Color, b: Color, t: f64, space: ColorSpace) -> Color {
+    let alpha = lerp(a.alpha, b.alpha, t);
+    match space {
+        ColorSpace::Rgb => Color::rgba(
+            lerp(a.red, b.red, t),
+            lerp(a.green, b.green, t),
+            lerp(a.blue, b.blue, t),
+            alpha,
+        ),
+        ColorSpace::Lab => {
+            let (la, lb) = (to_lab(a), to_lab(b));
+            from_lab(
+                [lerp(la[0], lb[0], t), lerp(la[1], lb[1], t), lerp(la[2], lb[2], t)],
+                alpha,
+            )
+        }
+        ColorSpace::Hcl => {
+            let (ha, hb) = (to_hcl(a), to_hcl(b));
+            // a grey has no hue of its own, it takes the other one's
+            let (mut h0, mut h1) = (ha[2], hb[2]);
+            if ha[1] < 1e-6 {
+                h0 = h1;
+            } else if hb[1] < 1e-6 {
+                h1 = h0;
+            }
+            let mut dh = h1 - h0;
+            if dh > 180.0 {
+                dh -= 360.0;
+            } else if dh < -180.0 {
+                dh += 360.0;
+            }
+            let (l, c, h) = (lerp(ha[0], hb[0], t), lerp(ha[1], hb[1], t), (h0 + dh * t).to_radians());
+            from_lab([l, c * h.cos(), c * h.sin()], alpha)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ramp::{ColorRamp, ColorRampConfig, ColorSpace, Interpolation, WidthRamp, WidthRampConfig};
+
+    fn stops() -> ColorRampConfig {
+        ColorRampConfig::Stops(vec![(0.0, "black".to_owned()), (10.0, "white".to_owned())])
+    }
+
+    #[test]
+    fn interpolate() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-3;
+
+        let rgb = ColorRamp::from_config(&stops(), Interpolation::Linear, ColorSpace::Rgb).unwrap();
+        assert!(close(rgb.at(5.0).red, 0.5));
+        assert!(close(rgb.at(-3.0).red, 0.0));
+        assert!(close(rgb.at(30.0).green, 1.0));
+
+        // halfway in Lab, black to white gives a grey darker than the RGB average
+        let lab = ColorRamp::from_config(&stops(), Interpolation::Linear, ColorSpace::Lab).unwrap();
+        assert!(close(lab.at(5.0).red, 0.466));
+        let hcl = ColorRamp::from_config(&stops(), Interpolation::Linear, ColorSpace::Hcl).unwrap();
+        assert!(close(hcl.at(5.0).red, lab.at(5.0).red));
+        assert!(close(hcl.at(0.0).blue, 0.0));
+
+        let step = ColorRamp::from_config(&stops(), Interpolation::Step, ColorSpace::Rgb).unwrap();
+        assert_eq!(step.at(9.9).red, 0.0);
+        assert_eq!(step.at(10.0).red, 1.0);
+
+        let width = WidthRamp::from_config(
+            &WidthRampConfig::Stops(vec![(0.0, 1.0), (2.0, 5.0)]),
+            Interpolation::Exponential(2.0),
+        ).unwrap();
+        assert!(close(width.at(1.0), 1.0 + 4.0 / 3.0));
+
+        let palette = ColorRampConfig::Palette {
+            palette: "Viridis".to_owned(),
+            domain: [0.0, 80.0],
+            reverse: true,
+        };
+        let viridis = ColorRamp::from_config(&palette, Interpolation::Linear, ColorSpace::Rgb).unwrap();
+        assert!(close(viridis.at(0.0).red, 253.0 / 255.0));
+
+        let unordered = ColorRampConfig::Stops(vec![(1.0, "red".to_owned()), (0.0, "blue".to_owned())]);
+        assert!(ColorRamp::from_config(&unordered, Interpolation::Linear, ColorSpace::Rgb).is_err());
+    }
+}
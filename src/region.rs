@@ -0,0 +1,854 @@
+use geojson::{GeoJson, Geometry, Value};
+use lingua::{PlaneKind, PlaneT, Point, Point2D};
+use manifest::RegionConfig;
+use std::fs;
+use validate::normal;
+
+/// The part of the scene to load, in scene coordinates. Only x and y are
+/// considered.
+#[derive(Debug, Clone)]
+pub struct Region {
+    /// Counter-clockwise, without repeating the first point.
+    ring: Vec<Point2D>,
+    min: Point2D,
+    max: Point2D,
+    convex: bool,
+    /// Cut planes crossing the boundary instead of keeping them whole.
+    clip: bool,
+}
+
+fn cross(o: &Point2D, a: &Point2D, b: &Point2D) -> f64 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+/// Whether segments `ab` and `cd` meet, touching included.
+fn crosses(a: &Point2D, b: &Point2D, c: &Point2D, d: &Point2D) -> bool {
+    let (d1, d2) = (cross(a, b, c), cross(a, b, d));
+    let (d3, d4) = (cross(c, d, a), cross(c, d, b));
+    if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+    {
+        return true;
+    }
+    let within = |o: &Point2D, p: &Point2D, q: &Point2D| {
+        q.x >= o.x.min(p.x) && q.x <= o.x.max(p.x) && q.y >= o.y.min(p.y) && q.y <= o.y.max(p.y)
+    };
+    (d1 == 0.0 && within(a, b, c))
+        || (d2 == 0.0 && within(a, b, d))
+        || (d3 == 0.0 && within(c, d, a))
+        || (d4 == 0.0 && within(c, d, b))
+}
+
+/// Parameters along `ab` and `cd` where the two segments cross, `None`
+/// when they do not or are parallel.
+fn meet(a: &Point2D, b: &Point2D, c: &Point2D, d: &Point2D) -> Option<(f64, f64)> {
+    let (r, s, ac) = (b - a, d - c, c - a);
+    let denom = r.x * s.y - r.y * s.x;
+    if denom == 0.0 {
+        return None;
+    }
+    let t = (ac.x * s.y - ac.y * s.x) / denom;
+    let u = (ac.x * r.y - ac.y * r.x) / denom;
+    if t >= 0.0 && t <= 1.0 && u >= 0.0 && u <= 1.0 {
+        Some((t, u))
+    } else {
+        None
+    }
+}
+
+/// The parameter along `ab` of the point of the segment within `eps` of
+/// `p`, if any.
+fn on_segment(p: &Point2D, a: &Point2D, b: &Point2D, eps: f64) -> Option<f64> {
+    let ab = b - a;
+    let length2 = ab.norm_squared();
+    if length2 == 0.0 {
+        return None;
+    }
+    let t = (p - a).dot(&ab) / length2;
+    if t < 0.0 || t > 1.0 || (a + ab * t - p).norm() > eps {
+        None
+    } else {
+        Some(t)
+    }
+}
+
+/// Where `ab` is to be cut by a set of edges, as parameters from 0 to 1:
+/// crossings and edge ends lying on it.
+fn cuts(a: &Point2D, b: &Point2D, edges: &[(Point2D, Point2D)], eps: f64) -> Vec<f64> {
+    let mut ts = vec![0.0, 1.0];
+    for (c, d) in edges {
+        ts.extend(meet(a, b, c, d).map(|m| m.0));
+        ts.extend(on_segment(c, a, b, eps));
+    }
+    ts.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    let step = eps / (b - a).norm().max(eps);
+    let mut out: Vec<f64> = Vec::with_capacity(ts.len());
+    for t in ts {
+        if out.last().map_or(true, |last| t - last > step) {
+            out.push(t);
+        }
+    }
+    if let Some(last) = out.last_mut() {
+        *last = 1.0;
+    }
+    out
+}
+
+fn ring_edges(ring: &[Point2D]) -> Vec<(Point2D, Point2D)> {
+    (0..ring.len())
+        .map(|i| (ring[i], ring[(i + 1) % ring.len()]))
+        .collect()
+}
+
+/// Even-odd test against one ring.
+fn encloses(ring: &[Point2D], p: &Point2D) -> bool {
+    let mut inside = false;
+    for (a, b) in ring_edges(ring) {
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn flat(p: &Point) -> Point2D {
+    Point2D::new(p.x, p.y)
+}
+
+fn lerp(p: &Point, q: &Point, t: f64) -> Point {
+    p + (q - p) * t
+}
+
+/// A ring without its closing point.
+fn open(ring: &[Point]) -> Vec<Point> {
+    let mut ring = ring.to_vec();
+    if ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+    ring
+}
+
+/// One step of Sutherland-Hodgman: the part of an open ring where `side`
+/// is not negative, heights being interpolated along cut edges.
+fn clip_half<F: Fn(&Point) -> f64>(input: &[Point], side: F) -> Vec<Point> {
+    let mut output = Vec::with_capacity(input.len() + 2);
+    for (i, q) in input.iter().enumerate() {
+        let p = &input[(i + input.len() - 1) % input.len()];
+        let (sp, sq) = (side(p), side(q));
+        if sq >= 0.0 {
+            if sp < 0.0 {
+                output.push(p + (q - p) * (sp / (sp - sq)));
+            }
+            output.push(*q);
+        } else if sp >= 0.0 {
+            output.push(p + (q - p) * (sp / (sp - sq)));
+        }
+    }
+    output
+}
+
+fn is_convex(ring: &[Point2D]) -> bool {
+    let n = ring.len();
+    (0..n).all(|i| cross(&ring[i], &ring[(i + 1) % n], &ring[(i + 2) % n]) >= 0.0)
+}
+
+fn first_ring(geometry: &Geometry) -> Option<Vec<Vec<f64>>> {
+    match geometry.value {
+        Value::Polygon(ref rings) => rings.get(0).cloned(),
+        Value::MultiPolygon(ref polys) => polys.get(0).and_then(|p| p.get(0)).cloned(),
+        _ => None,
+    }
+}
+
+fn read_ring(path: &::std::path::Path) -> Result<Vec<Point2D>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let gj = content
+        .parse::<GeoJson>()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let ring = match gj {
+        GeoJson::Geometry(ref g) => first_ring(g),
+        GeoJson::Feature(ref f) => f.geometry.as_ref().and_then(first_ring),
+        GeoJson::FeatureCollection(ref fc) => fc
+            .features
+            .iter()
+            .filter_map(|f| f.geometry.as_ref().and_then(first_ring))
+            .next(),
+    };
+    ring.map(|r| {
+        r.iter()
+            .filter(|pos| pos.len() >= 2)
+            .map(|pos| Point2D::new(pos[0], pos[1]))
+            .collect()
+    }).ok_or_else(|| format!("{}: no polygon found", path.display()))
+}
+
+impl Region {
+    pub fn from_config(config: &RegionConfig) -> Result<Region, String> {
+        let mut ring = match (config.bbox, config.polygon.as_ref()) {
+            (Some(b), None) => {
+                if b[0] >= b[2] || b[1] >= b[3] {
+                    return Err("bbox must be [minX, minY, maxX, maxY]".to_owned());
+                }
+                vec![
+                    Point2D::new(b[0], b[1]),
+                    Point2D::new(b[2], b[1]),
+                    Point2D::new(b[2], b[3]),
+                    Point2D::new(b[0], b[3]),
+                ]
+            }
+            (None, Some(path)) => read_ring(path)?,
+            _ => return Err("expected exactly one of bbox or polygon".to_owned()),
+        };
+        if ring.len() > 1 && ring.first() == ring.last() {
+            ring.pop();
+        }
+        if ring.len() < 3 {
+            return Err("the region needs at least 3 points".to_owned());
+        }
+        let area: f64 = (0..ring.len())
+            .map(|i| {
+                let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+                a.x * b.y - b.x * a.y
+            }).sum();
+        if area < 0.0 {
+            ring.reverse();
+        }
+        let convex = is_convex(&ring);
+        let min = ring.iter().fold(ring[0], |m, p| Point2D::new(m.x.min(p.x), m.y.min(p.y)));
+        let max = ring.iter().fold(ring[0], |m, p| Point2D::new(m.x.max(p.x), m.y.max(p.y)));
+        Ok(Region {
+            ring,
+            min,
+            max,
+            convex,
+            clip: config.clip,
+        })
+    }
+
+    /// `[minX, minY, maxX, maxY]`
+    pub fn bounds(&self) -> [f64; 4] {
+        [self.min.x, self.min.y, self.max.x, self.max.y]
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        if x < self.min.x || x > self.max.x || y < self.min.y || y > self.max.y {
+            return false;
+        }
+        let p = Point2D::new(x, y);
+        let n = self.ring.len();
+        if self.convex {
+            return (0..n).all(|i| cross(&self.ring[i], &self.ring[(i + 1) % n], &p) >= 0.0);
+        }
+        encloses(&self.ring, &p)
+    }
+
+    /// Distance under which points are taken as one.
+    fn tolerance(&self) -> f64 {
+        1e-9 * [self.min.x, self.min.y, self.max.x, self.max.y]
+            .iter()
+            .fold(1.0, |m: f64, v| m.max(v.abs()))
+    }
+
+    /// Inside or on the boundary.
+    fn covers(&self, p: &Point2D, eps: f64) -> bool {
+        self.contains(p.x, p.y)
+            || ring_edges(&self.ring)
+                .iter()
+                .any(|(a, b)| on_segment(p, a, b, eps).is_some())
+    }
+
+    /// Whether any part of the planes may lie in the region, erring on
+    /// the side of keeping them.
+    fn touches(&self, planes: &[PlaneT]) -> bool {
+        let points = || planes.iter().flat_map(|p| p.points.iter());
+        if points().any(|p| self.contains(p.x, p.y)) {
+            return true;
+        }
+        let (mut min, mut max) = (
+            Point2D::new(::std::f64::MAX, ::std::f64::MAX),
+            Point2D::new(::std::f64::MIN, ::std::f64::MIN),
+        );
+        for p in points() {
+            min = Point2D::new(min.x.min(p.x), min.y.min(p.y));
+            max = Point2D::new(max.x.max(p.x), max.y.max(p.y));
+        }
+        if max.x < self.min.x || min.x > self.max.x || max.y < self.min.y || min.y > self.max.y {
+            return false;
+        }
+        if self
+            .ring
+            .iter()
+            .any(|r| r.x >= min.x && r.x <= max.x && r.y >= min.y && r.y <= max.y)
+        {
+            return true;
+        }
+        // all vertices outside and no corner within reach: only an edge
+        // crossing the boundary can still bring the planes in
+        let n = self.ring.len();
+        planes.iter().any(|plane| {
+            let closed = plane.kind == PlaneKind::Polygon || plane.kind == PlaneKind::Ground;
+            Some(&plane.points)
+                .into_iter()
+                .chain(plane.holes.iter())
+                .any(|ring| {
+                    let closing = if closed && ring.len() > 2 {
+                        Some((ring[ring.len() - 1], ring[0]))
+                    } else {
+                        None
+                    };
+                    ring.windows(2)
+                        .map(|w| (w[0], w[1]))
+                        .chain(closing)
+                        .any(|(p, q)| {
+                            let (p, q) = (Point2D::new(p.x, p.y), Point2D::new(q.x, q.y));
+                            (0..n).any(|i| crosses(&p, &q, &self.ring[i], &self.ring[(i + 1) % n]))
+                        })
+                })
+        })
+    }
+
+    fn side(&self, edge: usize, p: &Point) -> f64 {
+        let a = self.ring[edge];
+        let b = self.ring[(edge + 1) % self.ring.len()];
+        cross(&a, &b, &Point2D::new(p.x, p.y))
+    }
+
+    /// Sutherland-Hodgman against a convex region.
+    fn clip_ring(&self, ring: &[Point]) -> Vec<Point> {
+        let mut output = open(ring);
+        for edge in 0..self.ring.len() {
+            output = clip_half(&output, |p| self.side(edge, p));
+            if output.is_empty() {
+                return output;
+            }
+        }
+        if let Some(first) = output.first().cloned() {
+            output.push(first);
+        }
+        output
+    }
+
+    /// Cyrus-Beck on each segment, joining the visible parts that follow
+    /// each other.
+    fn clip_line(&self, points: &[Point]) -> Vec<Vec<Point>> {
+        let mut lines: Vec<Vec<Point>> = Vec::new();
+        let mut open = false;
+        for pair in points.windows(2) {
+            let (p, q) = (pair[0], pair[1]);
+            let (mut t0, mut t1) = (0.0f64, 1.0f64);
+            for edge in 0..self.ring.len() {
+                let (sp, sq) = (self.side(edge, &p), self.side(edge, &q));
+                if sp < 0.0 && sq < 0.0 {
+                    t0 = 1.0;
+                    t1 = 0.0;
+                    break;
+                }
+                if sp < 0.0 {
+                    t0 = t0.max(sp / (sp - sq));
+                } else if sq < 0.0 {
+                    t1 = t1.min(sp / (sp - sq));
+                }
+            }
+            if t0 > t1 {
+                open = false;
+                continue;
+            }
+            let end = p + (q - p) * t1;
+            if open && t0 == 0.0 {
+                lines.last_mut().unwrap().push(end);
+            } else {
+                lines.push(vec![p + (q - p) * t0, end]);
+            }
+            open = t1 == 1.0;
+        }
+        lines
+    }
+
+    /// Lines against any region: each segment is cut wherever it meets the
+    /// boundary, the pieces within being kept.
+    fn clip_line_any(&self, points: &[Point]) -> Vec<Vec<Point>> {
+        let eps = self.tolerance();
+        let edges = ring_edges(&self.ring);
+        let mut lines: Vec<Vec<Point>> = Vec::new();
+        let mut open = false;
+        for pair in points.windows(2) {
+            let (p, q) = (pair[0], pair[1]);
+            for w in cuts(&flat(&p), &flat(&q), &edges, eps).windows(2) {
+                if !self.covers(&flat(&lerp(&p, &q, (w[0] + w[1]) / 2.0)), eps) {
+                    open = false;
+                    continue;
+                }
+                let end = lerp(&p, &q, w[1]);
+                if open {
+                    lines.last_mut().unwrap().push(end);
+                } else {
+                    lines.push(vec![lerp(&p, &q, w[0]), end]);
+                }
+                open = true;
+            }
+        }
+        lines
+    }
+
+    /// A polygon against any region, seen from above: its boundary within
+    /// the region and the region's boundary within it outline the parts
+    /// left, heights on the region's boundary being taken from the plane
+    /// of normal `n`.
+    fn clip_flat(&self, plane: &PlaneT, n: [f64; 3]) -> Vec<PlaneT> {
+        let eps = self.tolerance();
+        let origin = plane.points[0];
+        let height =
+            |p: &Point2D| origin.z - (n[0] * (p.x - origin.x) + n[1] * (p.y - origin.y)) / n[2];
+        // outer ring counter-clockwise, holes clockwise
+        let rings: Vec<Vec<Point>> = Some(&plane.points)
+            .into_iter()
+            .chain(plane.holes.iter())
+            .enumerate()
+            .map(|(i, ring)| {
+                let mut ring = open(ring);
+                if (normal(&ring)[2] < 0.0) == (i == 0) {
+                    ring.reverse();
+                }
+                ring
+            }).collect();
+        let flats: Vec<Vec<Point2D>> = rings.iter().map(|r| r.iter().map(flat).collect()).collect();
+        let plane_edges: Vec<(Point2D, Point2D)> =
+            flats.iter().flat_map(|r| ring_edges(r)).collect();
+        let region_edges = ring_edges(&self.ring);
+
+        let mut edges: Vec<(Point, Point)> = Vec::new();
+        let mut whole = true;
+        for ring in rings.iter() {
+            for i in 0..ring.len() {
+                let (p, q) = (ring[i], ring[(i + 1) % ring.len()]);
+                let (fp, fq) = (flat(&p), flat(&q));
+                let ts = cuts(&fp, &fq, &region_edges, eps);
+                whole &= ts.len() == 2;
+                for w in ts.windows(2) {
+                    let mid = flat(&lerp(&p, &q, (w[0] + w[1]) / 2.0));
+                    // along the boundary, kept when both insides are on
+                    // the same side
+                    let keep = match region_edges
+                        .iter()
+                        .find(|(c, d)| on_segment(&mid, c, d, eps).is_some())
+                    {
+                        Some((c, d)) => (fq - fp).dot(&(d - c)) > 0.0,
+                        None => self.contains(mid.x, mid.y),
+                    };
+                    if keep {
+                        edges.push((lerp(&p, &q, w[0]), lerp(&p, &q, w[1])));
+                    } else {
+                        whole = false;
+                    }
+                }
+            }
+        }
+        for (c, d) in region_edges.iter() {
+            for w in cuts(c, d, &plane_edges, eps).windows(2) {
+                let mid = c + (d - c) * ((w[0] + w[1]) / 2.0);
+                let within = flats.iter().fold(false, |inside, r| inside != encloses(r, &mid));
+                let along = plane_edges
+                    .iter()
+                    .any(|(a, b)| on_segment(&mid, a, b, eps).is_some());
+                if !within || along {
+                    continue;
+                }
+                let at = |t: f64| {
+                    let f = c + (d - c) * t;
+                    Point::new(f.x, f.y, height(&f))
+                };
+                edges.push((at(w[0]), at(w[1])));
+                whole = false;
+            }
+        }
+        if whole {
+            return vec![plane.clone()];
+        }
+
+        // chain the edges into rings
+        let near =
+            |a: &Point, b: &Point| (a.x - b.x).abs() <= 4.0 * eps && (a.y - b.y).abs() <= 4.0 * eps;
+        let mut used = vec![false; edges.len()];
+        let (mut outers, mut holes): (Vec<Vec<Point>>, Vec<Vec<Point>>) = (Vec::new(), Vec::new());
+        for first in 0..edges.len() {
+            if used[first] {
+                continue;
+            }
+            used[first] = true;
+            let mut ring = vec![edges[first].0];
+            let mut end = edges[first].1;
+            let closed = loop {
+                if near(&end, &ring[0]) {
+                    break true;
+                }
+                match (0..edges.len()).find(|j| !used[*j] && near(&edges[*j].0, &end)) {
+                    Some(j) => {
+                        used[j] = true;
+                        ring.push(edges[j].0);
+                        end = edges[j].1;
+                    }
+                    None => break false,
+                }
+            };
+            if !closed || ring.len() < 3 {
+                continue;
+            }
+            let area = normal(&ring)[2];
+            ring.push(ring[0]);
+            if area > 0.0 {
+                outers.push(ring);
+            } else if area < 0.0 {
+                holes.push(ring);
+            }
+        }
+
+        // back to the winding of the plane
+        let reversed = normal(&plane.points)[2] < 0.0;
+        let wind = |mut ring: Vec<Point>| {
+            if reversed {
+                ring.reverse();
+            }
+            ring
+        };
+        let mut parts: Vec<PlaneT> = outers
+            .into_iter()
+            .map(|points| PlaneT {
+                points,
+                holes: Vec::new(),
+                ..plane.clone()
+            }).collect();
+        for hole in holes {
+            let p = flat(&hole[0]);
+            let outer = parts.iter_mut().find(|part| {
+                let ring: Vec<Point2D> = part.points.iter().map(flat).collect();
+                encloses(&ring, &p)
+            });
+            if let Some(part) = outer {
+                part.holes.push(wind(hole));
+            }
+        }
+        for part in parts.iter_mut() {
+            part.points = wind(part.points.clone());
+        }
+        parts
+    }
+
+    /// An upright plane against any region, cut into the upright strips
+    /// where its foot runs within the region.
+    fn clip_upright(&self, plane: &PlaneT, n: [f64; 3]) -> Vec<PlaneT> {
+        let length = (n[0] * n[0] + n[1] * n[1]).sqrt();
+        let (ux, uy) = (-n[1] / length, n[0] / length);
+        let origin = plane.points[0];
+        let along = |p: &Point| (p.x - origin.x) * ux + (p.y - origin.y) * uy;
+        let (low, high) = plane.points.iter().fold((::std::f64::MAX, ::std::f64::MIN), |(l, h), p| {
+            (l.min(along(p)), h.max(along(p)))
+        });
+        let foot = [
+            Point::new(origin.x + ux * low, origin.y + uy * low, 0.0),
+            Point::new(origin.x + ux * high, origin.y + uy * high, 0.0),
+        ];
+        let mut parts = Vec::new();
+        for span in self.clip_line_any(&foot) {
+            let (start, end) = (along(&span[0]), along(&span[span.len() - 1]));
+            let cut = |ring: &[Point]| {
+                let ring = clip_half(&open(ring), |p| along(p) - start);
+                let mut ring = clip_half(&ring, |p| end - along(p));
+                if let Some(first) = ring.first().cloned() {
+                    ring.push(first);
+                }
+                ring
+            };
+            let points = cut(&plane.points);
+            if points.len() < 4 {
+                continue;
+            }
+            let holes = plane
+                .holes
+                .iter()
+                .map(|h| cut(h))
+                .filter(|h| h.len() >= 4)
+                .collect();
+            parts.push(PlaneT {
+                points,
+                holes,
+                ..plane.clone()
+            });
+        }
+        parts
+    }
+
+    fn clip_plane(&self, plane: PlaneT) -> Vec<PlaneT> {
+        match plane.kind {
+            PlaneKind::Marker => vec![plane],
+            PlaneKind::Line => {
+                let lines = if self.convex {
+                    self.clip_line(&plane.points)
+                } else {
+                    self.clip_line_any(&plane.points)
+                };
+                lines
+                    .into_iter()
+                    .map(|points| PlaneT { points, ..plane.clone() })
+                    .collect()
+            }
+            PlaneKind::Polygon | PlaneKind::Ground if !self.convex => {
+                let n = normal(&plane.points);
+                let size = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+                if !(size > 0.0) {
+                    let p = plane.points[0];
+                    if self.contains(p.x, p.y) {
+                        vec![plane]
+                    } else {
+                        Vec::new()
+                    }
+                } else if n[2].abs() > 1e-6 * size {
+                    self.clip_flat(&plane, n)
+                } else {
+                    self.clip_upright(&plane, n)
+                }
+            }
+            PlaneKind::Polygon | PlaneKind::Ground => {
+                let points = self.clip_ring(&plane.points);
+                if points.len() < 4 {
+                    return Vec::new();
+                }
+                let holes = plane
+                    .holes
+                    .iter()
+                    .map(|h| self.clip_ring(h))
+                    .filter(|h| h.len() >= 4)
+                    .collect();
+                vec![PlaneT {
+                    points,
+                    holes,
+                    ..plane
+                }]
+            }
+        }
+    }
+
+    /// The planes of one feature as they should be loaded, `None` when
+    /// the feature lies outside of the region.
+    pub fn apply(&self, planes: Vec<PlaneT>) -> Option<Vec<PlaneT>> {
+        if !self.touches(&planes) {
+            return None;
+        }
+        if !self.clip {
+            return Some(planes);
+        }
+        let clipped: Vec<PlaneT> = planes
+            .into_iter()
+            .filter(|p| p.kind != PlaneKind::Marker || self.contains(p.points[0].x, p.points[0].y))
+            .flat_map(|p| self.clip_plane(p))
+            .collect();
+        if clipped.is_empty() {
+            None
+        } else {
+            Some(clipped)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lingua::{PlaneT, Point};
+    use manifest::RegionConfig;
+    use region::Region;
+    use std::env;
+    use std::fs;
+    use validate::normal;
+
+    fn bbox(clip: bool) -> Region {
+        Region::from_config(&RegionConfig {
+            bbox: Some([0.0, 0.0, 10.0, 10.0]),
+            polygon: None,
+            clip,
+        }).unwrap()
+    }
+
+    fn square(x: f64, y: f64, size: f64) -> PlaneT {
+        PlaneT::polygon(
+            0,
+            0,
+            vec![
+                Point::new(x, y, 0.0),
+                Point::new(x + size, y, 2.0),
+                Point::new(x + size, y + size, 2.0),
+                Point::new(x, y + size, 0.0),
+                Point::new(x, y, 0.0),
+            ],
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn drop_outside() {
+        let region = bbox(false);
+        assert!(region.apply(vec![square(20.0, 20.0, 1.0)]).is_none());
+        let kept = region.apply(vec![square(8.0, 8.0, 4.0)]).unwrap();
+        assert_eq!(kept[0].points.len(), 5);
+        // a region entirely inside a large feature
+        assert!(region.apply(vec![square(-5.0, -5.0, 30.0)]).is_some());
+
+        // crossing the region with every vertex and corner out of reach
+        let road = PlaneT::line(0, 0, vec![Point::new(-5.0, 5.0, 0.0), Point::new(15.0, 5.0, 0.0)]);
+        assert!(region.apply(vec![road]).is_some());
+        let strip = PlaneT::polygon(
+            0,
+            0,
+            vec![
+                Point::new(-5.0, 4.0, 0.0),
+                Point::new(15.0, 4.0, 0.0),
+                Point::new(15.0, 6.0, 0.0),
+                Point::new(-5.0, 6.0, 0.0),
+            ],
+            Vec::new(),
+        );
+        assert!(region.apply(vec![strip]).is_some());
+        let beside = PlaneT::line(0, 0, vec![Point::new(-5.0, 12.0, 0.0), Point::new(15.0, 12.0, 0.0)]);
+        assert!(region.apply(vec![beside]).is_none());
+    }
+
+    #[test]
+    fn cut_at_boundary() {
+        let region = bbox(true);
+        let cut = region.apply(vec![square(5.0, 5.0, 10.0)]).unwrap();
+        let points = &cut[0].points;
+        assert!(points.iter().all(|p| p.x <= 10.0 && p.y <= 10.0));
+        assert!(points.contains(&Point::new(10.0, 5.0, 1.0)));
+        assert_eq!(points.first(), points.last());
+
+        let line = PlaneT::line(
+            0,
+            0,
+            vec![
+                Point::new(-5.0, 5.0, 0.0),
+                Point::new(5.0, 5.0, 0.0),
+                Point::new(5.0, 15.0, 0.0),
+                Point::new(15.0, 15.0, 0.0),
+                Point::new(15.0, 5.0, 0.0),
+                Point::new(8.0, 5.0, 0.0),
+            ],
+        );
+        let lines = region.apply(vec![line]).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0].points,
+            vec![
+                Point::new(0.0, 5.0, 0.0),
+                Point::new(5.0, 5.0, 0.0),
+                Point::new(5.0, 10.0, 0.0),
+            ]
+        );
+        assert_eq!(
+            lines[1].points,
+            vec![Point::new(10.0, 5.0, 0.0), Point::new(8.0, 5.0, 0.0)]
+        );
+    }
+
+    /// A U open to the north, its arms 3 wide.
+    fn u_shape() -> Region {
+        let path = env::temp_dir().join("cardboard-region-u.geojson");
+        fs::write(
+            &path,
+            r#"{"type":"Polygon",
+                "coordinates":[[[0,0],[9,0],[9,9],[6,9],[6,3],[3,3],[3,9],[0,9],[0,0]]]}"#,
+        ).unwrap();
+        Region::from_config(&RegionConfig {
+            bbox: None,
+            polygon: Some(path),
+            clip: true,
+        }).unwrap()
+    }
+
+    fn area(points: &[Point]) -> f64 {
+        normal(points)[2] / 2.0
+    }
+
+    #[test]
+    fn cut_at_concave_boundary() {
+        let region = u_shape();
+
+        // a sloping square over the notch keeps a U, the region's inner
+        // corners getting the height of the slope
+        let slope = |x: f64, y: f64, size: f64| {
+            let corners = [(x, y), (x + size, y), (x + size, y + size), (x, y + size), (x, y)];
+            PlaneT::polygon(
+                0,
+                0,
+                corners.iter().map(|c| Point::new(c.0, c.1, c.0)).collect(),
+                Vec::new(),
+            )
+        };
+        let cut = region.apply(vec![slope(2.0, 2.0, 5.0)]).unwrap();
+        assert_eq!(cut.len(), 1);
+        assert!((area(&cut[0].points) - 13.0).abs() < 1e-9);
+        assert!(cut[0].points.contains(&Point::new(3.0, 3.0, 3.0)));
+        assert!(cut[0].points.contains(&Point::new(6.0, 3.0, 6.0)));
+        assert!(cut[0].points.iter().all(|p| (p.z - p.x).abs() < 1e-9));
+        assert_eq!(cut[0].points.first(), cut[0].points.last());
+
+        // along the boundary, inside
+        let along = region.apply(vec![slope(6.0, 2.0, 2.0)]).unwrap();
+        assert!((area(&along[0].points) - 4.0).abs() < 1e-9);
+
+        // within the notch, nothing is left; within an arm, all of it
+        assert!(region.apply(vec![slope(4.0, 5.0, 1.0)]).is_none());
+        let whole = slope(0.5, 0.5, 1.0);
+        assert_eq!(region.apply(vec![whole.clone()]).unwrap()[0].points, whole.points);
+
+        // a bar across both arms comes out in two, a hole within is kept
+        let bar = PlaneT::polygon(
+            0,
+            0,
+            vec![
+                Point::new(1.0, 5.0, 0.0),
+                Point::new(8.0, 5.0, 0.0),
+                Point::new(8.0, 7.0, 0.0),
+                Point::new(1.0, 7.0, 0.0),
+            ],
+            vec![vec![
+                Point::new(1.5, 5.5, 0.0),
+                Point::new(1.5, 6.5, 0.0),
+                Point::new(2.5, 6.5, 0.0),
+                Point::new(2.5, 5.5, 0.0),
+            ]],
+        );
+        let mut parts = region.apply(vec![bar]).unwrap();
+        parts.sort_by(|a, b| a.points[0].x.partial_cmp(&b.points[0].x).unwrap());
+        assert_eq!(parts.len(), 2);
+        assert!((area(&parts[0].points) - 4.0).abs() < 1e-9);
+        assert_eq!(parts[0].holes.len(), 1);
+        assert!((area(&parts[1].points) - 4.0).abs() < 1e-9);
+        assert!(parts[1].holes.is_empty());
+
+        // an upright wall is cut in strips
+        let wall = PlaneT::polygon(
+            0,
+            0,
+            vec![
+                Point::new(1.0, 6.0, 0.0),
+                Point::new(8.0, 6.0, 0.0),
+                Point::new(8.0, 6.0, 3.0),
+                Point::new(1.0, 6.0, 3.0),
+            ],
+            Vec::new(),
+        );
+        let strips = region.apply(vec![wall]).unwrap();
+        assert_eq!(strips.len(), 2);
+        let xs: Vec<(f64, f64)> = strips
+            .iter()
+            .map(|s| {
+                s.points
+                    .iter()
+                    .fold((::std::f64::MAX, ::std::f64::MIN), |(l, h), p| (l.min(p.x), h.max(p.x)))
+            }).collect();
+        assert_eq!(xs, vec![(1.0, 3.0), (6.0, 8.0)]);
+
+        // and so is a line
+        let line = PlaneT::line(0, 0, vec![Point::new(1.0, 6.0, 0.0), Point::new(8.0, 6.0, 0.0)]);
+        let lines = region.apply(vec![line]).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].points, vec![Point::new(6.0, 6.0, 0.0), Point::new(8.0, 6.0, 0.0)]);
+    }
+}
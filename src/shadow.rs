@@ -0,0 +1,369 @@
+use lingua::{PlaneFlat, PlaneKind, PlaneT, Point};
+use nalgebra as na;
+use rayon::prelude::*;
+use sun::Sun;
+use validate::normal;
+
+/// Outlines of the shadows falling on a plane, all wound the same way
+/// around its normal but for the holes, so that filling them with the
+/// non-zero rule paints their union once.
+pub type Shadows = Vec<Vec<Point>>;
+
+/// Receivers facing the sun less than this (the cosine of the angle to
+/// it) are wholly in the shade.
+const GRAZING: f64 = 1e-3;
+
+fn along(n: [f64; 3], v: &na::Vector3<f64>) -> f64 {
+    n[0] * v.x + n[1] * v.y + n[2] * v.z
+}
+
+struct Face {
+    n: na::Vector3<f64>,
+    origin: Point,
+}
+
+fn face(points: &[Point]) -> Option<Face> {
+    let n = normal(points);
+    let n = na::Vector3::new(n[0], n[1], n[2]);
+    let length = n.norm();
+    if length > 0.0 && points.len() > 2 {
+        Some(Face {
+            n: n / length,
+            origin: points[0],
+        })
+    } else {
+        None
+    }
+}
+
+/// XY bounds, `[minx, miny, maxx, maxy]`.
+type Bounds = [f64; 4];
+
+fn bounds<'a, I: Iterator<Item = &'a Point>>(points: I) -> Bounds {
+    points.fold(
+        [::std::f64::MAX, ::std::f64::MAX, ::std::f64::MIN, ::std::f64::MIN],
+        |b, p| [b[0].min(p.x), b[1].min(p.y), b[2].max(p.x), b[3].max(p.y)],
+    )
+}
+
+fn meets(a: &Bounds, b: &Bounds) -> bool {
+    a[0] <= b[2] && b[0] <= a[2] && a[1] <= b[3] && b[1] <= a[3]
+}
+
+/// Occluders bucketed on a grid by the ground the shadow they may cast
+/// sweeps over.
+struct Grid {
+    bounds: Bounds,
+    size: usize,
+    cells: Vec<Vec<usize>>,
+    sweeps: Vec<Bounds>,
+}
+
+impl Grid {
+    fn cell(&self, x: f64, y: f64) -> (usize, usize) {
+        let b = &self.bounds;
+        let f = |v: f64, min: f64, max: f64| {
+            let t = if max > min { (v - min) / (max - min) } else { 0.0 };
+            ((t * self.size as f64) as usize).min(self.size - 1)
+        };
+        (f(x, b[0], b[2]), f(y, b[1], b[3]))
+    }
+
+    fn new(sweeps: Vec<Bounds>) -> Grid {
+        let bounds = sweeps.iter().fold(
+            [::std::f64::MAX, ::std::f64::MAX, ::std::f64::MIN, ::std::f64::MIN],
+            |a, b| [a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])],
+        );
+        let size = ((sweeps.len() as f64).sqrt().ceil() as usize).max(1).min(512);
+        let mut grid = Grid {
+            bounds,
+            size,
+            cells: vec![Vec::new(); size * size],
+            sweeps: Vec::new(),
+        };
+        for (i, s) in sweeps.iter().enumerate() {
+            let (x0, y0) = grid.cell(s[0], s[1]);
+            let (x1, y1) = grid.cell(s[2], s[3]);
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    grid.cells[y * size + x].push(i);
+                }
+            }
+        }
+        grid.sweeps = sweeps;
+        grid
+    }
+
+    /// Occluders whose sweep meets the bounds, in increasing order.
+    fn query(&self, b: &Bounds) -> Vec<usize> {
+        if self.sweeps.is_empty() || !meets(b, &self.bounds) {
+            return Vec::new();
+        }
+        let (x0, y0) = self.cell(b[0], b[1]);
+        let (x1, y1) = self.cell(b[2], b[3]);
+        let mut found: Vec<usize> = Vec::new();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                found.extend(
+                    self.cells[y * self.size + x]
+                        .iter()
+                        .filter(|i| meets(b, &self.sweeps[**i])),
+                );
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+}
+
+/// The part of a ring on the positive side of a plane.
+fn clip(ring: &[Point], face: &Face) -> Vec<Point> {
+    let distance = |p: &Point| (p - face.origin).dot(&face.n);
+    let mut out = Vec::with_capacity(ring.len() + 2);
+    for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+        let (da, db) = (distance(a), distance(b));
+        if da >= 0.0 {
+            out.push(*a);
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            let t = da / (da - db);
+            out.push(a + (b - a) * t);
+        }
+    }
+    out
+}
+
+/// Where a ring's shadow falls on the plane of a face, wound like the
+/// face when `outer` and the other way round otherwise. `None` when the
+/// ring has no part above the face or its shadow has no area.
+fn project(ring: &[Point], face: &Face, sun: &na::Vector3<f64>, outer: bool) -> Option<Vec<Point>> {
+    let across = sun.dot(&face.n);
+    let mut shadow: Vec<Point> = clip(ring, face)
+        .iter()
+        .map(|p| p - sun * ((p - face.origin).dot(&face.n) / across))
+        .collect();
+    let winding = along(normal(&shadow), &face.n);
+    if shadow.len() < 3 || winding.abs() < 1e-9 {
+        return None;
+    }
+    if (winding > 0.0) != outer {
+        shadow.reverse();
+    }
+    Some(shadow)
+}
+
+fn solid(plane: &PlaneT) -> bool {
+    plane.kind == PlaneKind::Polygon || plane.kind == PlaneKind::Ground
+}
+
+/// Whether two outlines on the plane of a face have overlapping bounds
+/// there, more than along an edge.
+fn overlap(a: &[Point], b: &[Point], face: &Face) -> bool {
+    let n = face.n.abs();
+    let flat = |p: &Point| {
+        if n.x >= n.y && n.x >= n.z {
+            (p.y, p.z)
+        } else if n.y >= n.z {
+            (p.x, p.z)
+        } else {
+            (p.x, p.y)
+        }
+    };
+    let range = |points: &[Point]| {
+        points.iter().map(flat).fold(
+            [::std::f64::MAX, ::std::f64::MAX, ::std::f64::MIN, ::std::f64::MIN],
+            |b, (u, v)| [b[0].min(u), b[1].min(v), b[2].max(u), b[3].max(v)],
+        )
+    };
+    let (a, b) = (range(a), range(b));
+    let e = 1e-9;
+    a[0] < b[2] - e && b[0] < a[2] - e && a[1] < b[3] - e && b[1] < a[3] - e
+}
+
+/// For every plane, the shadows cast on it by the polygons, ground and
+/// terrain of the scene in the sunlight. Faces are seen from
+/// `towards_eye`; a face showing its unlit side is wholly in the shade.
+/// Shadows missing their face, as the ground's on a wall, are left out.
+pub fn cast(planes: &PlaneFlat, sun: &Sun, towards_eye: &na::Vector3<f64>) -> Vec<Shadows> {
+    let s = sun.direction.unwrap();
+    let floor = planes
+        .iter()
+        .flat_map(|p| p.points.iter())
+        .fold(::std::f64::MAX, |z, p| z.min(p.z));
+
+    let occluders: Vec<usize> = (0..planes.len()).filter(|i| solid(planes[*i])).collect();
+    // with the sun at the horizon, shadows are endless and every occluder
+    // is tried
+    let grid = if s.z > GRAZING {
+        let sweeps = occluders
+            .iter()
+            .map(|i| {
+                let points = &planes[*i].points;
+                let low: Vec<Point> = points
+                    .iter()
+                    .map(|p| p - s * ((p.z - floor) / s.z))
+                    .collect();
+                bounds(points.iter().chain(low.iter()))
+            }).collect();
+        Some(Grid::new(sweeps))
+    } else {
+        None
+    };
+
+    (0..planes.len())
+        .into_par_iter()
+        .map(|index| {
+            let receiver = planes[index];
+            if !solid(receiver) {
+                return Vec::new();
+            }
+            let mut face = match face(&receiver.points) {
+                Some(face) => face,
+                None => return Vec::new(),
+            };
+            if face.n.dot(towards_eye) < 0.0 {
+                face.n = -face.n;
+            }
+            if sun.elevation <= 0.0 || face.n.dot(&s) <= GRAZING {
+                let mut whole = receiver.points.clone();
+                if along(normal(&whole), &face.n) < 0.0 {
+                    whole.reverse();
+                }
+                return vec![whole];
+            }
+            let candidates = match grid {
+                Some(ref grid) => grid.query(&bounds(receiver.points.iter())),
+                None => (0..occluders.len()).collect(),
+            };
+            let mut shadows = Vec::new();
+            for o in candidates {
+                let occluder = planes[occluders[o]];
+                if occluders[o] == index {
+                    continue;
+                }
+                let outline = project(&occluder.points, &face, &s, true)
+                    .filter(|outline| overlap(outline, &receiver.points, &face));
+                if let Some(outline) = outline {
+                    shadows.push(outline);
+                    shadows.extend(
+                        occluder
+                            .holes
+                            .iter()
+                            .filter_map(|hole| project(hole, &face, &s, false)),
+                    );
+                }
+            }
+            shadows
+        }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use lingua::{PlaneKind, PlaneT, Point};
+    use nalgebra as na;
+    use shadow::cast;
+    use style::Color;
+    use sun::Sun;
+
+    fn plane(kind: PlaneKind, points: &[[f64; 3]]) -> PlaneT {
+        let points = points.iter().map(|c| Point::new(c[0], c[1], c[2])).collect();
+        let mut p = PlaneT::polygon(0, 0, points, Vec::new());
+        p.kind = kind;
+        p
+    }
+
+    #[test]
+    fn shadows() {
+        let ground = plane(
+            PlaneKind::Ground,
+            &[[-10.0, -10.0, 0.0], [10.0, -10.0, 0.0], [10.0, 10.0, 0.0], [-10.0, 10.0, 0.0]],
+        );
+        // a flat roof hovering 2 above the ground, and a wall facing south
+        let roof = plane(
+            PlaneKind::Polygon,
+            &[[0.0, 0.0, 2.0], [1.0, 0.0, 2.0], [1.0, 1.0, 2.0], [0.0, 1.0, 2.0]],
+        );
+        let wall = plane(
+            PlaneKind::Polygon,
+            &[[5.0, 5.0, 0.0], [6.0, 5.0, 0.0], [6.0, 5.0, 1.0], [5.0, 5.0, 1.0]],
+        );
+        let planes = vec![&ground, &roof, &wall];
+        let eye = na::Vector3::new(0.0, -1.0, 1.0);
+        let sun = |direction: [f64; 3], elevation: f64| Sun {
+            direction: na::Unit::new_normalize(na::Vector3::new(direction[0], direction[1], direction[2])),
+            elevation,
+            shadow_color: Color::rgba(0.0, 0.0, 0.0, 0.5),
+        };
+
+        // from the south east at 45°, the roof's shadow moves by 2 to the
+        // north west, the wall's falls next to it
+        let shadows = cast(&planes, &sun([1.0, -1.0, 2f64.sqrt()], 45.0), &eye);
+        assert_eq!(shadows[0].len(), 2);
+        let shadow = &shadows[0][0];
+        assert!(shadow.iter().all(|p| p.z.abs() < 1e-9));
+        let min_x = shadow.iter().map(|p| p.x).fold(::std::f64::MAX, f64::min);
+        let min_y = shadow.iter().map(|p| p.y).fold(::std::f64::MAX, f64::min);
+        assert!((min_x + 2f64.sqrt()).abs() < 1e-9 && (min_y - 2f64.sqrt()).abs() < 1e-9);
+        // nothing above the roof, the wall is lit
+        assert!(shadows[1].is_empty() && shadows[2].is_empty());
+
+        // from the north, the wall shows its unlit side
+        let shadows = cast(&planes, &sun([0.0, 1.0, 1.0], 45.0), &eye);
+        assert_eq!(shadows[2].len(), 1);
+        assert_eq!(shadows[0].len(), 2);
+
+        // a sun barely above the horizon still casts on the faces it meets
+        let east = plane(
+            PlaneKind::Polygon,
+            &[[10.0, -5.0, 0.0], [10.0, -4.0, 0.0], [10.0, -4.0, 1.0], [10.0, -5.0, 1.0]],
+        );
+        let post = plane(
+            PlaneKind::Polygon,
+            &[[12.0, -5.0, 0.0], [12.0, -4.0, 0.0], [12.0, -4.0, 1.0], [12.0, -5.0, 1.0]],
+        );
+        let planes = vec![&ground, &east, &post];
+        let eye = na::Vector3::new(1.0, -1.0, 1.0);
+        let shadows = cast(&planes, &sun([1.0, 0.0, 0.0005], 0.03), &eye);
+        assert_eq!(shadows[1].len(), 1);
+        assert_eq!(shadows[0].len(), 1);
+
+        // at night, everything is in the shade
+        let shadows = cast(&planes, &sun([0.0, 1.0, -1.0], -45.0), &eye);
+        assert!(shadows.iter().all(|s| s.len() == 1));
+    }
+
+    #[test]
+    fn terrain_shadows() {
+        // a ridge 5 high along y, and flat ground east of it
+        let west = plane(
+            PlaneKind::Ground,
+            &[[0.0, 0.0, 0.0], [1.0, 0.0, 5.0], [1.0, 1.0, 5.0], [0.0, 1.0, 0.0]],
+        );
+        let east = plane(
+            PlaneKind::Ground,
+            &[[1.0, 0.0, 5.0], [2.0, 0.0, 0.0], [2.0, 1.0, 0.0], [1.0, 1.0, 5.0]],
+        );
+        let flat = plane(
+            PlaneKind::Ground,
+            &[[2.0, 0.0, 0.0], [10.0, 0.0, 0.0], [10.0, 1.0, 0.0], [2.0, 1.0, 0.0]],
+        );
+        let planes = vec![&west, &east, &flat];
+        let sun = Sun {
+            direction: na::Unit::new_normalize(na::Vector3::new(-1.0, 0.0, 1.0)),
+            elevation: 45.0,
+            shadow_color: Color::rgba(0.0, 0.0, 0.0, 0.5),
+        };
+
+        // with the sun in the west at 45°, the crest's shadow reaches 6
+        let shadows = cast(&planes, &sun, &na::Vector3::new(0.0, -1.0, 1.0));
+        assert!(shadows[0].is_empty());
+        assert_eq!(shadows[1].len(), 1);
+        let reach = shadows[2]
+            .iter()
+            .flat_map(|s| s.iter())
+            .map(|p| p.x)
+            .fold(::std::f64::MIN, f64::max);
+        assert!((reach - 6.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,602 @@
+use data::{LayerData, LayerSeed, LoadError};
+use geojson::feature::Id;
+use geojson::{Feature, Geometry, PolygonType, Position, Value};
+use manifest::LayerConfig;
+use serde_json::{Map, Number, Value as JsonValue};
+use std::fs;
+
+/// Little and big endian reads over a record, failing on truncation.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn slice(&self, offset: usize, n: usize) -> Result<&'a [u8], String> {
+        self.0
+            .get(offset..offset + n)
+            .ok_or_else(|| "truncated record".to_owned())
+    }
+
+    fn word(&self, offset: usize) -> Result<[u8; 4], String> {
+        let mut w = [0u8; 4];
+        w.copy_from_slice(self.slice(offset, 4)?);
+        Ok(w)
+    }
+
+    fn int(&self, offset: usize) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.word(offset)?))
+    }
+
+    fn int_be(&self, offset: usize) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.word(offset)?))
+    }
+
+    fn count(&self, offset: usize) -> Result<usize, String> {
+        let n = self.int(offset)?;
+        if n < 0 || n as usize > self.0.len() {
+            return Err(format!("bad count {}", n));
+        }
+        Ok(n as usize)
+    }
+
+    fn double(&self, offset: usize) -> Result<f64, String> {
+        let mut w = [0u8; 8];
+        w.copy_from_slice(self.slice(offset, 8)?);
+        Ok(f64::from_bits(u64::from_le_bytes(w)))
+    }
+}
+
+/// The record contents of a `.shp` file with their record numbers.
+fn records(bytes: &[u8]) -> Result<Vec<(i32, &[u8])>, String> {
+    let b = Bytes(bytes);
+    if b.int_be(0)? != 9994 {
+        return Err("not a shapefile".to_owned());
+    }
+    let mut records = Vec::new();
+    let mut offset = 100;
+    while offset + 8 <= bytes.len() {
+        let number = b.int_be(offset)?;
+        let length = b.int_be(offset + 4)?;
+        if length < 0 {
+            return Err(format!("record {}: bad length {}", number, length));
+        }
+        let content = b
+            .slice(offset + 8, length as usize * 2)
+            .map_err(|e| format!("record {}: {}", number, e))?;
+        records.push((number, content));
+        offset += 8 + length as usize * 2;
+    }
+    Ok(records)
+}
+
+/// Parts of a multi-part shape, the part types of a MultiPatch and the
+/// points, heights included when the shape has some.
+fn read_parts(b: &Bytes, patch: bool, z: bool) -> Result<(Vec<Vec<Position>>, Vec<i32>), String> {
+    let num_parts = b.count(36)?;
+    let num_points = b.count(40)?;
+    let starts = (0..num_parts)
+        .map(|i| b.count(44 + 4 * i))
+        .collect::<Result<Vec<usize>, String>>()?;
+    let mut offset = 44 + 4 * num_parts;
+    let types = if patch {
+        let types = (0..num_parts)
+            .map(|i| b.int(offset + 4 * i))
+            .collect::<Result<Vec<i32>, String>>()?;
+        offset += 4 * num_parts;
+        types
+    } else {
+        Vec::new()
+    };
+    let mut points = (0..num_points)
+        .map(|i| Ok(vec![b.double(offset + 16 * i)?, b.double(offset + 16 * i + 8)?]))
+        .collect::<Result<Vec<Position>, String>>()?;
+    if z {
+        let offset = offset + 16 * num_points + 16;
+        for (i, p) in points.iter_mut().enumerate() {
+            p.push(b.double(offset + 8 * i)?);
+        }
+    }
+    let mut parts = Vec::with_capacity(num_parts);
+    for (i, start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).cloned().unwrap_or(num_points);
+        if *start > end || end > num_points {
+            return Err(format!("part {} out of range", i));
+        }
+        parts.push(points[*start..end].to_vec());
+    }
+    Ok((parts, types))
+}
+
+fn signed_area(ring: &[Position]) -> f64 {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| a[0] * b[1] - b[0] * a[1])
+        .sum::<f64>()
+        / 2.0
+}
+
+fn ring_contains(ring: &[Position], p: &[f64]) -> bool {
+    let mut inside = false;
+    for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+        if (a[1] > p[1]) != (b[1] > p[1])
+            && p[0] < a[0] + (p[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0])
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn close(mut ring: Vec<Position>) -> Vec<Position> {
+    if ring.first() != ring.last() {
+        let first = ring[0].clone();
+        ring.push(first);
+    }
+    ring
+}
+
+/// Shapefile polygons list clockwise outer rings followed by their
+/// counter-clockwise holes. A hole goes to the outer ring containing it,
+/// or the last one when none does.
+fn group_rings(rings: Vec<Vec<Position>>) -> Vec<PolygonType> {
+    let mut polygons: Vec<PolygonType> = Vec::new();
+    for ring in rings.into_iter().filter(|r| r.len() >= 3) {
+        let ring = close(ring);
+        if signed_area(&ring) <= 0.0 || polygons.is_empty() {
+            polygons.push(vec![ring]);
+        } else {
+            let index = polygons
+                .iter()
+                .position(|p| ring_contains(&p[0], &ring[0]))
+                .unwrap_or(polygons.len() - 1);
+            polygons[index].push(ring);
+        }
+    }
+    polygons
+}
+
+const TRIANGLE_STRIP: i32 = 0;
+const TRIANGLE_FAN: i32 = 1;
+const OUTER_RING: i32 = 2;
+const INNER_RING: i32 = 3;
+const FIRST_RING: i32 = 4;
+const RING: i32 = 5;
+
+/// Triangles of strips and fans become polygons of their own, inner rings
+/// are holes of the preceding outer ring and rings following a first ring
+/// are its holes.
+fn patch_polygons(parts: Vec<Vec<Position>>, types: &[i32]) -> Result<Vec<PolygonType>, String> {
+    let mut polygons: Vec<PolygonType> = Vec::new();
+    // whether the last polygon takes the holes of the part being read
+    let mut open = false;
+    for (part, kind) in parts.into_iter().zip(types.iter()) {
+        match *kind {
+            TRIANGLE_STRIP | TRIANGLE_FAN => {
+                open = false;
+                for i in 2..part.len() {
+                    let (a, b, c) = if *kind == TRIANGLE_STRIP {
+                        (&part[i - 2], &part[i - 1], &part[i])
+                    } else {
+                        (&part[0], &part[i - 1], &part[i])
+                    };
+                    polygons.push(vec![vec![a.clone(), b.clone(), c.clone(), a.clone()]]);
+                }
+            }
+            OUTER_RING | FIRST_RING | INNER_RING | RING if part.len() >= 3 => {
+                let ring = close(part);
+                let hole = match *kind {
+                    INNER_RING | RING => open,
+                    _ => false,
+                };
+                if hole {
+                    polygons.last_mut().unwrap().push(ring);
+                } else {
+                    polygons.push(vec![ring]);
+                    open = *kind != RING;
+                }
+            }
+            OUTER_RING | FIRST_RING | INNER_RING | RING => (),
+            other => return Err(format!("unknown MultiPatch part type {}", other)),
+        }
+    }
+    Ok(polygons)
+}
+
+fn polygon_value(mut polygons: Vec<PolygonType>) -> Option<Value> {
+    match polygons.len() {
+        0 => None,
+        1 => Some(Value::Polygon(polygons.remove(0))),
+        _ => Some(Value::MultiPolygon(polygons)),
+    }
+}
+
+/// The geometry of a record, `None` for a null shape.
+fn read_shape(content: &[u8]) -> Result<Option<Value>, String> {
+    let b = Bytes(content);
+    let kind = b.int(0)?;
+    let z = match kind {
+        11 | 13 | 15 | 18 | 31 => true,
+        _ => false,
+    };
+    Ok(match kind {
+        0 => None,
+        1 | 11 | 21 => {
+            let mut p = vec![b.double(4)?, b.double(12)?];
+            if z {
+                p.push(b.double(20)?);
+            }
+            Some(Value::Point(p))
+        }
+        8 | 18 | 28 => {
+            let n = b.count(36)?;
+            let mut points = (0..n)
+                .map(|i| Ok(vec![b.double(40 + 16 * i)?, b.double(48 + 16 * i)?]))
+                .collect::<Result<Vec<Position>, String>>()?;
+            if z {
+                let offset = 40 + 16 * n + 16;
+                for (i, p) in points.iter_mut().enumerate() {
+                    p.push(b.double(offset + 8 * i)?);
+                }
+            }
+            Some(Value::MultiPoint(points))
+        }
+        3 | 13 | 23 => {
+            let (mut parts, _) = read_parts(&b, false, z)?;
+            match parts.len() {
+                0 => None,
+                1 => Some(Value::LineString(parts.remove(0))),
+                _ => Some(Value::MultiLineString(parts)),
+            }
+        }
+        5 | 15 | 25 => polygon_value(group_rings(read_parts(&b, false, z)?.0)),
+        31 => {
+            let (parts, types) = read_parts(&b, true, z)?;
+            polygon_value(patch_polygons(parts, &types)?)
+        }
+        other => return Err(format!("unsupported shape type {}", other)),
+    })
+}
+
+struct Field {
+    name: String,
+    kind: u8,
+    length: usize,
+    decimals: u8,
+}
+
+/// dBase files carry no reliable encoding, UTF-8 is tried before Latin-1.
+fn decode(raw: &[u8]) -> String {
+    match ::std::str::from_utf8(raw) {
+        Ok(s) => s.to_owned(),
+        Err(_) => raw.iter().map(|b| char::from(*b)).collect(),
+    }
+}
+
+fn field_value(field: &Field, raw: &[u8]) -> JsonValue {
+    let text = decode(raw);
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    match field.kind {
+        b'N' | b'F' => {
+            if field.decimals == 0 {
+                if let Ok(n) = text.parse::<i64>() {
+                    return JsonValue::Number(n.into());
+                }
+            }
+            text.parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .map_or(JsonValue::Null, JsonValue::Number)
+        }
+        b'L' => match text {
+            "T" | "t" | "Y" | "y" => JsonValue::Bool(true),
+            "F" | "f" | "N" | "n" => JsonValue::Bool(false),
+            _ => JsonValue::Null,
+        },
+        _ if text.is_empty() => JsonValue::Null,
+        _ => JsonValue::String(text.to_owned()),
+    }
+}
+
+/// The attributes of every record of a `.dbf` file, `None` for deleted
+/// records.
+fn read_dbf(bytes: &[u8]) -> Result<Vec<Option<Map<String, JsonValue>>>, String> {
+    let truncated = || "truncated attribute table".to_owned();
+    if bytes.len() < 32 {
+        return Err(truncated());
+    }
+    let count = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    let header = usize::from(u16::from_le_bytes([bytes[8], bytes[9]]));
+    let length = usize::from(u16::from_le_bytes([bytes[10], bytes[11]]));
+
+    let mut fields = Vec::new();
+    let mut offset = 32;
+    while offset + 32 <= header && bytes.get(offset) != Some(&0x0d) {
+        let d = bytes.get(offset..offset + 32).ok_or_else(truncated)?;
+        let name = d[..11].split(|b| *b == 0).next().unwrap_or(&[]);
+        fields.push(Field {
+            name: decode(name).trim().to_owned(),
+            kind: d[11],
+            length: usize::from(d[16]),
+            decimals: d[17],
+        });
+        offset += 32;
+    }
+    if 1 + fields.iter().map(|f| f.length).sum::<usize>() > length {
+        return Err("fields longer than the record".to_owned());
+    }
+
+    (0..count)
+        .map(|i| {
+            let start = header + i * length;
+            let record = bytes.get(start..start + length).ok_or_else(truncated)?;
+            if record[0] == b'*' {
+                return Ok(None);
+            }
+            let mut props = Map::new();
+            let mut offset = 1;
+            for field in fields.iter() {
+                let raw = &record[offset..offset + field.length];
+                props.insert(field.name.clone(), field_value(field, raw));
+                offset += field.length;
+            }
+            Ok(Some(props))
+        }).collect()
+}
+
+/// Loads an ESRI Shapefile, one feature per record with the attributes of
+/// the matching `.dbf` record as properties.
+pub fn load(layer: &LayerConfig, seed: LayerSeed) -> Result<LayerData, LoadError> {
+    eprintln!("Loading shapefile {} ({})", layer.name, layer.data.display());
+    let bytes = fs::read(&layer.data).map_err(|e| LoadError::Io(layer.data.clone(), e))?;
+    let attributes = match layer.attribute_table() {
+        Some(path) => {
+            let table = fs::read(&path).map_err(|e| LoadError::Io(path.clone(), e))?;
+            read_dbf(&table).map_err(|e| LoadError::Format(path.clone(), e))?
+        }
+        None => Vec::new(),
+    };
+    let records = records(&bytes).map_err(|e| LoadError::Format(layer.data.clone(), e))?;
+
+    let mut data = seed.empty();
+    for (index, (number, content)) in records.into_iter().enumerate() {
+        let properties = match attributes.get(index) {
+            Some(None) => continue,
+            Some(Some(props)) => Some(props.clone()),
+            None => None,
+        };
+        data.report.features_read += 1;
+        match read_shape(content) {
+            Ok(Some(value)) => seed.push_feature(
+                &Feature {
+                    bbox: None,
+                    geometry: Some(Geometry::new(value)),
+                    id: Some(Id::Number(number.into())),
+                    properties,
+                    foreign_members: None,
+                },
+                &mut data,
+            ),
+            Ok(None) => data.report.skip("null"),
+            Err(_) => data.report.features_invalid += 1,
+        }
+    }
+
+    eprintln!("Loaded {}", layer.name);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use data::Data;
+    use geojson::Value;
+    use serde_json::Value as JsonValue;
+    use shapefile::{read_dbf, read_shape, records};
+    use std::env;
+    use std::fs;
+
+    fn polygon_z(rings: &[&[[f64; 3]]]) -> Vec<u8> {
+        let mut c = Vec::new();
+        c.extend_from_slice(&15i32.to_le_bytes());
+        c.extend_from_slice(&[0u8; 32]);
+        let n: usize = rings.iter().map(|r| r.len()).sum();
+        c.extend_from_slice(&(rings.len() as i32).to_le_bytes());
+        c.extend_from_slice(&(n as i32).to_le_bytes());
+        let mut start = 0;
+        for r in rings {
+            c.extend_from_slice(&(start as i32).to_le_bytes());
+            start += r.len();
+        }
+        let points = rings.iter().flat_map(|r| r.iter());
+        for p in points.clone() {
+            c.extend_from_slice(&p[0].to_bits().to_le_bytes());
+            c.extend_from_slice(&p[1].to_bits().to_le_bytes());
+        }
+        c.extend_from_slice(&[0u8; 16]);
+        for p in points {
+            c.extend_from_slice(&p[2].to_bits().to_le_bytes());
+        }
+        c
+    }
+
+    fn multipatch(parts: &[(i32, &[[f64; 3]])]) -> Vec<u8> {
+        let mut c = Vec::new();
+        c.extend_from_slice(&31i32.to_le_bytes());
+        c.extend_from_slice(&[0u8; 32]);
+        let n: usize = parts.iter().map(|p| p.1.len()).sum();
+        c.extend_from_slice(&(parts.len() as i32).to_le_bytes());
+        c.extend_from_slice(&(n as i32).to_le_bytes());
+        let mut start = 0;
+        for p in parts {
+            c.extend_from_slice(&(start as i32).to_le_bytes());
+            start += p.1.len();
+        }
+        for p in parts {
+            c.extend_from_slice(&p.0.to_le_bytes());
+        }
+        let points = parts.iter().flat_map(|p| p.1.iter());
+        for p in points.clone() {
+            c.extend_from_slice(&p[0].to_bits().to_le_bytes());
+            c.extend_from_slice(&p[1].to_bits().to_le_bytes());
+        }
+        c.extend_from_slice(&[0u8; 16]);
+        for p in points {
+            c.extend_from_slice(&p[2].to_bits().to_le_bytes());
+        }
+        c
+    }
+
+    fn shp(contents: &[&[u8]]) -> Vec<u8> {
+        let mut shp = 9994i32.to_be_bytes().to_vec();
+        shp.resize(100, 0);
+        for (i, content) in contents.iter().enumerate() {
+            shp.extend_from_slice(&(i as i32 + 1).to_be_bytes());
+            shp.extend_from_slice(&((content.len() / 2) as i32).to_be_bytes());
+            shp.extend_from_slice(content);
+        }
+        shp
+    }
+
+    #[test]
+    fn polygon_with_hole() {
+        let outer = [
+            [0.0, 0.0, 5.0],
+            [0.0, 10.0, 5.0],
+            [10.0, 10.0, 5.0],
+            [10.0, 0.0, 5.0],
+            [0.0, 0.0, 5.0],
+        ];
+        let hole = [
+            [2.0, 2.0, 5.0],
+            [4.0, 2.0, 5.0],
+            [4.0, 4.0, 5.0],
+            [2.0, 2.0, 5.0],
+        ];
+        let other = [[20.0, 0.0, 1.0], [20.0, 1.0, 1.0], [21.0, 0.0, 1.0]];
+        let content = polygon_z(&[&outer, &hole, &other]);
+        let shp = shp(&[&content, &0i32.to_le_bytes()]);
+
+        let records = records(&shp).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(read_shape(records[1].1).unwrap(), None);
+        match read_shape(records[0].1).unwrap() {
+            Some(Value::MultiPolygon(polys)) => {
+                assert_eq!(polys.len(), 2);
+                assert_eq!(polys[0].len(), 2);
+                assert_eq!(polys[0][1][1], vec![4.0, 2.0, 5.0]);
+                assert_eq!(polys[1][0].len(), 4);
+            }
+            other => panic!("expected a MultiPolygon, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn multipatch_parts() {
+        let strip = [[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [0.0, 1.0, 2.0], [1.0, 1.0, 2.0]];
+        let fan = [[5.0, 5.0, 0.0], [6.0, 5.0, 0.0], [6.0, 6.0, 0.0], [5.0, 6.0, 0.0]];
+        let square = |o: f64, size: f64| {
+            [
+                [o, o, 3.0],
+                [o + size, o, 3.0],
+                [o + size, o + size, 3.0],
+                [o, o + size, 3.0],
+            ]
+        };
+        let (outer, inner) = (square(10.0, 4.0), square(11.0, 1.0));
+        let (first, ring, other) = (square(20.0, 4.0), square(21.0, 1.0), square(22.5, 1.0));
+        let content = multipatch(&[
+            (0, &strip),
+            (1, &fan),
+            (2, &outer),
+            (3, &inner),
+            (4, &first),
+            (5, &ring),
+            (5, &other),
+            // an inner ring after triangles has no outer ring to go in
+            (0, &strip[..3]),
+            (3, &inner),
+        ]);
+
+        let polys = match read_shape(&content).unwrap() {
+            Some(Value::MultiPolygon(polys)) => polys,
+            other => panic!("expected a MultiPolygon, got {:?}", other),
+        };
+        let rings: Vec<usize> = polys.iter().map(|p| p.len()).collect();
+        assert_eq!(rings, vec![1, 1, 1, 1, 2, 3, 1, 1]);
+        // strips take consecutive triples, fans turn around the first point
+        assert_eq!(
+            polys[1][0],
+            vec![vec![1.0, 0.0, 1.0], vec![0.0, 1.0, 2.0], vec![1.0, 1.0, 2.0], vec![1.0, 0.0, 1.0]]
+        );
+        assert_eq!(
+            polys[3][0],
+            vec![vec![5.0, 5.0, 0.0], vec![6.0, 6.0, 0.0], vec![5.0, 6.0, 0.0], vec![5.0, 5.0, 0.0]]
+        );
+        // rings are closed, holes follow their outer or first ring
+        assert_eq!(polys[4][1].len(), 5);
+        assert_eq!(polys[4][1][0], vec![11.0, 11.0, 3.0]);
+        assert_eq!(polys[5][2][0], vec![22.5, 22.5, 3.0]);
+        assert_eq!(polys[7][0][0], vec![11.0, 11.0, 3.0]);
+
+        assert!(read_shape(&multipatch(&[(9, &strip)])).is_err());
+    }
+
+    #[test]
+    fn malformed_record() {
+        let dir = env::temp_dir().join("cardboard-shapefile");
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("m.json.cache"));
+        let square = polygon_z(&[&[
+            [0.0, 0.0, 1.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [0.0, 0.0, 1.0],
+        ]]);
+        // a polygon cut short before its part count
+        let cut = &square[..12];
+        fs::write(dir.join("a.shp"), shp(&[&square, cut, &square])).unwrap();
+        fs::write(
+            dir.join("s.json"),
+            r#"{"kind":"simple","strokeColor":"black","fillColor":"red","strokeWidth":1}"#,
+        ).unwrap();
+        fs::write(
+            dir.join("m.json"),
+            r#"{"layers":[{"name":"a","data":"a.shp","style":"s.json"}]}"#,
+        ).unwrap();
+
+        let data = Data::from_file(dir.join("m.json")).unwrap();
+        let report = &data.reports[0];
+        assert!(report.error.is_none());
+        assert_eq!(report.features_read, 3);
+        assert_eq!(report.features_invalid, 1);
+        assert_eq!(data.features[0].len(), 2);
+    }
+
+    #[test]
+    fn attributes() {
+        let mut dbf = vec![3u8, 118, 1, 1];
+        dbf.extend_from_slice(&2u32.to_le_bytes());
+        dbf.extend_from_slice(&(32u16 + 3 * 32 + 1).to_le_bytes());
+        dbf.extend_from_slice(&(1u16 + 8 + 6 + 1).to_le_bytes());
+        dbf.resize(32, 0);
+        let fields = [("NAME", b'C', 8, 0), ("H", b'N', 6, 2), ("OK", b'L', 1, 0)];
+        for (name, kind, length, decimals) in fields.iter() {
+            let mut d = name.as_bytes().to_vec();
+            d.resize(11, 0);
+            d.push(*kind);
+            d.resize(16, 0);
+            d.push(*length);
+            d.push(*decimals);
+            d.resize(32, 0);
+            dbf.extend_from_slice(&d);
+        }
+        dbf.push(0x0d);
+        dbf.extend_from_slice(b" Caf\xe9     12.50T");
+        dbf.extend_from_slice(b"*Gone          F");
+
+        let records = read_dbf(&dbf).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[1].is_none());
+        let props = records[0].as_ref().unwrap();
+        assert_eq!(props["NAME"], JsonValue::String("Café".to_owned()));
+        assert_eq!(props["H"].as_f64(), Some(12.5));
+        assert_eq!(props["OK"], JsonValue::Bool(true));
+    }
+}
@@ -1,6 +1,11 @@
 use lingua::Properties;
+use ramp::{ColorRamp, ColorRampConfig, ColorSpace, Interpolation, WidthRamp, WidthRampConfig};
 use serde_json;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
+use std::io;
+use std::path::Path;
 use svgtypes::Color as CSSColor;
 
 #[derive(Copy, Clone, Debug)]
@@ -24,18 +29,31 @@ pub struct StyleConfigDiscrete {
     toks: Vec<String>,
 }
 
+#[derive(Clone, Debug)]
+pub struct StyleConfigInterpolated {
+    prop_name: String,
+    fill: ColorRamp,
+    stroke: ColorRamp,
+    width: WidthRamp,
+}
+
 #[derive(Clone, Debug)]
 pub enum StyleConfig {
     Simple,
     Continuous(StyleConfigContinuous),
     Discrete(StyleConfigDiscrete),
+    Interpolated(Box<StyleConfigInterpolated>),
 }
 
+/// Diameter of point symbols when a style does not set one.
+const DEFAULT_MARKER_SIZE: f64 = 6.0;
+
 #[derive(Clone, Debug)]
 pub struct Style {
     pub strokeWidth: f64,
     pub strokeColor: Option<Color>,
     pub fillColor: Option<Color>,
+    pub markerSize: f64,
     config: StyleConfig,
 }
 
@@ -45,8 +63,16 @@ pub struct Style {
 //     Discrete,
 // }
 
+/// The configured styles, the fallback last, followed by the styles
+/// resolved from ramps as features ask for them.
 #[derive(Clone, Debug)]
-pub struct StyleList(Vec<Style>);
+pub struct StyleList {
+    styles: Vec<Style>,
+    configured: usize,
+    /// Index of each resolved style by its 8 bit colours and width in
+    /// hundredths.
+    resolved: HashMap<([u8; 4], [u8; 4], u64), usize>,
+}
 
 pub type StyleCollection = Vec<StyleList>;
 
@@ -66,6 +92,38 @@ fn u2f(v: u8) -> f64 {
     vf / 255.0
 }
 
+fn quantize(c: &Color) -> [u8; 4] {
+    let q = |v: f64| (v.max(0.0).min(1.0) * 255.0).round() as u8;
+    [q(c.red), q(c.green), q(c.blue), q(c.alpha)]
+}
+
+/// The arguments of a CSS function such as `rgba(...)`.
+fn function_args<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    if !text.starts_with(name) {
+        return None;
+    }
+    let rest = text[name.len()..].trim_start();
+    if rest.starts_with('(') && rest.ends_with(')') {
+        Some(&rest[1..rest.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// A colour channel in [0, 1], from a number up to `scale` or a percentage.
+fn channel(s: &str, scale: f64) -> Option<f64> {
+    let v = if s.ends_with('%') {
+        s[..s.len() - 1].parse::<f64>().ok()? / 100.0
+    } else {
+        s.parse::<f64>().ok()? / scale
+    };
+    if v.is_finite() {
+        Some(v.max(0.0).min(1.0))
+    } else {
+        None
+    }
+}
+
 impl Color {
     pub fn new() -> Color {
         Color {
@@ -76,15 +134,51 @@ impl Color {
         }
     }
 
-    pub fn from_string(s: &str) -> Color {
-        match s.parse::<CSSColor>() {
-            Ok(css_color) => Color::rgb(
-                u2f(css_color.red),
-                u2f(css_color.green),
-                u2f(css_color.blue),
-            ),
-            Err(_) => Color::new(),
+    /// Reads a CSS colour: a name, `transparent`, `#rgb`, `#rgba`,
+    /// `#rrggbb`, `#rrggbbaa`, `rgb(...)` or `rgba(...)`, channels being
+    /// numbers or percentages.
+    pub fn from_string(s: &str) -> Result<Color, String> {
+        let invalid = || format!("invalid colour \"{}\"", s);
+        let text = s.trim().to_lowercase();
+        if text == "transparent" {
+            return Ok(Color::rgba(0.0, 0.0, 0.0, 0.0));
         }
+        if text.starts_with('#') {
+            let digits = text[1..]
+                .chars()
+                .map(|c| c.to_digit(16))
+                .collect::<Option<Vec<u32>>>()
+                .ok_or_else(invalid)?;
+            let values: Vec<f64> = match digits.len() {
+                3 | 4 => digits.iter().map(|d| f64::from(d * 17) / 255.0).collect(),
+                6 | 8 => digits
+                    .chunks(2)
+                    .map(|p| f64::from(p[0] * 16 + p[1]) / 255.0)
+                    .collect(),
+                _ => return Err(invalid()),
+            };
+            return Ok(Color::rgba(
+                values[0],
+                values[1],
+                values[2],
+                values.get(3).cloned().unwrap_or(1.0),
+            ));
+        }
+        if let Some(args) = function_args(&text, "rgba").or_else(|| function_args(&text, "rgb")) {
+            let parts: Vec<&str> = args
+                .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+                .filter(|p| !p.is_empty())
+                .collect();
+            if parts.len() != 3 && parts.len() != 4 {
+                return Err(invalid());
+            }
+            let value = |i: usize, scale: f64| channel(parts[i], scale).ok_or_else(invalid);
+            let alpha = if parts.len() == 4 { value(3, 1.0)? } else { 1.0 };
+            return Ok(Color::rgba(value(0, 255.0)?, value(1, 255.0)?, value(2, 255.0)?, alpha));
+        }
+        text.parse::<CSSColor>()
+            .map(|c| Color::rgb(u2f(c.red), u2f(c.green), u2f(c.blue)))
+            .map_err(|_| invalid())
     }
 
     pub fn rgb(red: f64, green: f64, blue: f64) -> Color {
@@ -119,6 +213,7 @@ impl Style {
             strokeWidth: 1.0,
             strokeColor: None,
             fillColor: None,
+            markerSize: DEFAULT_MARKER_SIZE,
             config,
         }
     }
@@ -128,6 +223,7 @@ impl Style {
             strokeWidth: 1.0,
             strokeColor: Some(Color::black()),
             fillColor: Some(Color::white()),
+            markerSize: DEFAULT_MARKER_SIZE,
             config: StyleConfig::Simple,
         }
     }
@@ -146,6 +242,7 @@ impl Style {
             strokeWidth,
             strokeColor: self.strokeColor,
             fillColor: self.fillColor,
+            markerSize: self.markerSize,
             config: self.config,
         }
     }
@@ -154,6 +251,16 @@ impl Style {
             strokeWidth: self.strokeWidth,
             strokeColor: Some(strokeColor),
             fillColor: self.fillColor,
+            markerSize: self.markerSize,
+            config: self.config,
+        }
+    }
+    pub fn marker(self, markerSize: Option<f64>) -> Style {
+        Style {
+            strokeWidth: self.strokeWidth,
+            strokeColor: self.strokeColor,
+            fillColor: self.fillColor,
+            markerSize: markerSize.unwrap_or(DEFAULT_MARKER_SIZE),
             config: self.config,
         }
     }
@@ -162,6 +269,7 @@ impl Style {
             strokeWidth: self.strokeWidth,
             strokeColor: self.strokeColor,
             fillColor: Some(fillColor),
+            markerSize: self.markerSize,
             config: self.config,
         }
     }
@@ -169,20 +277,64 @@ impl Style {
 
 impl StyleList {
     pub fn new() -> StyleList {
-        StyleList(Vec::new())
+        StyleList {
+            styles: Vec::new(),
+            configured: 0,
+            resolved: HashMap::new(),
+        }
+    }
+
+    /// Only the fallback, for reading a layer whose style cannot be used.
+    pub fn unstyled() -> StyleList {
+        let mut sl = StyleList::new();
+        sl.add(Style::default());
+        sl
     }
 
     fn add(&mut self, s: Style) -> &mut StyleList {
-        self.0.push(s);
+        self.styles.push(s);
+        self.configured = self.styles.len();
         self
     }
 
     pub fn get_for(&self, index: &usize) -> Option<&Style> {
-        let styles = &self.0;
+        let styles = &self.styles;
         styles.get(index.to_owned())
     }
 
-    pub fn from_config(style_config: &PolygonStyleConfig) -> StyleList {
+    /// The trailing default style, picked when no configured style matches.
+    pub fn is_fallback(&self, index: usize) -> bool {
+        index + 1 == self.configured
+    }
+
+    pub fn fallback(&self) -> usize {
+        self.configured - 1
+    }
+
+    /// The list as configured, without resolved styles.
+    pub fn configured(&self) -> StyleList {
+        StyleList {
+            styles: self.styles[..self.configured].to_vec(),
+            configured: self.configured,
+            resolved: HashMap::new(),
+        }
+    }
+
+    fn intern(&mut self, style: Style) -> usize {
+        let key = (
+            quantize(&style.fillColor.unwrap_or_else(Color::new)),
+            quantize(&style.strokeColor.unwrap_or_else(Color::new)),
+            (style.strokeWidth * 100.0).round() as u64,
+        );
+        let styles = &mut self.styles;
+        *self.resolved.entry(key).or_insert_with(|| {
+            styles.push(style);
+            styles.len() - 1
+        })
+    }
+
+    pub fn from_config(style_config: &PolygonStyleConfig) -> Result<StyleList, StyleError> {
+        let color = |s: &str| Color::from_string(s).map_err(StyleError::Color);
         let mut sl = StyleList::new();
         match style_config {
             PolygonStyleConfig::Simple(config) => {
@@ -190,12 +342,13 @@ impl StyleList {
                 sl.add(
                     Style::new(StyleConfig::Simple)
                         .width(config.strokeWidth)
-                        .stroke(Color::from_string(&config.strokeColor))
-                        .fill(Color::from_string(&config.fillColor)),
+                        .stroke(color(&config.strokeColor)?)
+                        .fill(color(&config.fillColor)?)
+                        .marker(config.markerSize),
                 );
             }
             PolygonStyleConfig::Continuous(config) => {
-                config.intervals.iter().for_each(|it| {
+                for it in config.intervals.iter() {
                     // println!("Insert Continuous Style {:?}", it);
                     sl.add(
                         Style::new(StyleConfig::Continuous(StyleConfigContinuous {
@@ -203,35 +356,68 @@ impl StyleList {
                             high: it.high.clone(),
                             low: it.low.clone(),
                         })).width(it.strokeWidth)
-                        .stroke(Color::from_string(&it.strokeColor))
-                        .fill(Color::from_string(&it.fillColor)),
+                        .stroke(color(&it.strokeColor)?)
+                        .fill(color(&it.fillColor)?)
+                        .marker(it.markerSize),
                     );
-                });
+                }
+            }
+            PolygonStyleConfig::Interpolated(config) => {
+                let ramp = |e: String| StyleError::Ramp(e);
+                let fill = ColorRamp::from_config(&config.fillColor, config.interpolation, config.colorSpace)
+                    .map_err(ramp)?;
+                let stroke = ColorRamp::from_config(&config.strokeColor, config.interpolation, config.colorSpace)
+                    .map_err(ramp)?;
+                let width = WidthRamp::from_config(&config.strokeWidth, config.interpolation).map_err(ramp)?;
+                sl.add(
+                    Style::new(StyleConfig::Interpolated(Box::new(StyleConfigInterpolated {
+                        prop_name: config.propName.clone(),
+                        fill,
+                        stroke,
+                        width,
+                    }))).marker(config.markerSize),
+                );
             }
             PolygonStyleConfig::Discrete(config) => {
-                config.groups.iter().for_each(|it| {
+                for it in config.groups.iter() {
                     // println!("Insert Discrete Style {:?}", it);
                     sl.add(
                         Style::new(StyleConfig::Discrete(StyleConfigDiscrete {
                             prop_name: config.propName.clone(),
                             toks: it.values.clone(),
                         })).width(it.strokeWidth)
-                        .stroke(Color::from_string(&it.strokeColor))
-                        .fill(Color::from_string(&it.fillColor)),
+                        .stroke(color(&it.strokeColor)?)
+                        .fill(color(&it.fillColor)?)
+                        .marker(it.markerSize),
                     );
-                });
+                }
             }
         };
         // make sure ther's a default style at the end
         sl.add(Style::default());
-        sl
+        Ok(sl)
     }
 
-    pub fn select(&self, props_opt: &Properties) -> Option<usize> {
-        let styles = &self.0;
+    /// Makes every colour of the list more transparent, 1 leaving them as
+    /// they are.
+    pub fn with_opacity(mut self, opacity: f64) -> StyleList {
+        for s in self.styles.iter_mut() {
+            for color in s.strokeColor.iter_mut().chain(s.fillColor.iter_mut()) {
+                color.alpha *= opacity;
+            }
+            if let StyleConfig::Interpolated(ref mut config) = s.config {
+                config.fill.fade(opacity);
+                config.stroke.fade(opacity);
+            }
+        }
+        self
+    }
 
-        for i in 0..styles.len() {
-            let s = &styles[i];
+    /// The style of a feature, resolving it from a ramp when the matching
+    /// style is interpolated.
+    pub fn select(&mut self, props_opt: &Properties) -> Option<usize> {
+        for i in 0..self.configured {
+            let s = &self.styles[i];
             match &s.config {
                 StyleConfig::Simple => return Some(i),
 
@@ -260,6 +446,21 @@ impl StyleList {
                         return Some(i);
                     }
                 }
+
+                StyleConfig::Interpolated(config) => {
+                    let value = props_opt
+                        .as_ref()
+                        .and_then(|props| props.get(&config.prop_name))
+                        .and_then(|v| v.as_f64());
+                    if let Some(n) = value {
+                        let resolved = Style::new(StyleConfig::Simple)
+                            .width(config.width.at(n))
+                            .stroke(config.stroke.at(n))
+                            .fill(config.fill.at(n))
+                            .marker(Some(s.markerSize));
+                        return Some(self.intern(resolved));
+                    }
+                }
             }
         }
 
@@ -273,6 +474,8 @@ pub struct PolygonStyleConfigSimple {
     strokeColor: String,
     fillColor: String,
     strokeWidth: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    markerSize: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -282,6 +485,8 @@ pub struct PolygonInterval {
     fillColor: String,
     strokeColor: String,
     strokeWidth: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    markerSize: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -297,6 +502,8 @@ pub struct PolygonGroup {
     fillColor: String,
     strokeColor: String,
     strokeWidth: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    markerSize: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -306,9 +513,35 @@ pub struct PolygonStyleConfigDiscrete {
     groups: Vec<PolygonGroup>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub enum InterpolatedKind {
+    #[serde(rename = "interpolated")]
+    Interpolated,
+}
+
+/// Colours and width computed from a numeric property for each feature.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PolygonStyleConfigInterpolated {
+    kind: InterpolatedKind,
+    propName: String,
+    #[serde(default)]
+    interpolation: Interpolation,
+    #[serde(default)]
+    colorSpace: ColorSpace,
+    fillColor: ColorRampConfig,
+    strokeColor: ColorRampConfig,
+    strokeWidth: WidthRampConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    markerSize: Option<f64>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum PolygonStyleConfig {
+    // first, as it would otherwise pass for a simple style when its
+    // colours and width are fixed
+    #[serde(rename = "interpolated")]
+    Interpolated(PolygonStyleConfigInterpolated),
     #[serde(rename = "simple")]
     Simple(PolygonStyleConfigSimple),
     #[serde(rename = "continuous")]
@@ -317,11 +550,53 @@ pub enum PolygonStyleConfig {
     Discrete(PolygonStyleConfigDiscrete),
 }
 
-pub fn load_style(filename: &str) -> Result<PolygonStyleConfig, serde_json::Error> {
-    let serialized = fs::read_to_string(filename).expect("Something went wrong reading the file");
+impl PolygonInterval {
+    pub fn new(low: f64, high: f64, fill_color: String, stroke_color: &str, stroke_width: f64) -> PolygonInterval {
+        PolygonInterval {
+            low,
+            high,
+            fillColor: fill_color,
+            strokeColor: stroke_color.to_owned(),
+            strokeWidth: stroke_width,
+            markerSize: None,
+        }
+    }
+}
+
+impl PolygonStyleConfig {
+    pub fn continuous(prop_name: &str, intervals: Vec<PolygonInterval>) -> PolygonStyleConfig {
+        PolygonStyleConfig::Continuous(PolygonStyleConfigContinuous {
+            kind: "continuous".to_owned(),
+            propName: prop_name.to_owned(),
+            intervals,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum StyleError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Color(String),
+    Ramp(String),
+}
+
+impl fmt::Display for StyleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StyleError::Io(e) => write!(f, "{}", e),
+            StyleError::Json(e) => write!(f, "{}", e),
+            StyleError::Color(e) => write!(f, "{}", e),
+            StyleError::Ramp(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub fn load_style<P: AsRef<Path>>(filename: P) -> Result<PolygonStyleConfig, StyleError> {
+    let serialized = fs::read_to_string(filename).map_err(StyleError::Io)?;
     // println!("{}", serialized);
 
-    serde_json::from_str::<PolygonStyleConfig>(&serialized)
+    serde_json::from_str::<PolygonStyleConfig>(&serialized).map_err(StyleError::Json)
 }
 
 #[cfg(test)]
@@ -335,10 +610,52 @@ mod tests {
             strokeColor: "red".to_owned(),
             fillColor: "blue".to_owned(),
             strokeWidth: 2.0,
+            markerSize: None,
         });
         serde_json::to_string(&p).map(|ser| {
             println!("{}", ser);
             assert_eq!(s, ser);
         });
     }
+
+    #[test]
+    fn parse_colors() {
+        let rgba = |s: &str| {
+            let c = style::Color::from_string(s).unwrap();
+            [c.red, c.green, c.blue, c.alpha]
+        };
+        assert_eq!(rgba("red"), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(rgba("transparent")[3], 0.0);
+        assert_eq!(rgba("#00f"), [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(rgba("#0000ff80")[3], 128.0 / 255.0);
+        assert_eq!(rgba("#fff0")[3], 0.0);
+        assert_eq!(rgba("rgba(255, 0, 0, 0.25)"), [1.0, 0.0, 0.0, 0.25]);
+        assert_eq!(rgba("rgb(100% 0% 0% / 50%)"), [1.0, 0.0, 0.0, 0.5]);
+        for s in &["#12", "rgba(1, 2)", "rgb(a, b, c)", "nocolor", ""] {
+            assert!(style::Color::from_string(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn interpolated_select() {
+        let config: style::PolygonStyleConfig = serde_json::from_str(
+            r#"{"kind":"interpolated","propName":"h","colorSpace":"lab",
+                "fillColor":{"palette":"blues","domain":[0,100]},
+                "strokeColor":"black","strokeWidth":[[0,1],[100,3]]}"#,
+        ).unwrap();
+        let mut list = style::StyleList::from_config(&config).unwrap();
+        let props = |h: f64| {
+            let mut m = serde_json::Map::new();
+            m.insert("h".to_owned(), serde_json::Value::from(h));
+            Some(m)
+        };
+        let a = list.select(&props(50.0)).unwrap();
+        assert_eq!(list.select(&props(50.0)), Some(a));
+        let b = list.select(&props(100.0)).unwrap();
+        assert!(a != b && !list.is_fallback(a));
+        assert_eq!(list.get_for(&b).unwrap().strokeWidth, 3.0);
+        let missing = list.select(&None).unwrap();
+        assert!(list.is_fallback(missing));
+        assert!(list.configured().get_for(&a).is_none());
+    }
 }
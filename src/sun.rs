@@ -0,0 +1,182 @@
+use crs::Crs;
+use geom::direction;
+use lingua::Point;
+use manifest::SunConfig;
+use nalgebra as na;
+use style::Color;
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn numbers(text: &str, what: &str) -> Result<Vec<f64>, String> {
+    text.split(':')
+        .map(|n| n.parse::<f64>().map_err(|_| format!("bad {} \"{}\"", what, text)))
+        .collect()
+}
+
+/// Julian day of `YYYY-MM-DDTHH:MM[:SS]`, followed by `Z`, an offset such
+/// as `+02:00` or nothing for UTC.
+pub fn parse_time(text: &str) -> Result<f64, String> {
+    let bad = || format!("bad time \"{}\", expected YYYY-MM-DDTHH:MM[:SS][Z|+HH:MM]", text);
+    let mut parts = text.trim().splitn(2, |c| c == 'T' || c == ' ');
+    let date = parts.next().unwrap_or("");
+    let rest = parts.next().ok_or_else(bad)?;
+    let (clock, offset) = if rest.ends_with('Z') {
+        (&rest[..rest.len() - 1], 0.0)
+    } else {
+        match rest.rfind(|c| c == '+' || c == '-') {
+            Some(i) => {
+                let zone = numbers(&rest[i + 1..], "offset")?;
+                let minutes = zone[0] * 60.0 + zone.get(1).cloned().unwrap_or(0.0);
+                (
+                    &rest[..i],
+                    if &rest[i..=i] == "-" { -minutes } else { minutes },
+                )
+            }
+            None => (rest, 0.0),
+        }
+    };
+
+    let ymd: Vec<i64> = date
+        .split('-')
+        .map(|n| n.parse::<i64>().map_err(|_| bad()))
+        .collect::<Result<Vec<i64>, String>>()?;
+    let hms = numbers(clock, "time")?;
+    if ymd.len() != 3 || hms.len() < 2 || hms.len() > 3 {
+        return Err(bad());
+    }
+    if !(1 <= ymd[1] && ymd[1] <= 12 && 1 <= ymd[2] && ymd[2] <= 31)
+        || !(hms[0] < 24.0 && hms[1] < 60.0 && hms.get(2).map_or(true, |s| *s < 61.0))
+    {
+        return Err(bad());
+    }
+    let seconds =
+        hms[0] * 3600.0 + hms[1] * 60.0 + hms.get(2).cloned().unwrap_or(0.0) - offset * 60.0;
+    Ok(2_440_587.5 + days_from_civil(ymd[0], ymd[1], ymd[2]) as f64 + seconds / 86_400.0)
+}
+
+/// Azimuth, clockwise from north, and elevation of the sun in degrees, from
+/// the Astronomical Almanac's low precision formulas, good to about a
+/// hundredth of a degree this century. Refraction is not accounted for.
+pub fn position(julian_day: f64, longitude: f64, latitude: f64) -> (f64, f64) {
+    let n = julian_day - 2_451_545.0;
+    let mean_longitude = (280.460 + 0.985_647_4 * n).to_radians();
+    let anomaly = (357.528 + 0.985_600_3 * n).to_radians();
+    let ecliptic_longitude = mean_longitude
+        + 1.915f64.to_radians() * anomaly.sin()
+        + 0.020f64.to_radians() * (2.0 * anomaly).sin();
+    let obliquity = (23.439 - 0.000_000_4 * n).to_radians();
+
+    let right_ascension = (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+    let sidereal = (280.460_618_37 + 360.985_647_366_29 * n + longitude).to_radians();
+    let hour_angle = sidereal - right_ascension;
+
+    let lat = latitude.to_radians();
+    let elevation = (lat.sin() * declination.sin()
+        + lat.cos() * declination.cos() * hour_angle.cos())
+        .asin();
+    let azimuth = (-declination.cos() * hour_angle.sin()).atan2(
+        declination.sin() * lat.cos() - declination.cos() * lat.sin() * hour_angle.cos(),
+    );
+    (azimuth.to_degrees().rem_euclid(360.0), elevation.to_degrees())
+}
+
+/// Bearing in degrees, clockwise from north, of the great circle from one
+/// `(longitude, latitude)` to another.
+fn bearing(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (l1, p1) = (from.0.to_radians(), from.1.to_radians());
+    let (l2, p2) = (to.0.to_radians(), to.1.to_radians());
+    let dl = l2 - l1;
+    (dl.sin() * p2.cos())
+        .atan2(p1.cos() * p2.sin() - p1.sin() * p2.cos() * dl.cos())
+        .to_degrees()
+}
+
+/// The sun at a given time over the scene, in scene coordinates.
+#[derive(Debug, Clone, Copy)]
+pub struct Sun {
+    /// Towards the sun.
+    pub direction: na::Unit<na::Vector3<f64>>,
+    pub elevation: f64,
+    pub shadow_color: Color,
+}
+
+impl Sun {
+    /// `centre` locates the scene when the configuration does not, and
+    /// `extent` is its size, both in scene coordinates. With a scene
+    /// `crs`, azimuths are turned from true north to grid north.
+    pub fn from_config(
+        config: &SunConfig,
+        time: Option<&str>,
+        crs: Option<Crs>,
+        centre: &Point,
+        extent: f64,
+    ) -> Result<Sun, String> {
+        let time = time
+            .or_else(|| config.time.as_ref().map(|t| t.as_str()))
+            .ok_or_else(|| "sun: no time given".to_owned())?;
+        let julian_day = parse_time(time)?;
+        let shadow_color = Color::from_string(&config.shadow_color)?;
+        let c = [centre.x, centre.y, centre.z];
+        let location = match (config.location, crs) {
+            (Some(l), _) => (l[0], l[1]),
+            (None, Some(crs)) => crs.geographic(c),
+            (None, None) => return Err("sun: a location is needed when the scene has no crs".to_owned()),
+        };
+        if !(location.1.abs() <= 90.0) {
+            return Err(format!("sun: bad latitude {}", location.1));
+        }
+        let convergence = crs.map_or(0.0, |crs| {
+            let step = (extent * 0.01).max(1e-6);
+            bearing(crs.geographic(c), crs.geographic([c[0], c[1] + step, c[2]]))
+        });
+        let (azimuth, elevation) = position(julian_day, location.0, location.1);
+        println!(
+            "sun at {:.2}° azimuth, {:.2}° elevation over {:.5}, {:.5}",
+            azimuth, elevation, location.0, location.1
+        );
+        Ok(Sun {
+            direction: na::Unit::new_normalize(direction(azimuth - convergence, elevation)),
+            elevation,
+            shadow_color,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sun::{parse_time, position};
+
+    #[test]
+    fn sun_position() {
+        assert_eq!(parse_time("2000-01-01T12:00:00Z").unwrap(), 2_451_545.0);
+        assert_eq!(
+            parse_time("2000-01-01T14:00+02:00").unwrap(),
+            parse_time("2000-01-01 12:00").unwrap()
+        );
+        assert!(parse_time("2000-13-01T12:00Z").is_err());
+        assert!(parse_time("2000-01-01").is_err());
+
+        // summer solstice, solar noon at Greenwich
+        let (azimuth, elevation) = position(parse_time("2026-06-21T12:02Z").unwrap(), 0.0, 51.4769);
+        assert!((azimuth - 180.0).abs() < 1.0);
+        assert!((elevation - 61.96).abs() < 0.1);
+
+        // Sydney, mid-morning in winter: low in the north east
+        let (azimuth, elevation) = position(
+            parse_time("2026-06-21T10:00+10:00").unwrap(),
+            151.2093,
+            -33.8688,
+        );
+        assert!(azimuth > 20.0 && azimuth < 60.0);
+        assert!(elevation > 20.0 && elevation < 30.0);
+    }
+}
@@ -1,9 +1,11 @@
 use cairo::{Context, PDFSurface};
 use camera::Camera;
 use draw::{get_draw_config, DrawConfig, Drawable};
+use light::Light;
 use lingua::PlaneFlat;
 use operation::paint_op;
 use style::{StyleCollection, StyleGetter};
+use sun::Sun;
 
 pub enum CliMode {
     Replay,
@@ -29,12 +31,14 @@ impl UiCli {
         &self,
         planes: &PlaneFlat,
         style: &StyleCollection,
+        lights: Vec<Option<Light>>,
+        sun: Option<Sun>,
         camera: Option<Camera>,
         target_path: &str,
     ) {
         match self.mode {
             CliMode::Print => match camera {
-                Some(cam) => self.run_print(planes, style, cam, target_path),
+                Some(cam) => self.run_print(planes, style, lights, sun, cam, target_path),
                 None => println!("Camera is missing"),
             },
             CliMode::Replay => self.run_replay(planes, style, target_path),
@@ -47,6 +51,8 @@ impl UiCli {
         &self,
         planes: &PlaneFlat,
         style: &StyleCollection,
+        lights: Vec<Option<Light>>,
+        sun: Option<Sun>,
         camera: Camera,
         target_path: &str,
     ) {
@@ -55,7 +61,7 @@ impl UiCli {
         let context = Context::new(&surface);
         self.paint(
             planes,
-            &get_draw_config(planes, &camera, f64::from(self.width)),
+            &get_draw_config(planes, &camera, f64::from(self.width), lights, sun.as_ref()),
             style,
             &context,
         );
@@ -1,6 +1,7 @@
 use cairo::Context;
 use camera::Camera;
 use capture::Capture;
+use data::Data;
 use draw::get_draw_config;
 use draw::{DrawConfig, Drawable};
 use handlers::handle_key_event;
@@ -16,6 +17,10 @@ use sdl2::render::Texture;
 use style::{StyleCollection, StyleGetter};
 use surface_data::create_for_data_unsafe;
 use time::PreciseTime;
+use watch::{Change, Watcher};
+
+/// How long to wait for an event before looking for changed files.
+const WATCH_INTERVAL_MS: u32 = 500;
 
 pub struct UiSdl {
     width: u32,
@@ -101,7 +106,7 @@ impl UiSdl {
         }
     }
 
-    pub fn run(&mut self, planes: &PlaneFlat, style: &StyleCollection, initial_camera: Camera) {
+    pub fn run(&mut self, data: &mut Data, initial_camera: Camera) {
         let sdl = sdl2::init().unwrap();
         let video_subsystem = sdl.video().unwrap();
         let mut event_pump = sdl.event_pump().unwrap();
@@ -111,6 +116,7 @@ impl UiSdl {
             .unwrap();
         let mut canvas = sdl2::render::CanvasBuilder::new(window).build().unwrap();
         let mut camera = initial_camera;
+        let mut watcher = Watcher::new(data);
 
         canvas.set_draw_color(sdl2::pixels::Color::RGB(100, 100, 100));
         canvas.clear();
@@ -124,15 +130,18 @@ impl UiSdl {
                 self.height,
             ).unwrap();
 
-        match self.paint(
-            planes,
-            &get_draw_config(planes, &camera, f64::from(self.width)),
-            &mut sdl_texture,
-            style,
-        ) {
-            Ok(s) => println!("First draw success"),
-            Err(e) => println!("draw failure {}", e),
-        };
+        {
+            let planes = data.planes.flattened();
+            match self.paint(
+                &planes,
+                &get_draw_config(&planes, &camera, f64::from(self.width), data.lights(), None),
+                &mut sdl_texture,
+                &data.styles,
+            ) {
+                Ok(s) => println!("First draw success"),
+                Err(e) => println!("draw failure {}", e),
+            };
+        }
         match canvas.copy(&sdl_texture, None, None) {
             Ok(r) => {
                 println!("canvas copied {:?}", r);
@@ -144,28 +153,54 @@ impl UiSdl {
         };
 
         'main: loop {
-            for event in event_pump.wait_iter() {
+            let mut redraw = false;
+            if let Some(event) = event_pump.wait_event_timeout(WATCH_INTERVAL_MS) {
                 match self.process_event(event, &camera, &initial_camera) {
                     PostEventAction::Quit => break 'main,
                     PostEventAction::Draw(new_camera, timestamp) => {
                         camera = new_camera;
                         self.capture.map(timestamp, camera);
-                        let start_paint = PreciseTime::now();
-                        self.paint(
-                            planes,
-                            &get_draw_config(planes, &camera, f64::from(self.width)),
-                            &mut sdl_texture,
-                            style,
-                        ).and_then(|_| {
-                            println!("Painted in {}", start_paint.to(PreciseTime::now()));
-                            canvas.copy(&sdl_texture, None, None)
-                        }).map(|_| canvas.present());
+                        redraw = true;
                     }
                     PostEventAction::Idle => (),
                 }
+            }
 
-                // std::thread::sleep(std::time::Duration::from_millis(1000));
+            let mut reloaded_any = false;
+            for change in watcher.poll() {
+                let reloaded = match change {
+                    Change::Scene => Data::from_file(&data.manifest.path).map(|d| *data = d),
+                    Change::Style(index) => data.reload_style(index),
+                    Change::Data(index) => data.reload_layer(index),
+                };
+                match reloaded {
+                    Ok(_) => reloaded_any = true,
+                    Err(e) => println!("Reload failed, {}", e),
+                }
+                if change == Change::Scene {
+                    watcher = Watcher::new(data);
+                }
             }
+
+            if reloaded_any {
+                data.reports.iter().for_each(|r| println!("{}", r));
+            }
+
+            if redraw || reloaded_any {
+                let planes = data.planes.flattened();
+                let start_paint = PreciseTime::now();
+                self.paint(
+                    &planes,
+                    &get_draw_config(&planes, &camera, f64::from(self.width), data.lights(), None),
+                    &mut sdl_texture,
+                    &data.styles,
+                ).and_then(|_| {
+                    println!("Painted in {}", start_paint.to(PreciseTime::now()));
+                    canvas.copy(&sdl_texture, None, None)
+                }).map(|_| canvas.present());
+            }
+
+            // std::thread::sleep(std::time::Duration::from_millis(1000));
         }
     }
 
@@ -0,0 +1,502 @@
+use data::Data;
+use lingua::{PlaneKind, PlaneT, Point, Point2D};
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Something wrong with a plane, as named in reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Problem {
+    /// A NaN or infinite coordinate.
+    NonFinite,
+    /// The same vertex twice in a row.
+    RepeatedVertex,
+    /// A ring with less than three distinct vertices, or all of them on a
+    /// line.
+    Degenerate,
+    /// A ring whose vertices are not on a common plane.
+    NonPlanar,
+    /// A ring crossing itself.
+    SelfIntersection,
+}
+
+/// Drops the closing vertex of a ring, returning whether it was there.
+fn open_ring(points: &[Point]) -> (&[Point], bool) {
+    if points.len() > 1 && points.first() == points.last() {
+        (&points[..points.len() - 1], true)
+    } else {
+        (points, false)
+    }
+}
+
+/// Newell's normal, its length being twice the area of the ring.
+pub fn normal(ring: &[Point]) -> [f64; 3] {
+    let mut n = [0.0; 3];
+    for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+        n[0] += (a.y - b.y) * (a.z + b.z);
+        n[1] += (a.z - b.z) * (a.x + b.x);
+        n[2] += (a.x - b.x) * (a.y + b.y);
+    }
+    n
+}
+
+fn norm(v: [f64; 3]) -> f64 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+/// Largest extent of the ring along one axis, the scale tolerances are
+/// taken against.
+fn extent(ring: &[Point]) -> f64 {
+    let mut min = [::std::f64::MAX; 3];
+    let mut max = [::std::f64::MIN; 3];
+    for p in ring.iter() {
+        for (i, v) in [p.x, p.y, p.z].iter().enumerate() {
+            min[i] = min[i].min(*v);
+            max[i] = max[i].max(*v);
+        }
+    }
+    (0..3).map(|i| max[i] - min[i]).fold(0.0, f64::max)
+}
+
+const TOLERANCE: f64 = 1e-4;
+
+fn sub(a: &Point, b: &Point) -> [f64; 3] {
+    [a.x - b.x, a.y - b.y, a.z - b.z]
+}
+
+fn cross3(u: [f64; 3], v: [f64; 3]) -> [f64; 3] {
+    [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ]
+}
+
+/// The normal of a ring, `None` when its vertices are all on one line.
+/// Newell's normal is preferred, but vanishes on rings crossing themselves
+/// into lobes of opposite orientation.
+fn ring_normal(ring: &[Point], size: f64) -> Option<[f64; 3]> {
+    if ring.len() < 3 || size == 0.0 {
+        return None;
+    }
+    let o = ring[0];
+    let far = ring
+        .iter()
+        .max_by(|a, b| norm(sub(a, &o)).partial_cmp(&norm(sub(b, &o))).unwrap())?;
+    let axis = sub(far, &o);
+    let (side, spread) = ring
+        .iter()
+        .map(|p| {
+            let c = cross3(axis, sub(p, &o));
+            (c, norm(c) / norm(axis))
+        }).fold(([0.0; 3], 0.0), |acc, c| if c.1 > acc.1 { c } else { acc });
+    if spread <= TOLERANCE * size {
+        return None;
+    }
+    let n = normal(ring);
+    if norm(n) > TOLERANCE * size * size {
+        Some(n)
+    } else {
+        Some(side)
+    }
+}
+
+/// Projects a ring on the coordinate plane it is the most parallel to.
+fn project(ring: &[Point], n: [f64; 3]) -> Vec<Point2D> {
+    let (ax, ay, az) = (n[0].abs(), n[1].abs(), n[2].abs());
+    ring.iter()
+        .map(|p| {
+            if az >= ax && az >= ay {
+                Point2D::new(p.x, p.y)
+            } else if ax >= ay {
+                Point2D::new(p.y, p.z)
+            } else {
+                Point2D::new(p.z, p.x)
+            }
+        }).collect()
+}
+
+fn cross(o: &Point2D, a: &Point2D, b: &Point2D) -> f64 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+fn segments_cross(a: &Point2D, b: &Point2D, c: &Point2D, d: &Point2D) -> bool {
+    let (d1, d2) = (cross(a, b, c), cross(a, b, d));
+    let (d3, d4) = (cross(c, d, a), cross(c, d, b));
+    ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+}
+
+fn self_intersects(ring: &[Point2D]) -> bool {
+    let n = ring.len();
+    for i in 0..n {
+        for j in i + 2..n {
+            if i == 0 && j == n - 1 {
+                continue;
+            }
+            if segments_cross(&ring[i], &ring[(i + 1) % n], &ring[j], &ring[(j + 1) % n]) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn check_ring(ring: &[Point], problems: &mut Vec<Problem>) {
+    let (ring, _) = open_ring(ring);
+    if ring.windows(2).any(|w| w[0] == w[1]) {
+        problems.push(Problem::RepeatedVertex);
+    }
+    let mut distinct: Vec<Point> = ring.to_vec();
+    distinct.dedup();
+    let size = extent(&distinct);
+    let n = match ring_normal(&distinct, size) {
+        Some(n) => n,
+        None => {
+            problems.push(Problem::Degenerate);
+            return;
+        }
+    };
+    let length = norm(n);
+    let unit = [n[0] / length, n[1] / length, n[2] / length];
+    let o = distinct[0];
+    let off_plane = distinct.iter().any(|p| {
+        let d = unit[0] * (p.x - o.x) + unit[1] * (p.y - o.y) + unit[2] * (p.z - o.z);
+        d.abs() > TOLERANCE * size
+    });
+    if off_plane {
+        problems.push(Problem::NonPlanar);
+    }
+    if self_intersects(&project(&distinct, n)) {
+        problems.push(Problem::SelfIntersection);
+    }
+}
+
+/// Problems found in a plane, each reported once.
+pub fn check(plane: &PlaneT) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let all = || plane.points.iter().chain(plane.holes.iter().flat_map(|h| h.iter()));
+    if all().any(|p| !(p.x.is_finite() && p.y.is_finite() && p.z.is_finite())) {
+        return vec![Problem::NonFinite];
+    }
+    match plane.kind {
+        PlaneKind::Marker => (),
+        PlaneKind::Line => {
+            if plane.points.windows(2).any(|w| w[0] == w[1]) {
+                problems.push(Problem::RepeatedVertex);
+            }
+        }
+        PlaneKind::Polygon | PlaneKind::Ground => {
+            for ring in Some(&plane.points).into_iter().chain(plane.holes.iter()) {
+                check_ring(ring, &mut problems);
+            }
+        }
+    }
+    problems.sort();
+    problems.dedup();
+    problems
+}
+
+fn is_ear(ring: &[Point2D], indices: &[usize], i: usize) -> bool {
+    let n = indices.len();
+    let (a, b, c) = (
+        &ring[indices[(i + n - 1) % n]],
+        &ring[indices[i]],
+        &ring[indices[(i + 1) % n]],
+    );
+    if cross(a, b, c) <= 0.0 {
+        return false;
+    }
+    indices.iter().all(|&k| {
+        let p = &ring[k];
+        k == indices[(i + n - 1) % n]
+            || k == indices[i]
+            || k == indices[(i + 1) % n]
+            || !(cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0)
+    })
+}
+
+/// Ear clipping of a simple ring, as indices of triangles.
+fn triangulate(ring: &[Point2D]) -> Vec<[usize; 3]> {
+    let mut indices: Vec<usize> = (0..ring.len()).collect();
+    let area: f64 = (0..ring.len())
+        .map(|i| cross(&Point2D::origin(), &ring[i], &ring[(i + 1) % ring.len()]))
+        .sum();
+    if area < 0.0 {
+        indices.reverse();
+    }
+    let mut triangles = Vec::with_capacity(ring.len());
+    while indices.len() > 3 {
+        let n = indices.len();
+        match (0..n).find(|&i| is_ear(ring, &indices, i)) {
+            Some(i) => {
+                triangles.push([indices[(i + n - 1) % n], indices[i], indices[(i + 1) % n]]);
+                indices.remove(i);
+            }
+            None => break,
+        }
+    }
+    if indices.len() == 3 {
+        triangles.push([indices[0], indices[1], indices[2]]);
+    }
+    triangles
+}
+
+/// Removes repeated vertices, degenerate rings and non-finite planes, and
+/// splits non-planar faces without holes into triangles.
+pub fn repair(planes: Vec<PlaneT>) -> Vec<PlaneT> {
+    let mut result = Vec::with_capacity(planes.len());
+    for mut plane in planes {
+        let problems = check(&plane);
+        if problems.is_empty() {
+            result.push(plane);
+            continue;
+        }
+        if problems.contains(&Problem::NonFinite) {
+            continue;
+        }
+        plane.points.dedup();
+        for hole in plane.holes.iter_mut() {
+            hole.dedup();
+        }
+        if plane.kind == PlaneKind::Line || plane.kind == PlaneKind::Marker {
+            result.push(plane);
+            continue;
+        }
+        plane.holes.retain(|h| {
+            let mut p = Vec::new();
+            check_ring(h, &mut p);
+            !p.contains(&Problem::Degenerate)
+        });
+        let problems = check(&plane);
+        if problems.contains(&Problem::Degenerate) {
+            continue;
+        }
+        if problems.contains(&Problem::NonPlanar) && plane.holes.is_empty() {
+            let (ring, closed) = open_ring(&plane.points);
+            let ring = ring.to_vec();
+            let projected = project(&ring, normal(&ring));
+            for t in triangulate(&projected) {
+                let mut points = vec![ring[t[0]], ring[t[1]], ring[t[2]]];
+                if closed {
+                    points.push(ring[t[0]]);
+                }
+                result.push(PlaneT {
+                    points,
+                    holes: Vec::new(),
+                    ..plane.clone()
+                });
+            }
+            continue;
+        }
+        result.push(plane);
+    }
+    result
+}
+
+/// The problems of the planes of one feature.
+#[derive(Serialize)]
+struct FeatureIssue {
+    /// The feature's id, or its index in the layer when it has none.
+    id: serde_json::Value,
+    planes: usize,
+    problems: BTreeSet<Problem>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LayerIssues {
+    name: String,
+    planes: usize,
+    problems: BTreeMap<Problem, usize>,
+    /// Left after repair, when asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after_repair: Option<BTreeMap<Problem, usize>>,
+    features: Vec<FeatureIssue>,
+}
+
+#[derive(Serialize)]
+pub struct ValidationReport {
+    valid: bool,
+    layers: Vec<LayerIssues>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+}
+
+/// Checks every plane of a scene, listing problems by feature. With
+/// `repair`, also counts what the repair pass would leave, the planes
+/// themselves staying as they are.
+pub fn validate(data: &Data, repair_planes: bool) -> ValidationReport {
+    let mut layers: Vec<LayerIssues> = data
+        .reports
+        .iter()
+        .map(|r| LayerIssues {
+            name: r.name.clone(),
+            planes: 0,
+            problems: BTreeMap::new(),
+            after_repair: if repair_planes {
+                Some(BTreeMap::new())
+            } else {
+                None
+            },
+            features: Vec::new(),
+        }).collect();
+    let mut features: Vec<BTreeMap<usize, FeatureIssue>> =
+        layers.iter().map(|_| BTreeMap::new()).collect();
+
+    for plane in data.planes.flattened() {
+        let layer = match layers.get_mut(plane.layer_index) {
+            Some(layer) => layer,
+            None => continue,
+        };
+        layer.planes += 1;
+        let problems = check(plane);
+        if problems.is_empty() {
+            continue;
+        }
+        for p in problems.iter() {
+            *layer.problems.entry(*p).or_insert(0) += 1;
+        }
+        if let Some(ref mut after) = layer.after_repair {
+            for repaired in repair(vec![plane.clone()]) {
+                for p in check(&repaired) {
+                    *after.entry(p).or_insert(0) += 1;
+                }
+            }
+        }
+        let issue = features[plane.layer_index]
+            .entry(plane.feature_index)
+            .or_insert_with(|| FeatureIssue {
+                id: data
+                    .feature(plane)
+                    .and_then(|f| f.id.as_ref())
+                    .and_then(|id| serde_json::to_value(id).ok())
+                    .unwrap_or_else(|| serde_json::Value::from(plane.feature_index)),
+                planes: 0,
+                problems: BTreeSet::new(),
+            });
+        issue.planes += 1;
+        issue.problems.extend(problems);
+    }
+    for (layer, issues) in layers.iter_mut().zip(features) {
+        layer.features = issues.into_values().collect();
+    }
+
+    ValidationReport {
+        valid: layers.iter().all(|l| l.problems.is_empty()),
+        layers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data::Data;
+    use lingua::{PlaneT, Point};
+    use serde_json;
+    use std::env;
+    use std::fs;
+    use validate::{check, repair, validate, Problem};
+
+    fn polygon(points: &[(f64, f64, f64)]) -> PlaneT {
+        PlaneT::polygon(
+            0,
+            0,
+            points.iter().map(|p| Point::new(p.0, p.1, p.2)).collect(),
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn problems() {
+        let square = [(0., 0., 0.), (1., 0., 0.), (1., 1., 0.), (0., 1., 0.), (0., 0., 0.)];
+        assert!(check(&polygon(&square)).is_empty());
+        let wall = [(0., 0., 0.), (1., 0., 0.), (1., 0., 3.), (0., 0., 3.)];
+        assert!(check(&polygon(&wall)).is_empty());
+        assert_eq!(
+            check(&polygon(&[(0., 0., 0.), (1., 0., 0.), (1., 0., 0.), (1., 1., 0.)])),
+            vec![Problem::RepeatedVertex]
+        );
+        assert_eq!(
+            check(&polygon(&[(0., 0., 0.), (1., 0., 0.), (2., 0., 0.)])),
+            vec![Problem::Degenerate]
+        );
+        assert_eq!(
+            check(&polygon(&[(0., 0., 0.), (1., 0., 0.), (1., 1., 1.), (0., 1., 0.)])),
+            vec![Problem::NonPlanar]
+        );
+        assert_eq!(
+            check(&polygon(&[(0., 0., 0.), (1., 1., 0.), (1., 0., 0.), (0., 1., 0.)])),
+            vec![Problem::SelfIntersection]
+        );
+        assert_eq!(
+            check(&polygon(&[(0., ::std::f64::NAN, 0.), (1., 0., 0.), (1., 1., 0.)])),
+            vec![Problem::NonFinite]
+        );
+    }
+
+    #[test]
+    fn repairs() {
+        let bent = polygon(&[
+            (0., 0., 0.),
+            (1., 0., 0.),
+            (1., 0., 0.),
+            (1., 1., 1.),
+            (0., 1., 0.),
+            (0., 0., 0.),
+        ]);
+        let triangles = repair(vec![bent]);
+        assert_eq!(triangles.len(), 2);
+        assert!(triangles.iter().all(|t| t.points.len() == 4 && check(t).is_empty()));
+        assert!(repair(vec![polygon(&[(0., 0., 0.), (1., 0., 0.), (0., 0., 0.)])]).is_empty());
+    }
+
+    #[test]
+    fn issues_by_feature() {
+        let dir = env::temp_dir().join("cardboard-validate");
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("m.json.cache"));
+        let bowtie = |x: f64| {
+            format!(
+                "[[[{0},0,0],[{1},1,0],[{1},0,0],[{0},1,0],[{0},0,0]]]",
+                x,
+                x + 1.0
+            )
+        };
+        fs::write(
+            dir.join("a.geojson"),
+            format!(
+                r#"{{"type":"FeatureCollection","features":[
+                {{"type":"Feature","properties":{{}},
+                  "geometry":{{"type":"MultiPolygon","coordinates":[{},{}]}}}},
+                {{"type":"Feature","id":"b","properties":{{}},
+                  "geometry":{{"type":"Polygon","coordinates":{}}}}}]}}"#,
+                bowtie(0.0),
+                bowtie(2.0),
+                bowtie(4.0)
+            ),
+        ).unwrap();
+        fs::write(
+            dir.join("s.json"),
+            r#"{"kind":"simple","strokeColor":"black","fillColor":"red","strokeWidth":1}"#,
+        ).unwrap();
+        fs::write(
+            dir.join("m.json"),
+            r#"{"layers":[{"name":"a","data":"a.geojson","style":"s.json"}]}"#,
+        ).unwrap();
+        let data = Data::from_file(dir.join("m.json")).unwrap();
+
+        let report = serde_json::to_value(validate(&data, false)).unwrap();
+        let layer = &report["layers"][0];
+        assert_eq!(layer["planes"], 3);
+        assert_eq!(layer["problems"]["selfIntersection"], 3);
+        let features: serde_json::Value = serde_json::from_str(
+            r#"[{"id": 0, "planes": 2, "problems": ["selfIntersection"]},
+                {"id": "b", "planes": 1, "problems": ["selfIntersection"]}]"#,
+        ).unwrap();
+        assert_eq!(layer["features"], features);
+    }
+}
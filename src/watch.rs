@@ -0,0 +1,70 @@
+use data::Data;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// What has to be reloaded after a file changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    /// The manifest or a scene-wide input, everything is reloaded.
+    Scene,
+    Style(usize),
+    Data(usize),
+}
+
+/// Polls the modification time of every file a scene was built from.
+pub struct Watcher {
+    files: Vec<(PathBuf, Option<SystemTime>, Change)>,
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Watcher {
+    pub fn new(data: &Data) -> Watcher {
+        let manifest = &data.manifest;
+        let mut paths = vec![(manifest.path.clone(), Change::Scene)];
+        paths.extend(
+            manifest
+                .region
+                .as_ref()
+                .and_then(|r| r.polygon.clone())
+                .map(|p| (p, Change::Scene)),
+        );
+        paths.extend(manifest.dem.clone().map(|p| (p, Change::Scene)));
+        for (index, layer) in manifest.visible_layers().iter().enumerate() {
+            paths.push((layer.style.clone(), Change::Style(index)));
+            for path in layer.data_files() {
+                paths.push((path, Change::Data(index)));
+            }
+        }
+        Watcher {
+            files: paths
+                .into_iter()
+                .map(|(path, change)| {
+                    let time = modified(&path);
+                    (path, time, change)
+                }).collect(),
+        }
+    }
+
+    /// Changes since the last call, a scene change standing for all others.
+    pub fn poll(&mut self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for file in self.files.iter_mut() {
+            let time = modified(&file.0);
+            if time != file.1 {
+                println!("Changed {}", file.0.display());
+                file.1 = time;
+                if !changes.contains(&file.2) {
+                    changes.push(file.2);
+                }
+            }
+        }
+        if changes.contains(&Change::Scene) {
+            return vec![Change::Scene];
+        }
+        changes
+    }
+}
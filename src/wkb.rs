@@ -0,0 +1,191 @@
+use geojson::{Geometry, PolygonType, Position, Value};
+
+/// Well-known binary, as found in GeoPackages and PostGIS dumps. Both the
+/// ISO (`1000 + type` for Z) and the extended (high bit flags) dimension
+/// encodings are understood, M values are read and dropped.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    little: bool,
+}
+
+const WKB_Z: u32 = 0x8000_0000;
+const WKB_M: u32 = 0x4000_0000;
+const WKB_SRID: u32 = 0x2000_0000;
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let b = self
+            .bytes
+            .get(self.offset..self.offset + n)
+            .ok_or_else(|| "truncated WKB".to_owned())?;
+        self.offset += n;
+        Ok(b)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut w = [0u8; 4];
+        w.copy_from_slice(self.take(4)?);
+        Ok(if self.little {
+            u32::from_le_bytes(w)
+        } else {
+            u32::from_be_bytes(w)
+        })
+    }
+
+    fn count(&mut self) -> Result<usize, String> {
+        let n = self.u32()? as usize;
+        // every item takes at least 4 bytes
+        if n > (self.bytes.len() - self.offset) / 4 {
+            return Err(format!("bad count {}", n));
+        }
+        Ok(n)
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        let mut w = [0u8; 8];
+        w.copy_from_slice(self.take(8)?);
+        Ok(f64::from_bits(if self.little {
+            u64::from_le_bytes(w)
+        } else {
+            u64::from_be_bytes(w)
+        }))
+    }
+
+    fn position(&mut self, z: bool, m: bool) -> Result<Position, String> {
+        let mut p = vec![self.f64()?, self.f64()?];
+        if z {
+            p.push(self.f64()?);
+        }
+        if m {
+            self.f64()?;
+        }
+        Ok(p)
+    }
+
+    fn positions(&mut self, z: bool, m: bool) -> Result<Vec<Position>, String> {
+        let n = self.count()?;
+        (0..n).map(|_| self.position(z, m)).collect()
+    }
+
+    fn rings(&mut self, z: bool, m: bool) -> Result<PolygonType, String> {
+        let n = self.count()?;
+        (0..n).map(|_| self.positions(z, m)).collect()
+    }
+
+    fn parts(&mut self) -> Result<Vec<Value>, String> {
+        let n = self.count()?;
+        (0..n).map(|_| self.geometry()).collect()
+    }
+
+    fn geometry(&mut self) -> Result<Value, String> {
+        self.little = match self.take(1)?[0] {
+            0 => false,
+            1 => true,
+            b => return Err(format!("bad byte order {}", b)),
+        };
+        let code = self.u32()?;
+        if code & WKB_SRID != 0 {
+            self.u32()?;
+        }
+        let iso = code & 0x0fff_ffff;
+        let (kind, dimensions) = (iso % 1000, iso / 1000);
+        let z = code & WKB_Z != 0 || dimensions == 1 || dimensions == 3;
+        let m = code & WKB_M != 0 || dimensions == 2 || dimensions == 3;
+        Ok(match kind {
+            1 => Value::Point(self.position(z, m)?),
+            2 => Value::LineString(self.positions(z, m)?),
+            3 | 17 => Value::Polygon(self.rings(z, m)?),
+            4 => Value::MultiPoint(points(self.parts()?)?),
+            5 => Value::MultiLineString(lines(self.parts()?)?),
+            // polyhedral surfaces and TINs are collections of polygons
+            6 | 15 | 16 => Value::MultiPolygon(polygons(self.parts()?)?),
+            7 => Value::GeometryCollection(self.parts()?.into_iter().map(Geometry::new).collect()),
+            other => return Err(format!("unsupported geometry type {}", other)),
+        })
+    }
+}
+
+fn points(parts: Vec<Value>) -> Result<Vec<Position>, String> {
+    parts
+        .into_iter()
+        .map(|v| match v {
+            Value::Point(p) => Ok(p),
+            _ => Err("expected a point".to_owned()),
+        }).collect()
+}
+
+fn lines(parts: Vec<Value>) -> Result<Vec<Vec<Position>>, String> {
+    parts
+        .into_iter()
+        .map(|v| match v {
+            Value::LineString(l) => Ok(l),
+            _ => Err("expected a line string".to_owned()),
+        }).collect()
+}
+
+fn polygons(parts: Vec<Value>) -> Result<Vec<PolygonType>, String> {
+    parts
+        .into_iter()
+        .map(|v| match v {
+            Value::Polygon(p) => Ok(p),
+            _ => Err("expected a polygon".to_owned()),
+        }).collect()
+}
+
+/// Decodes one geometry, ignoring anything following it.
+pub fn read(bytes: &[u8]) -> Result<Value, String> {
+    Reader {
+        bytes,
+        offset: 0,
+        little: true,
+    }.geometry()
+}
+
+#[cfg(test)]
+mod tests {
+    use geojson::Value;
+    use wkb::read;
+
+    fn doubles(values: &[f64], little: bool) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| {
+                if little {
+                    v.to_bits().to_le_bytes()
+                } else {
+                    v.to_bits().to_be_bytes()
+                }.to_vec()
+            }).collect()
+    }
+
+    #[test]
+    fn dimensions() {
+        // ISO PointZ, little endian
+        let mut b = vec![1u8];
+        b.extend_from_slice(&1001u32.to_le_bytes());
+        b.extend(doubles(&[1.0, 2.0, 3.0], true));
+        assert_eq!(read(&b).unwrap(), Value::Point(vec![1.0, 2.0, 3.0]));
+
+        // extended MultiPolygon of one PolygonZM, big endian
+        let mut b = vec![0u8];
+        b.extend_from_slice(&6u32.to_be_bytes());
+        b.extend_from_slice(&1u32.to_be_bytes());
+        b.push(0);
+        b.extend_from_slice(&(3u32 | 0xc000_0000).to_be_bytes());
+        b.extend_from_slice(&1u32.to_be_bytes());
+        b.extend_from_slice(&4u32.to_be_bytes());
+        for p in &[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 0.0]] {
+            b.extend(doubles(&[p[0], p[1], 7.0, 99.0], false));
+        }
+        match read(&b).unwrap() {
+            Value::MultiPolygon(polys) => {
+                assert_eq!(polys[0][0].len(), 4);
+                assert_eq!(polys[0][0][2], vec![1.0, 1.0, 7.0]);
+            }
+            other => panic!("expected a MultiPolygon, got {:?}", other),
+        }
+
+        assert!(read(&b[..20]).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A scene of two squares, one of them a bow tie.
+fn scene(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(name);
+    fs::create_dir_all(&dir).unwrap();
+    let _ = fs::remove_file(dir.join("m.json.cache"));
+    fs::write(
+        dir.join("a.geojson"),
+        r#"{"type":"FeatureCollection","features":[
+            {"type":"Feature","properties":{"h":10},"geometry":{"type":"Polygon",
+             "coordinates":[[[0,0,0],[1,0,0],[1,1,0],[0,1,0],[0,0,0]]]}},
+            {"type":"Feature","properties":{"h":20},"geometry":{"type":"Polygon",
+             "coordinates":[[[2,0,0],[3,1,0],[3,0,0],[2,1,0],[2,0,0]]]}}]}"#,
+    ).unwrap();
+    fs::write(
+        dir.join("s.json"),
+        r#"{"kind":"simple","strokeColor":"black","fillColor":"red","strokeWidth":1}"#,
+    ).unwrap();
+    fs::write(
+        dir.join("m.json"),
+        r#"{"layers":[{"name":"a","data":"a.geojson","style":"s.json"}]}"#,
+    ).unwrap();
+    dir.join("m.json")
+}
+
+fn run(args: &[&str]) -> (Option<i32>, serde_json::Value) {
+    let output = Command::new(env!("CARGO_BIN_EXE_cardboard"))
+        .args(args)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let json = serde_json::from_str(&stdout)
+        .unwrap_or_else(|e| panic!("not JSON ({}): {}", e, stdout));
+    (output.status.code(), json)
+}
+
+#[test]
+fn validate_prints_json() {
+    let manifest = scene("cardboard-cli-validate");
+    let (code, report) = run(&["validate", manifest.to_str().unwrap()]);
+    assert_eq!(code, Some(2));
+    assert_eq!(report["valid"], false);
+    assert_eq!(report["layers"][0]["problems"]["selfIntersection"], 1);
+}
+
+#[test]
+fn classify_prints_style() {
+    let manifest = scene("cardboard-cli-classify");
+    let (code, style) = run(&[
+        "classify",
+        manifest.to_str().unwrap(),
+        "a",
+        "h",
+        "equal",
+        "2",
+        "blues",
+    ]);
+    assert_eq!(code, Some(0));
+    assert_eq!(style["kind"], "continuous");
+    assert_eq!(style["intervals"].as_array().unwrap().len(), 2);
+}